@@ -0,0 +1,142 @@
+// protocol spoken between server and webapp: names of multipart fields, lengths of binary ones
+// and JSON responses. third-party clients follow the same, as described by openapi spec.
+
+use serde::{Deserialize, Serialize};
+
+// names of multipart fields. prepare_upload takes those up to `EXPIRY`, upload takes those from
+// `ID`, and upload_small takes fields of both but id, seq and is_last
+pub mod field {
+    pub const SALT: &str = "salt";
+    pub const NONCE: &str = "nonce";
+    pub const FILENAME_NONCE: &str = "filename_nonce";
+    pub const FILENAME: &str = "filename";
+    pub const IS_TEXT: &str = "is_text";
+    pub const FORMAT_VERSION: &str = "format_version";
+    pub const IDEMPOTENCY_KEY: &str = "idempotency_key";
+    pub const AVAILABLE_FROM: &str = "available_from";
+    pub const EXPECTED_CHUNKS: &str = "expected_chunks";
+    pub const EXPECTED_SIZE: &str = "expected_size";
+    pub const MAX_DOWNLOADS: &str = "max_downloads";
+    pub const EXPIRY: &str = "expiry";
+
+    pub const ID: &str = "id";
+    pub const SEQ: &str = "seq";
+    pub const IS_LAST: &str = "is_last";
+    pub const CONTENT: &str = "content";
+    pub const DIGEST: &str = "digest";
+
+    // fields of prepare_upload
+    pub const PREPARE: &[&str] = &[
+        SALT,
+        NONCE,
+        FILENAME_NONCE,
+        FILENAME,
+        IS_TEXT,
+        FORMAT_VERSION,
+        IDEMPOTENCY_KEY,
+        AVAILABLE_FROM,
+        EXPECTED_CHUNKS,
+        EXPECTED_SIZE,
+        MAX_DOWNLOADS,
+        EXPIRY,
+    ];
+}
+
+// salt of key derivation
+pub const SALT_LEN: usize = 32;
+// nonce of stream encrypting file chunks
+pub const STREAM_NONCE_LEN: usize = 19;
+// nonce of encrypted filename, and of text which is encrypted at once instead of as stream
+pub const NONCE_LEN: usize = 24;
+// sha256 of chunk content
+pub const DIGEST_LEN: usize = 32;
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
+
+// response of prepare_upload and upload_small
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreparedFile {
+    // id to upload chunks with
+    pub id: i64,
+    // id to put in share url, which is signed if server is configured so. servers before signed
+    // share urls do not send it, and their share id is plain file id.
+    #[serde(default)]
+    pub share_id: Option<String>,
+    // lets uploader delete file before it expires. servers before deletion tokens do not send it
+    #[serde(default)]
+    pub deletion_token: Option<String>,
+    // chunks of file are accepted only with this token, so that others cannot append to it.
+    // servers before upload tokens do not send it, and take chunks without one.
+    #[serde(default)]
+    pub upload_token: Option<String>,
+}
+
+// response of metadata, which file is decrypted with
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Metadata {
+    #[serde(with = "base64")]
+    pub filename: Vec<u8>,
+    #[serde(with = "base64")]
+    pub salt: Vec<u8>,
+    #[serde(with = "base64")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "base64")]
+    pub filename_nonce: Vec<u8>,
+    pub is_text: bool,
+    pub size: i64,
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    // servers before format versioning do not send this
+    #[serde(default = "default_format_version")]
+    pub format_version: u8,
+    // base urls which serve the same `download` endpoint closer to some clients
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    // downloads allowed before file is gone, including this one. none if not limited, or if
+    // server is before download limits were told
+    #[serde(default)]
+    pub downloads_left: Option<i64>,
+    // one-time ticket to fetch chunks after first from `download`, so that they are only served
+    // within download counted by `open`. only `open` of file with more chunks gives it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_ticket: Option<String>,
+}
+
+fn default_format_version() -> u8 {
+    1
+}
+
+pub mod base64 {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        let base64 = base64::encode(v);
+        String::serialize(&base64, s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let base64 = String::deserialize(d)?;
+        base64::decode(base64.as_bytes()).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_of_older_servers_are_read() {
+        let prepared: PreparedFile = serde_json::from_str(r#"{"id": 3}"#).unwrap();
+        assert_eq!(prepared.id, 3);
+        assert!(prepared.share_id.is_none());
+        assert!(prepared.upload_token.is_none());
+
+        let metadata: Metadata = serde_json::from_str(
+            r#"{"filename": "AQI=", "salt": "", "nonce": "", "filename_nonce": "", "is_text": false, "size": 10}"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.filename, vec![1, 2]);
+        assert_eq!(metadata.format_version, 1);
+        assert!(metadata.mirrors.is_empty());
+        assert!(metadata.downloads_left.is_none());
+    }
+}
@@ -23,6 +23,11 @@
             rust-toolchain
             trunk
             tailwindcss
+            pkg-config
+          ];
+          # native-tls of outgoing webhook requests
+          buildInputs = with pkgs; [
+            openssl
           ];
         };
       }
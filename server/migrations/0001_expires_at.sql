@@ -0,0 +1 @@
+alter table files add column expires_at integer;
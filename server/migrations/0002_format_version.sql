@@ -0,0 +1 @@
+alter table files add column format_version integer not null default 1;
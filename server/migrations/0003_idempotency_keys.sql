@@ -0,0 +1,7 @@
+create table if not exists upload_idempotency_keys (
+    key blob primary key,
+    file_id integer not null,
+    created_at integer not null,
+
+    foreign key (file_id) references files(id) on delete cascade
+);
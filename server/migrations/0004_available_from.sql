@@ -0,0 +1 @@
+alter table files add column available_from integer;
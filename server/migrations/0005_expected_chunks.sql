@@ -0,0 +1,2 @@
+alter table files add column expected_chunks integer;
+alter table files add column expected_size integer;
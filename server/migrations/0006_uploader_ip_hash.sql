@@ -0,0 +1 @@
+alter table files add column uploader_ip_hash text;
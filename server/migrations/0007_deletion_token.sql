@@ -0,0 +1 @@
+alter table files add column deletion_token_hash blob;
@@ -0,0 +1,2 @@
+alter table files add column max_downloads integer;
+alter table files add column download_count integer not null default 0;
@@ -0,0 +1 @@
+alter table files add column last_seq integer;
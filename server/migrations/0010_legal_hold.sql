@@ -0,0 +1,9 @@
+alter table files add column legal_hold boolean not null default false;
+
+create table if not exists admin_audit_log (
+    id integer primary key,
+    created_at timestamp with time zone default current_timestamp,
+    action text not null,
+    file_id integer not null,
+    reason text
+);
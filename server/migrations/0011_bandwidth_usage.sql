@@ -0,0 +1,8 @@
+create table if not exists bandwidth_usage (
+    tenant text not null,
+    month text not null,
+    uploaded_bytes integer not null default 0,
+    downloaded_bytes integer not null default 0,
+
+    primary key (tenant, month)
+);
@@ -0,0 +1 @@
+alter table files add column upload_token_hash blob;
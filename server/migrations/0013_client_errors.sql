@@ -0,0 +1,7 @@
+create table if not exists client_errors (
+    id integer primary key,
+    created_at timestamp with time zone default current_timestamp,
+    kind text not null,
+    app_version text not null,
+    browser text not null
+);
@@ -0,0 +1,14 @@
+create table if not exists api_keys (
+    id integer primary key,
+    created_at timestamp with time zone default current_timestamp,
+    label text,
+    key_hash blob not null unique,
+    max_size integer,
+    max_files_per_day integer,
+    min_expiry integer,
+    max_expiry integer,
+    valid_from integer,
+    valid_until integer,
+    revoked boolean not null default false
+);
+alter table files add column api_key_id integer;
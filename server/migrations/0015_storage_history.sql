@@ -0,0 +1,6 @@
+create table if not exists storage_history (
+    day text primary key,
+    stored_bytes integer,
+    deleted_files integer not null default 0,
+    deleted_bytes integer not null default 0
+);
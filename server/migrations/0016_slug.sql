@@ -0,0 +1,2 @@
+alter table files add column slug text;
+create unique index if not exists files_slug on files (slug);
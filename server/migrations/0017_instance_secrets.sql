@@ -0,0 +1,4 @@
+create table if not exists instance_secrets (
+    name text primary key,
+    secret blob not null
+);
@@ -0,0 +1,12 @@
+-- keys are stored as hashes from now on, along with encrypted response of prepare. rows live
+-- only for idempotency window, so dropping old ones just makes retries of them start over.
+drop table if exists upload_idempotency_keys;
+
+create table upload_idempotency_keys (
+    key_hash blob primary key,
+    file_id integer not null,
+    response blob not null,
+    created_at integer not null,
+
+    foreign key (file_id) references files(id) on delete cascade
+);
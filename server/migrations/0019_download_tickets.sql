@@ -0,0 +1,8 @@
+-- tickets given by `open` for the rest of download it counted. only their hash is stored.
+create table download_tickets (
+    ticket_hash blob primary key,
+    file_id integer not null,
+    created_at integer not null,
+
+    foreign key (file_id) references files(id) on delete cascade
+);
@@ -0,0 +1,147 @@
+use axum::http::{header::AUTHORIZATION, HeaderMap, StatusCode};
+use rusqlite::Connection;
+
+pub use crate::repo::ApiKey;
+use crate::{error::ApiError, handlers, state::State, utils};
+
+impl ApiKey {
+    // expiry within bounds of key. files of key with max expiry cannot be kept forever
+    pub fn clamp_expiry(&self, expiry: Option<u64>) -> Option<u64> {
+        let expiry = expiry.or_else(|| self.max_expiry.map(|max| max as u64))?;
+        let expiry = self.min_expiry.map_or(expiry, |min| expiry.max(min as u64));
+        Some(self.max_expiry.map_or(expiry, |max| expiry.min(max as u64)))
+    }
+
+    // refuse file over size limit or over daily file limit of key. size should be declared up
+    // front if key has size limit, so that it is checked when last chunk arrives.
+    pub fn check_upload(
+        &self,
+        state: &State,
+        conn: &Connection,
+        expected_size: Option<i64>,
+        now: i64,
+    ) -> Result<(), ApiError> {
+        if let Some(max_size) = self.max_size {
+            match expected_size {
+                Some(size) if size <= max_size => {}
+                Some(size) => {
+                    log::error!("file too large for API key: id={}, size={}", self.id, size);
+                    return Err(ApiError::ApiKeyMaxSizeExceeded { max_size });
+                }
+                None => {
+                    log::error!(
+                        "API key with size limit requires expected_size: id={}",
+                        self.id
+                    );
+                    return Err(StatusCode::BAD_REQUEST.into());
+                }
+            }
+        }
+
+        if let Some(max_files_per_day) = self.max_files_per_day {
+            let since = now - 24 * 60 * 60;
+            match state.files.count_uploads_of_key(conn, self.id, since) {
+                Ok(count) if count < max_files_per_day => {}
+                Ok(_) => {
+                    log::error!("API key file quota exceeded: id={}", self.id);
+                    return Err(ApiError::ApiKeyQuotaExceeded { max_files_per_day });
+                }
+                Err(err) => {
+                    log::error!("failed to count files of API key: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// whether uploads need API key: one is given in config, or any key issued by admin API is
+// still usable
+pub fn required(state: &State, conn: &Connection, now: i64) -> rusqlite::Result<bool> {
+    if !state.config.api_keys.is_empty() {
+        return Ok(true);
+    }
+    state.instance.any_api_key_usable(conn, now)
+}
+
+// uploads need API key in `authorization: bearer` header, if there is any. returns record of
+// issued key, or none if key is from config or not required at all.
+pub fn check(
+    state: &State,
+    conn: &Connection,
+    headers: &HeaderMap,
+    now: i64,
+) -> Result<Option<ApiKey>, ApiError> {
+    match required(state, conn, now) {
+        Ok(true) => {}
+        Ok(false) => return Ok(None),
+        Err(err) => {
+            log::error!("failed to query API keys: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    }
+
+    let given = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // check every key, so that timing does not tell which one is close
+    let valid = state.config.api_keys.iter().fold(false, |valid, key| {
+        utils::constant_time_eq(given.as_bytes(), key.as_bytes()) | valid
+    });
+    if valid {
+        return Ok(None);
+    }
+
+    // issued keys are looked up by hash, which does not leak key through timing
+    let key_hash = match handlers::bearer_token_hash(headers) {
+        Some(key_hash) => key_hash,
+        None => {
+            log::error!("upload request without valid API key");
+            return Err(StatusCode::UNAUTHORIZED.into());
+        }
+    };
+    match state.instance.api_key(conn, &key_hash, now) {
+        Ok(Some(key)) => Ok(Some(key)),
+        Ok(None) => {
+            log::error!("upload request without valid API key");
+            Err(StatusCode::UNAUTHORIZED.into())
+        }
+        Err(err) => {
+            log::error!("failed to query API key: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_is_clamped_to_key_bounds() {
+        let key = ApiKey {
+            id: 1,
+            max_size: None,
+            max_files_per_day: None,
+            min_expiry: Some(60),
+            max_expiry: Some(3600),
+        };
+        assert_eq!(key.clamp_expiry(Some(10)), Some(60));
+        assert_eq!(key.clamp_expiry(Some(600)), Some(600));
+        assert_eq!(key.clamp_expiry(Some(86400)), Some(3600));
+        // never expiring file is not allowed
+        assert_eq!(key.clamp_expiry(None), Some(3600));
+
+        let unbounded = ApiKey {
+            min_expiry: None,
+            max_expiry: None,
+            ..key
+        };
+        assert_eq!(unbounded.clamp_expiry(None), None);
+        assert_eq!(unbounded.clamp_expiry(Some(10)), Some(10));
+    }
+}
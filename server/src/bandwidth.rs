@@ -0,0 +1,72 @@
+use rusqlite::Connection;
+
+use crate::repo::InstanceRepo;
+
+// bytes of file uploaded or downloaded, added to monthly totals of its uploader. uploaders are told
+// apart by hash of their address, the same one shown in admin API. files uploaded before it was
+// recorded are accounted to `unknown`.
+pub fn record(
+    repo: &dyn InstanceRepo,
+    conn: &Connection,
+    file_id: i64,
+    now: i64,
+    uploaded: i64,
+    downloaded: i64,
+) {
+    let result = repo.add_bandwidth(conn, file_id, now, uploaded, downloaded);
+
+    // accounting failure should not fail transfer itself
+    if let Err(err) = result {
+        log::error!(
+            "failed to record bandwidth usage: id={}, error={:?}",
+            file_id,
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{migrations, repo::SqliteInstanceRepo};
+
+    #[test]
+    fn usage_adds_up_per_uploader_and_month() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../schema.sql")).unwrap();
+        migrations::run(&mut conn).unwrap();
+        conn.execute(
+            "insert into files (id, filename, salt, nonce, filename_nonce, uploader_ip_hash) values (1, x'', zeroblob(32), x'', x'', 'abcd')",
+            [],
+        )
+        .unwrap();
+
+        // 2022-01-31 and 2022-02-01
+        record(&SqliteInstanceRepo, &conn, 1, 1643587200, 10, 0);
+        record(&SqliteInstanceRepo, &conn, 1, 1643587200, 0, 30);
+        record(&SqliteInstanceRepo, &conn, 1, 1643673600, 5, 0);
+        // unknown file is not accounted
+        record(&SqliteInstanceRepo, &conn, 2, 1643587200, 100, 0);
+
+        let usage = SqliteInstanceRepo
+            .bandwidth(&conn, None)
+            .unwrap()
+            .into_iter()
+            .map(|usage| {
+                (
+                    usage.tenant,
+                    usage.month,
+                    usage.uploaded_bytes,
+                    usage.downloaded_bytes,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            usage,
+            vec![
+                ("abcd".to_owned(), "2022-01".to_owned(), 10, 30),
+                ("abcd".to_owned(), "2022-02".to_owned(), 5, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use axum::{extract::Extension, http::StatusCode, response::Json};
+use rusqlite::Connection;
+use serde::Deserialize;
+
+use crate::{db, error::ApiError, repo::InstanceRepo, state::State};
+
+// reports are kept for this many days
+const RETENTION_DAYS: i64 = 30;
+// and at most this many of them
+const MAX_REPORTS: i64 = 10_000;
+// browser families stored as they are. anything else is stored as `other`
+const BROWSERS: &[&str] = &["chrome", "edge", "firefox", "safari", "opera"];
+
+#[derive(Deserialize)]
+pub struct ClientErrorReport {
+    kind: String,
+    app_version: String,
+    browser: String,
+}
+
+// reports carry no free text, so that error messages with file names or passphrases in them
+// cannot end up in database
+fn is_identifier(s: &str, max_len: usize, allowed: impl Fn(char) -> bool) -> bool {
+    !s.is_empty() && s.len() <= max_len && s.chars().all(allowed)
+}
+
+// store kind, app version and browser of report. reports past retention are forgotten, and so
+// are oldest ones over `max_reports`, so that flood of reports cannot fill database
+fn store(
+    repo: &dyn InstanceRepo,
+    conn: &Connection,
+    now: i64,
+    (kind, app_version, browser): (&str, &str, &str),
+    max_reports: i64,
+) -> rusqlite::Result<()> {
+    repo.insert_client_error(conn, kind, app_version, browser)?;
+    let cutoff = now - RETENTION_DAYS * 24 * 60 * 60;
+    repo.forget_client_errors(conn, cutoff, max_reports)
+}
+
+// error which webapp user opted in to report. neither address nor file of reporter is stored
+pub async fn report(
+    state: Extension<Arc<State>>,
+    Json(report): Json<ClientErrorReport>,
+) -> Result<StatusCode, ApiError> {
+    if !state.0.config.client_error_reports {
+        return Err(ApiError::ClientErrorsDisabled);
+    }
+
+    if !is_identifier(&report.kind, 64, |c| {
+        c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+    }) {
+        log::error!("invalid client error kind");
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    if !is_identifier(&report.app_version, 32, |c| {
+        c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+'
+    }) {
+        log::error!("invalid client app version");
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    let browser = BROWSERS
+        .iter()
+        .find(|b| **b == report.browser)
+        .copied()
+        .unwrap_or("other");
+
+    let result = db::blocking(state.0.clone(), move |state, conn| {
+        let report = (report.kind.as_str(), report.app_version.as_str(), browser);
+        store(
+            &*state.instance,
+            conn,
+            state.clock.now(),
+            report,
+            MAX_REPORTS,
+        )
+    })
+    .await;
+    if let Err(err) = result {
+        log::error!("failed to store client error: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::SqliteInstanceRepo;
+
+    #[test]
+    fn free_text_is_not_identifier() {
+        let kind = |s: &str| is_identifier(s, 64, |c| c.is_ascii_lowercase() || c == '_');
+        assert!(kind("decrypt_failed"));
+        assert!(!kind(""));
+        assert!(!kind("failed to open secret.pdf"));
+        assert!(!kind(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn oldest_reports_over_limit_are_forgotten() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../schema.sql")).unwrap();
+        crate::migrations::run(&mut conn).unwrap();
+
+        let repo = SqliteInstanceRepo;
+        for kind in ["first", "second", "third"] {
+            store(&repo, &conn, 0, (kind, "1.0.0", "firefox"), 2).unwrap();
+        }
+        let kinds: Vec<String> = repo
+            .client_error_counts(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|count| count.kind)
+            .collect();
+        assert_eq!(kinds, ["second", "third"]);
+    }
+}
@@ -1,18 +1,428 @@
+use std::net::IpAddr;
+
+use clap::CommandFactory;
+
 #[derive(clap::Parser, Debug, Clone)]
 #[clap(author, version, about)]
 pub struct Config {
+    // TOML file of options, named as long flags with underscores, e.g. `bind_addr = "0.0.0.0:80"`.
+    // options given by flags or env win over those in file
+    #[clap(long, env)]
+    pub config: Option<String>,
+
     #[clap(long, env, default_value = "127.0.0.1:12321")]
     pub bind_addr: String,
 
+    // path prefix which server is reached at, e.g. `/hako` behind reverse proxy. every route,
+    // static file and link of webapp is under it. empty if served at root
+    #[clap(long, env, default_value = "", value_parser = parse_base_path)]
+    pub base_path: String,
+
     #[clap(long, env, default_value = "hako.db")]
     pub sqlite_db_filename: String,
 
+    // pragmas set on database connection at startup. sqlite defaults are kept for those not given.
+    // `wal` journal lets downloads read while uploads write
+    #[clap(long, env, possible_values = &["delete", "truncate", "persist", "memory", "wal", "off"])]
+    pub sqlite_journal_mode: Option<String>,
+
+    #[clap(long, env, possible_values = &["off", "normal", "full", "extra"])]
+    pub sqlite_synchronous: Option<String>,
+
+    // read-only connections which downloads and metadata are read with, so that they do not wait
+    // for uploads. only opened in `wal` journal mode
+    #[clap(long, env, default_value = "4")]
+    pub sqlite_read_connections: usize,
+
+    // milliseconds sqlite waits for lock held by another connection before reporting busy
+    #[clap(long, env)]
+    pub sqlite_busy_timeout: Option<u64>,
+
+    // pages if positive, or kibibytes if negative, as in `cache_size` pragma
+    #[clap(long, env, allow_hyphen_values = true)]
+    pub sqlite_cache_size: Option<i64>,
+
     #[clap(long, env)]
     pub expiry: Option<usize>,
 
+    // bounds of expiry in seconds which clients may request per upload, instead of `expiry`.
+    // per-upload expiry is accepted only when max is set
+    #[clap(long, env)]
+    pub min_expiry: Option<u64>,
+
+    #[clap(long, env)]
+    pub max_expiry: Option<u64>,
+
     #[clap(long, env, default_value = "60")]
     pub delete_interval: u64,
 
+    // only log files which expiry worker would delete, without deleting them
+    #[clap(long, env)]
+    pub expiry_dry_run: bool,
+
+    // delete rows of expired files as well as their chunks, so that their share ids read as
+    // unknown instead of expired
+    #[clap(long, env)]
+    pub expiry_hard_delete: bool,
+
+    // seconds after which incomplete upload is considered abandoned and deleted with its chunks.
+    // incomplete uploads are kept until they expire if not set
+    #[clap(long, env)]
+    pub abandoned_upload_age: Option<u64>,
+
+    // seconds between runs of worker returning space freed by deletions to filesystem. freed
+    // space is only reused by new chunks if not set, unless admin API reclaims it
+    #[clap(long, env)]
+    pub vacuum_interval: Option<u64>,
+
+    // reclaim space by full vacuum, which rewrites whole database file and blocks every request
+    // while it runs, instead of incremental one. meant for maintenance windows
+    #[clap(long, env)]
+    pub full_vacuum: bool,
+
+    // seconds to wait on shutdown for in-flight requests and running workers to finish, before
+    // they are cut off
+    #[clap(long, env, default_value = "30")]
+    pub shutdown_timeout: u64,
+
     #[clap(long, env, default_value = "128")]
     pub chunk_count_limit: u64,
+
+    // bytes of ciphertext one file may take. uploads are refused as soon as their chunks add up
+    // to more, whether or not they declared their size
+    #[clap(long, env)]
+    pub max_file_size: Option<u64>,
+
+    #[clap(long, env, default_value = "3600")]
+    pub idempotency_window: u64,
+
+    #[clap(long, env)]
+    pub alert_webhook_url: Option<String>,
+
+    #[clap(long, env, default_value = "300")]
+    pub alert_interval: u64,
+
+    #[clap(long, env)]
+    pub alert_db_size: Option<u64>,
+
+    #[clap(long, env)]
+    pub alert_stored_bytes: Option<u64>,
+
+    #[clap(long, env)]
+    pub alert_server_errors: Option<u64>,
+
+    // matrix room which receives notifications, posted by account of given access token
+    #[clap(long, env)]
+    pub matrix_homeserver_url: Option<String>,
+
+    #[clap(long, env)]
+    pub matrix_room_id: Option<String>,
+
+    #[clap(long, env)]
+    pub matrix_access_token: Option<String>,
+
+    // mail notifications, sent through SMTP relay with STARTTLS
+    #[clap(long, env)]
+    pub smtp_host: Option<String>,
+
+    #[clap(long, env, default_value = "587")]
+    pub smtp_port: u16,
+
+    #[clap(long, env)]
+    pub smtp_username: Option<String>,
+
+    #[clap(long, env)]
+    pub smtp_password: Option<String>,
+
+    #[clap(long, env)]
+    pub email_from: Option<String>,
+
+    #[clap(long, env)]
+    pub email_to: Option<String>,
+
+    // extensions of filenames which webapp refuses to upload, as filenames are encrypted
+    #[clap(long, env, use_value_delimiter = true)]
+    pub forbidden_extensions: Vec<String>,
+
+    #[clap(long, env)]
+    pub text_uploads_per_ip_per_day: Option<u64>,
+
+    // requests per minute each client ip can make to these routes, with bursts up to the same
+    // number. unlimited if not set
+    #[clap(long, env)]
+    pub prepare_upload_rate_limit: Option<u32>,
+
+    #[clap(long, env)]
+    pub upload_rate_limit: Option<u32>,
+
+    #[clap(long, env)]
+    pub metadata_rate_limit: Option<u32>,
+
+    #[clap(long, env)]
+    pub events_rate_limit: Option<u32>,
+
+    // error reports are sent without authentication, so they are limited unless this is 0
+    #[clap(long, env, default_value = "10")]
+    pub client_error_rate_limit: Option<u32>,
+
+    // addresses of reverse proxies in front of server. client address is taken from forwarded
+    // headers of requests coming from them, so that clients behind proxy are told apart
+    #[clap(long, env, use_value_delimiter = true)]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    // file which backup job touches after each run, to report age of last backup
+    #[clap(long, env)]
+    pub backup_marker_file: Option<String>,
+
+    // mark files with missing chunks unavailable and delete orphan chunks on startup, instead of
+    // only logging them
+    #[clap(long, env)]
+    pub repair_on_startup: bool,
+
+    // files are shared by random slugs of this many characters, so that they cannot be
+    // enumerated
+    #[clap(long, env, default_value = "10", value_parser = parse_slug_length)]
+    pub slug_length: usize,
+
+    // characters which slugs are drawn from
+    #[clap(
+        long,
+        env,
+        default_value = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ",
+        value_parser = parse_slug_alphabet
+    )]
+    pub slug_alphabet: String,
+
+    // key which numeric ids in share urls were signed with before slugs, so that links shared
+    // back then keep working
+    #[clap(long, env)]
+    pub share_url_key: Option<String>,
+
+    // keep accepting bare numeric ids while share url key is set, for links shared before
+    #[clap(long, env)]
+    pub accept_unsigned_ids: bool,
+
+    // key to sign embed urls with, which admin API issues so that other sites can fetch files
+    // cross-origin. embed urls are refused if not set
+    #[clap(long, env)]
+    pub embed_url_key: Option<String>,
+
+    // keys which uploaders should give as bearer token to prepare_upload. anyone can upload if no
+    // key is configured nor issued by admin API, and downloads are public either way
+    #[clap(long, env, use_value_delimiter = true)]
+    pub api_keys: Vec<String>,
+
+    // file with more API keys, one per line
+    #[clap(long, env)]
+    pub api_keys_file: Option<String>,
+
+    // bearer token for admin API under /api/admin. admin API is disabled without it
+    #[clap(long, env)]
+    pub admin_token: Option<String>,
+
+    // directory of html pages replacing built-in ones, e.g. `not_found.en.html` or
+    // `unsupported.html`
+    #[clap(long, env)]
+    pub error_pages_dir: Option<String>,
+
+    // stylesheet served at /custom.css, which webapp links after its own styles
+    #[clap(long, env)]
+    pub custom_css: Option<String>,
+
+    // accept error reports which webapp users opted in to send, at /api/client_error
+    #[clap(long, env)]
+    pub client_error_reports: bool,
+
+    // base urls of mirrors or CDN edge proxies in front of this server, offered to clients in
+    // metadata to download ciphertext from. they should allow cross-origin requests from webapp
+    #[clap(long, env, use_value_delimiter = true)]
+    pub download_mirrors: Vec<String>,
+
+    // bytes of database this instance is meant to hold. announced in policy feed only as coarse
+    // bucket of free capacity, and storage forecast of admin API counts days until it is reached
+    #[clap(long, env)]
+    pub storage_capacity: Option<u64>,
+
+    // url receiving lifecycle events of files (created, completed, downloaded, expired,
+    // deleted) as JSON, for automation around this server
+    #[clap(long, env)]
+    pub event_webhook_url: Option<String>,
+
+    // unix socket receiving lifecycle events, one JSON line per connection
+    #[clap(long, env)]
+    pub event_socket: Option<String>,
+
+    // `host:port` of NATS server to publish lifecycle events to, without auth
+    #[clap(long, env)]
+    pub event_nats_addr: Option<String>,
+
+    #[clap(long, env, default_value = "hako.events")]
+    pub event_nats_subject: String,
+}
+
+// `hako`, `/hako` and `/hako/` are all `/hako`, and `/` is root. kept to plain characters, as it
+// is put into html of webapp as is
+fn parse_base_path(s: &str) -> Result<String, String> {
+    let trimmed = s.trim_matches('/');
+    let plain = trimmed
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-._~/".contains(&b));
+    if !plain {
+        return Err("base path can only have letters, digits, `-._~` and `/`".into());
+    }
+    if trimmed.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("/{}", trimmed))
+    }
+}
+
+fn parse_slug_length(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(len) if (1..=64).contains(&len) => Ok(len),
+        _ => Err("slug length should be between 1 and 64".into()),
+    }
+}
+
+// characters should be safe in urls as they are. repeated ones would be drawn more often than
+// others, so they are refused
+fn parse_slug_alphabet(s: &str) -> Result<String, String> {
+    let plain = s
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !plain {
+        return Err("slug alphabet can only have letters, digits, `-` and `_`".into());
+    }
+    let mut chars = s.as_bytes().to_vec();
+    chars.sort_unstable();
+    chars.dedup();
+    if chars.len() != s.len() || chars.len() < 2 {
+        return Err("slug alphabet should have at least 2 characters, each only once".into());
+    }
+    Ok(s.to_owned())
+}
+
+// path of config file, looked up ahead of clap so that its options can sit under env and flags
+fn config_path() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_owned());
+        }
+    }
+    std::env::var("CONFIG").ok()
+}
+
+// options of config file as env variables clap reads them from. lists are joined by comma, as
+// options taking many values are delimited so
+fn env_values(text: &str) -> Result<Vec<(String, String)>, String> {
+    let table: toml::value::Table = toml::from_str(text).map_err(|err| err.to_string())?;
+    let command = Config::command();
+    let scalar = |key: &str, value: toml::Value| match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(n) => Ok(n.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(format!("unsupported value of {}", key)),
+    };
+
+    let mut values = Vec::new();
+    for (key, value) in table {
+        let known = key != "config" && command.get_arguments().any(|arg| arg.get_id() == key);
+        if !known {
+            return Err(format!("unknown option {}", key));
+        }
+        let value = match value {
+            toml::Value::Array(items) => items
+                .into_iter()
+                .map(|item| scalar(&key, item))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            value => scalar(&key, value)?,
+        };
+        values.push((key.to_uppercase(), value));
+    }
+    Ok(values)
+}
+
+// put options of config file into env, where they are read unless already set. precedence is
+// then flags, env, file and defaults in order
+pub fn load_file_into_env() -> Result<(), String> {
+    let path = match config_path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let text = std::fs::read_to_string(&path).map_err(|err| format!("{}: {}", path, err))?;
+    for (name, value) in env_values(&text).map_err(|err| format!("{}: {}", path, err))? {
+        if std::env::var_os(&name).is_none() {
+            std::env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    // add keys in API keys file to ones given directly. blank lines and lines starting with `#`
+    // are skipped
+    pub fn load_api_keys_file(&mut self) -> std::io::Result<()> {
+        if let Some(path) = &self.api_keys_file {
+            let keys = std::fs::read_to_string(path)?;
+            self.api_keys.extend(
+                keys.lines()
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty() && !key.starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        Ok(())
+    }
+
+    // expiry in seconds of new upload, with requested one clamped into configured bounds
+    pub fn upload_expiry(&self, requested: Option<u64>) -> Option<u64> {
+        match (requested, self.max_expiry) {
+            (Some(requested), Some(max)) => {
+                Some(requested.max(self.min_expiry.unwrap_or(1)).min(max))
+            }
+            _ => self
+                .expiry
+                .filter(|expiry| *expiry > 0)
+                .map(|expiry| expiry as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_of_file_are_named_as_env() {
+        let text = r#"
+            bind_addr = "0.0.0.0:80"
+            chunk_count_limit = 10
+            expiry_dry_run = true
+            download_mirrors = ["https://a.example", "https://b.example"]
+        "#;
+        let mut values = env_values(text).unwrap();
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                ("BIND_ADDR".to_owned(), "0.0.0.0:80".to_owned()),
+                ("CHUNK_COUNT_LIMIT".to_owned(), "10".to_owned()),
+                (
+                    "DOWNLOAD_MIRRORS".to_owned(),
+                    "https://a.example,https://b.example".to_owned()
+                ),
+                ("EXPIRY_DRY_RUN".to_owned(), "true".to_owned()),
+            ]
+        );
+
+        assert!(env_values("bind_adr = \"0.0.0.0:80\"").is_err());
+        assert!(env_values("config = \"other.toml\"").is_err());
+        assert!(env_values("[bind_addr]\nport = 80").is_err());
+    }
 }
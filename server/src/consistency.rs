@@ -0,0 +1,67 @@
+use rusqlite::Connection;
+
+use crate::db;
+
+// available files whose chunks are not exactly 1..=max(seq), e.g. after crash in the middle of
+// upload or deletion. files without any chunk are included as max(seq) is null there.
+const BROKEN_FILES_QUERY: &str = "select id from files where available = true and (select count(*) from file_contents where file_id = files.id) != coalesce((select max(seq) from file_contents where file_id = files.id), -1)";
+// chunks whose file row is gone. foreign keys are not enforced, so cascade may not have removed
+// them
+const ORPHAN_CHUNKS_QUERY: &str =
+    "select count(*) from file_contents where file_id not in (select id from files)";
+
+// check that every available file can be downloaded in whole and that no chunk is left without its
+// file. problems are logged, and repaired when `repair` is set by marking broken files unavailable
+// and deleting orphan chunks.
+pub fn check(conn: &mut Connection, repair: bool) -> rusqlite::Result<()> {
+    let broken_files = {
+        let mut stmt = conn.prepare(BROKEN_FILES_QUERY)?;
+        let ids = stmt.query_map([], |row| row.get::<_, i64>(0))?;
+        ids.collect::<Result<Vec<_>, _>>()?
+    };
+    let orphan_chunks: i64 = conn.query_row(ORPHAN_CHUNKS_QUERY, [], |row| row.get(0))?;
+
+    if broken_files.is_empty() && orphan_chunks == 0 {
+        log::info!("consistency check passed");
+        return Ok(());
+    }
+
+    for id in &broken_files {
+        log::warn!("file is available but has missing chunks: id={}", id);
+    }
+    log::warn!(
+        "consistency check found problems: broken_files={}, orphan_chunks={}",
+        broken_files.len(),
+        orphan_chunks
+    );
+
+    if !repair {
+        log::warn!("not repairing, pass --repair-on-startup to repair them");
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    let unavailable = db::retry(|| {
+        tx.execute(
+            &format!(
+                "update files set available = false where id in ({})",
+                BROKEN_FILES_QUERY
+            ),
+            [],
+        )
+    })?;
+    let deleted = db::retry(|| {
+        tx.execute(
+            "delete from file_contents where file_id not in (select id from files)",
+            [],
+        )
+    })?;
+    db::commit(tx)?;
+    log::info!(
+        "consistency repaired: marked_unavailable={}, deleted_chunks={}",
+        unavailable,
+        deleted
+    );
+
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rusqlite::{Connection, ErrorCode, OpenFlags, Transaction};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::{
+    config::Config,
+    repo::Deletable,
+    request_id::REQUEST_ID,
+    state::State,
+    storage::{self, Tx},
+};
+
+// attempts made for one operation before giving up on lock contention
+const MAX_ATTEMPTS: u32 = 5;
+// delay before first retry, doubled on every following retry
+const BASE_BACKOFF: Duration = Duration::from_millis(10);
+// statements kept prepared on each connection. hot queries of uploads and downloads are prepared
+// with `prepare_cached`, so that every chunk does not parse its sql again
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+// operations which met busy database at least once
+static CONTENDED: AtomicU64 = AtomicU64::new(0);
+// retries made in total
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+// operations which were still busy after all attempts
+static GAVE_UP: AtomicU64 = AtomicU64::new(0);
+
+// time spent waiting for connections, to tell how much requests serialize on them
+struct Waits {
+    count: AtomicU64,
+    micros: AtomicU64,
+}
+
+impl Waits {
+    const fn new() -> Self {
+        Waits {
+            count: AtomicU64::new(0),
+            micros: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, started: Instant) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.micros
+            .fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn seconds(&self) -> f64 {
+        self.micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    }
+}
+
+static WRITER_WAITS: Waits = Waits::new();
+static READER_WAITS: Waits = Waits::new();
+
+// totals of connection waits since startup, for metrics
+pub struct WaitStats {
+    pub writer_locks: u64,
+    pub writer_wait_seconds: f64,
+    pub reader_locks: u64,
+    pub reader_wait_seconds: f64,
+}
+
+pub fn wait_stats() -> WaitStats {
+    WaitStats {
+        writer_locks: WRITER_WAITS.count.load(Ordering::Relaxed),
+        writer_wait_seconds: WRITER_WAITS.seconds(),
+        reader_locks: READER_WAITS.count.load(Ordering::Relaxed),
+        reader_wait_seconds: READER_WAITS.seconds(),
+    }
+}
+
+// connection which every write goes through. sqlite admits one writer at a time anyway, so more
+// writing connections would only move waiting from here into busy retries
+pub struct Writer(Mutex<Connection>);
+
+impl Writer {
+    pub fn new(conn: Connection) -> Self {
+        Writer(Mutex::new(conn))
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, Connection> {
+        let started = Instant::now();
+        let conn = self.0.lock().await;
+        WRITER_WAITS.record(started);
+        conn
+    }
+
+    pub fn blocking_lock(&self) -> MutexGuard<'_, Connection> {
+        let started = Instant::now();
+        let conn = self.0.blocking_lock();
+        WRITER_WAITS.record(started);
+        conn
+    }
+
+    pub fn into_inner(self) -> Connection {
+        self.0.into_inner()
+    }
+}
+
+// read-only connections to database file, so that downloads do not wait for uploads writing. only
+// opened in WAL mode, as readers would block writer otherwise. empty pool sends reads to writer.
+#[derive(Default)]
+pub struct ReadPool {
+    conns: Vec<std::sync::Mutex<Connection>>,
+    next: AtomicUsize,
+}
+
+impl ReadPool {
+    pub fn open(config: &Config, writer: &Connection) -> rusqlite::Result<Self> {
+        if config.sqlite_read_connections == 0 {
+            return Ok(Default::default());
+        }
+        let mode: String = writer.query_row("pragma journal_mode", [], |row| row.get(0))?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            log::info!("database is not in wal mode. reads will share connection of writer");
+            return Ok(Default::default());
+        }
+
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI;
+        let conns = (0..config.sqlite_read_connections)
+            .map(|_| {
+                let conn = Connection::open_with_flags(&config.sqlite_db_filename, flags)?;
+                conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+                if let Some(timeout) = config.sqlite_busy_timeout {
+                    conn.busy_timeout(Duration::from_millis(timeout))?;
+                }
+                if let Some(cache_size) = config.sqlite_cache_size {
+                    conn.execute_batch(&format!("pragma cache_size = {}", cache_size))?;
+                }
+                Ok(std::sync::Mutex::new(conn))
+            })
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ReadPool {
+            conns,
+            next: Default::default(),
+        })
+    }
+
+    // idle connection if there is one, or else the next one in turn
+    fn take(&self) -> std::sync::MutexGuard<'_, Connection> {
+        let started = Instant::now();
+        let idle = self.conns.iter().find_map(|conn| conn.try_lock().ok());
+        let conn = idle.unwrap_or_else(|| {
+            let next = self.next.fetch_add(1, Ordering::Relaxed) % self.conns.len();
+            // connection of panicked read is still fine to read with
+            self.conns[next]
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        });
+        READER_WAITS.record(started);
+        conn
+    }
+}
+
+fn is_busy(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(e, _)
+            if e.code == ErrorCode::DatabaseBusy || e.code == ErrorCode::DatabaseLocked
+    )
+}
+
+// run database operation, retrying with bounded backoff while database is busy or locked by
+// someone else. blocks current thread while waiting like sqlite's own busy handler does, since
+// statements cannot be held across await points.
+pub fn retry<T, F>(mut op: F) -> rusqlite::Result<T>
+where
+    F: FnMut() -> rusqlite::Result<T>,
+{
+    let mut backoff = BASE_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(err) if is_busy(&err) => {
+                if attempt == 1 {
+                    CONTENDED.fetch_add(1, Ordering::Relaxed);
+                }
+                if attempt >= MAX_ATTEMPTS {
+                    GAVE_UP.fetch_add(1, Ordering::Relaxed);
+                    log::error!(
+                        "database still busy after {} attempts: {}",
+                        attempt,
+                        contention_stats()
+                    );
+                    return Err(err);
+                }
+                RETRIES.fetch_add(1, Ordering::Relaxed);
+                log::warn!(
+                    "database busy, retrying in {:?}: attempt={}, {}",
+                    backoff,
+                    attempt,
+                    contention_stats()
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// apply sqlite pragmas given in config. values of journal mode and synchronous are checked by
+// clap, so they are safe to put in statement
+pub fn tune(conn: &Connection, config: &Config) -> rusqlite::Result<()> {
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    if let Some(mode) = &config.sqlite_journal_mode {
+        // journal_mode returns the mode in effect, which differs if it cannot be changed
+        let mode_set: String =
+            conn.query_row(&format!("pragma journal_mode = {}", mode), [], |row| {
+                row.get(0)
+            })?;
+        if !mode_set.eq_ignore_ascii_case(mode) {
+            log::warn!("journal mode is {} instead of {}", mode_set, mode);
+        }
+    }
+    if let Some(synchronous) = &config.sqlite_synchronous {
+        conn.execute_batch(&format!("pragma synchronous = {}", synchronous))?;
+    }
+    if let Some(timeout) = config.sqlite_busy_timeout {
+        conn.busy_timeout(Duration::from_millis(timeout))?;
+    }
+    if let Some(cache_size) = config.sqlite_cache_size {
+        conn.execute_batch(&format!("pragma cache_size = {}", cache_size))?;
+    }
+    Ok(())
+}
+
+// incremental vacuum needs auto_vacuum to be incremental, which existing database only takes
+// after one full vacuum. that may take long for large database, so it is done once at startup.
+pub fn enable_incremental_vacuum(conn: &Connection) -> rusqlite::Result<()> {
+    let mode: i64 = conn.query_row("pragma auto_vacuum", [], |row| row.get(0))?;
+    // 2 is incremental
+    if mode == 2 {
+        return Ok(());
+    }
+    log::info!("enabling incremental vacuum. this rewrites database file once...");
+    conn.execute_batch("pragma auto_vacuum = incremental; vacuum")
+}
+
+// bytes of database file in pages freed by deletions
+fn free_bytes(conn: &Connection) -> rusqlite::Result<i64> {
+    let pages: i64 = conn.query_row("pragma freelist_count", [], |row| row.get(0))?;
+    let page_size: i64 = conn.query_row("pragma page_size", [], |row| row.get(0))?;
+    Ok(pages * page_size)
+}
+
+// return pages freed by deletions to filesystem and tell how many bytes were returned.
+// incremental vacuum only truncates free pages and does nothing unless it is enabled, while full
+// vacuum rewrites whole file.
+pub fn vacuum(conn: &Connection, full: bool) -> rusqlite::Result<i64> {
+    let before = free_bytes(conn)?;
+    if full {
+        retry(|| conn.execute_batch("vacuum"))?;
+    } else {
+        retry(|| conn.execute_batch("pragma incremental_vacuum"))?;
+    }
+    Ok((before - free_bytes(conn)?).max(0))
+}
+
+// run database work on blocking thread pool with connection locked, so that reading or writing
+// large chunks and waiting in `retry` do not stall runtime threads serving other requests
+pub async fn blocking<T, F>(state: Arc<State>, op: F) -> T
+where
+    F: FnOnce(&State, &mut Connection) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_blocking(move || {
+        let mut conn = state.conn.blocking_lock();
+        op(&state, &mut conn)
+    })
+    .await
+}
+
+// like `blocking`, but with connection of read pool, if there is any
+pub async fn read<T, F>(state: Arc<State>, op: F) -> T
+where
+    F: FnOnce(&State, &Connection) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    if state.readers.conns.is_empty() {
+        return blocking(state, move |state, conn| op(state, conn)).await;
+    }
+    spawn_blocking(move || {
+        let conn = state.readers.take();
+        op(&state, &conn)
+    })
+    .await
+}
+
+async fn spawn_blocking<T, F>(op: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    // blocking pool does not inherit task locals, so carry request id over for logs
+    let request_id = REQUEST_ID.try_with(|id| id.clone()).ok();
+    let result = tokio::task::spawn_blocking(move || match request_id {
+        Some(id) => REQUEST_ID.sync_scope(id, op),
+        None => op(),
+    })
+    .await;
+    match result {
+        Ok(result) => result,
+        // blocking tasks are never cancelled, so this is panic of `op`
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+// commit transaction with retry. `Transaction::commit` consumes transaction and rolls it back when
+// commit fails, so issue commit statement by hand to keep it open between attempts. if commit never
+// succeeds, dropping transaction rolls it back as usual.
+pub fn commit(tx: Transaction) -> rusqlite::Result<()> {
+    retry(|| tx.execute_batch("commit"))
+}
+
+// failure of work which touches both metadata in database and chunks in storage
+#[derive(Debug)]
+pub enum Error {
+    Sqlite(rusqlite::Error),
+    Storage(storage::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Sqlite(err) => fmt::Display::fmt(err, f),
+            Error::Storage(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Sqlite(err)
+    }
+}
+
+impl From<storage::Error> for Error {
+    fn from(err: storage::Error) -> Self {
+        Error::Storage(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// delete file together with its chunks and every row referring to it. returns number of deleted
+// file rows, which is 0 if file does not exist or is not `deletable`.
+pub fn delete_file(
+    state: &State,
+    tx: &Transaction,
+    id: i64,
+    deletable: Deletable,
+) -> Result<usize> {
+    let deleted = state.files.delete(tx, id, deletable)?;
+    if deleted == 0 {
+        return Ok(0);
+    }
+    state.storage.delete_file(Tx::of(tx), id)?;
+    Ok(deleted)
+}
+
+fn contention_stats() -> String {
+    format!(
+        "contended={}, retries={}, gave_up={}",
+        CONTENDED.load(Ordering::Relaxed),
+        RETRIES.load(Ordering::Relaxed),
+        GAVE_UP.load(Ordering::Relaxed)
+    )
+}
@@ -0,0 +1,137 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{FromRequest, Query, RequestParts},
+    http::{
+        header::{ACCESS_CONTROL_ALLOW_ORIGIN, VARY},
+        HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use crate::{error::ApiError, handlers::FileId, state::State, utils};
+
+// urls which let pages of one origin fetch metadata and ciphertext of one file cross-origin, until
+// they expire. signed by operator with embed url key, so that nobody else can hand them out.
+pub fn sign(key: &str, id: i64, origin: &str, expires: i64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("hmac accepts key of any length");
+    mac.update(&id.to_be_bytes());
+    mac.update(&expires.to_be_bytes());
+    mac.update(origin.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn verify(key: &str, id: i64, origin: &str, expires: i64, given: &str) -> bool {
+    let expected = sign(key, id, origin, expires);
+    utils::constant_time_eq(given.as_bytes(), expected.as_bytes())
+}
+
+// origin as browsers send it: scheme, host and optional port, without path
+pub fn is_origin(s: &str) -> bool {
+    let host = match s
+        .strip_prefix("https://")
+        .or_else(|| s.strip_prefix("http://"))
+    {
+        Some(host) => host,
+        None => return false,
+    };
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-.:[]".contains(&b))
+}
+
+// allow origin of embed url to read response, if request carries valid signature. requests
+// without one are served as usual, for same origin only.
+pub async fn cors<B: Send>(req: Request<B>, next: Next<B>) -> Response {
+    let mut parts = RequestParts::new(req);
+    let Query(params) = match Query::<HashMap<String, String>>::from_request(&mut parts).await {
+        Ok(params) => params,
+        Err(_) => return ApiError::from(StatusCode::BAD_REQUEST).into_response(),
+    };
+    let given = match params.get("signature") {
+        Some(given) => given.clone(),
+        None => return next.run(into_request(parts)).await,
+    };
+    let state = match parts.extensions().get::<Arc<State>>() {
+        Some(state) => state.clone(),
+        None => return ApiError::from(StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+    };
+    let FileId(id) = match FileId::from_request(&mut parts).await {
+        Ok(id) => id,
+        Err(err) => return err.into_response(),
+    };
+
+    let key = match &state.config.embed_url_key {
+        Some(key) => key,
+        None => {
+            log::error!("embed url given, but embed url key is not configured");
+            return ApiError::from(StatusCode::FORBIDDEN).into_response();
+        }
+    };
+    let origin = params.get("origin").map(String::as_str).unwrap_or_default();
+    let expires = match params.get("expires").map(|expires| expires.parse::<i64>()) {
+        Some(Ok(expires)) => expires,
+        _ => {
+            log::error!("embed url requires expires");
+            return ApiError::from(StatusCode::BAD_REQUEST).into_response();
+        }
+    };
+    if !verify(key, id, origin, expires, &given) {
+        log::error!("invalid embed url signature: id={}", id);
+        return ApiError::from(StatusCode::FORBIDDEN).into_response();
+    }
+    if expires <= state.clock.now() {
+        log::error!("embed url expired: id={}", id);
+        return ApiError::from(StatusCode::FORBIDDEN).into_response();
+    }
+    // signed origins were checked when signing, so this only fails for forged ones
+    let origin = match HeaderValue::from_str(origin) {
+        Ok(origin) => origin,
+        Err(_) => return ApiError::from(StatusCode::BAD_REQUEST).into_response(),
+    };
+
+    let mut resp = next.run(into_request(parts)).await;
+    resp.headers_mut()
+        .insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    resp.headers_mut()
+        .insert(VARY, HeaderValue::from_static("origin"));
+    resp
+}
+
+fn into_request<B>(parts: RequestParts<B>) -> Request<B> {
+    // body is left untouched by query and id extractors
+    parts.try_into_request().expect("body is not extracted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_bound_to_file_origin_and_expiry() {
+        let sig = sign("secret", 1, "https://example.com", 100);
+        assert!(verify("secret", 1, "https://example.com", 100, &sig));
+        assert!(!verify("secret", 2, "https://example.com", 100, &sig));
+        assert!(!verify("secret", 1, "https://evil.example", 100, &sig));
+        assert!(!verify("secret", 1, "https://example.com", 200, &sig));
+        assert!(!verify("other", 1, "https://example.com", 100, &sig));
+    }
+
+    #[test]
+    fn origins_have_no_path() {
+        assert!(is_origin("https://example.com"));
+        assert!(is_origin("http://localhost:8080"));
+        assert!(!is_origin("https://example.com/page"));
+        assert!(!is_origin("example.com"));
+        assert!(!is_origin("https://"));
+    }
+}
@@ -0,0 +1,274 @@
+use axum::{
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{json, Value};
+
+use crate::request_id::REQUEST_ID;
+
+// errors returned by API handlers. plain failures are told by status code alone, and the others
+// carry details for clients.
+#[derive(Debug)]
+pub enum ApiError {
+    Status(StatusCode),
+    ChunkCountExceeded {
+        chunk_count_limit: u64,
+        max_file_size: u64,
+    },
+    // some chunks of file are missing, so its ciphertext cannot be decrypted
+    FileIncomplete {
+        expected_chunks: i64,
+        found_chunks: i64,
+    },
+    TextQuotaExceeded {
+        text_uploads_per_day: u64,
+    },
+    // file is embargoed until given unix timestamp
+    NotYetAvailable {
+        available_from: i64,
+    },
+    // every declared chunk arrived but their total size does not match what client declared at
+    // prepare time
+    UploadIncomplete {
+        expected_chunks: i64,
+        found_chunks: i64,
+        expected_size: i64,
+        found_size: i64,
+    },
+    // last chunk is stored, but chunks before it are missing. not failure for clients uploading out
+    // of order, as file is published once the rest arrives
+    ChunksMissing {
+        last_seq: i64,
+        missing_chunks: i64,
+        missing_seqs: Vec<i64>,
+    },
+    // declared size is over size limit of server
+    FileTooLarge {
+        max_file_size: u64,
+    },
+    // chunks stored so far add up to more than declared size or size limit of server
+    UploadSizeExceeded {
+        max_size: i64,
+        found_size: i64,
+    },
+    // declared size is over limit of API key used for upload
+    ApiKeyMaxSizeExceeded {
+        max_size: i64,
+    },
+    ApiKeyQuotaExceeded {
+        max_files_per_day: i64,
+    },
+    // chunks of file are requested before its last chunk arrived
+    UploadInProgress,
+    // admin API is requested, but no admin token is configured
+    AdminDisabled,
+    // client error report is sent, but reports are not enabled in config
+    ClientErrorsDisabled,
+    // embed url is requested, but no embed url key is configured
+    EmbedKeyMissing,
+    // seconds until rate limited client may try again
+    RateLimited {
+        retry_after: u64,
+    },
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        ApiError::Status(status)
+    }
+}
+
+// code and message of plain failure, which is told only by its status
+fn status_code(status: StatusCode) -> (&'static str, &'static str) {
+    match status {
+        StatusCode::BAD_REQUEST => ("bad_request", "request is invalid"),
+        StatusCode::UNAUTHORIZED => ("unauthorized", "token or API key is required"),
+        StatusCode::FORBIDDEN => ("forbidden", "token or signature is invalid"),
+        StatusCode::NOT_FOUND => ("not_found", "file does not exist"),
+        StatusCode::CONFLICT => ("conflict", "request conflicts with stored file"),
+        StatusCode::GONE => (
+            "gone",
+            "file is expired or downloaded as many times as allowed",
+        ),
+        StatusCode::PAYLOAD_TOO_LARGE => ("payload_too_large", "request is too large"),
+        StatusCode::UNPROCESSABLE_ENTITY => ("unprocessable_entity", "request cannot be handled"),
+        StatusCode::TOO_MANY_REQUESTS => ("too_many_requests", "too many requests"),
+        StatusCode::METHOD_NOT_ALLOWED => ("method_not_allowed", "method is not allowed here"),
+        StatusCode::LENGTH_REQUIRED => ("length_required", "request needs content length"),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => {
+            ("unsupported_media_type", "content type is not supported")
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE => ("range_not_satisfiable", "range is out of file"),
+        StatusCode::BAD_GATEWAY => ("bad_gateway", "other server failed"),
+        StatusCode::SERVICE_UNAVAILABLE => ("service_unavailable", "server is not ready"),
+        StatusCode::GATEWAY_TIMEOUT => ("gateway_timeout", "other server did not answer"),
+        status if status.is_client_error() => ("client_error", "request cannot be handled"),
+        _ => ("internal_error", "server failed to handle request"),
+    }
+}
+
+impl ApiError {
+    // status, code for clients to tell errors apart, message for people, and fields particular
+    // to the code
+    fn parts(self) -> (StatusCode, &'static str, &'static str, Value) {
+        match self {
+            ApiError::Status(status) => {
+                let (code, message) = status_code(status);
+                (status, code, message, Value::Null)
+            }
+            ApiError::ChunkCountExceeded {
+                chunk_count_limit,
+                max_file_size,
+            } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "chunk_count_exceeded",
+                "file needs more chunks than server allows",
+                json!({
+                    "chunk_count_limit": chunk_count_limit,
+                    "max_file_size": max_file_size,
+                }),
+            ),
+            ApiError::FileIncomplete {
+                expected_chunks,
+                found_chunks,
+            } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "file_incomplete",
+                "some chunks of file are missing",
+                json!({
+                    "expected_chunks": expected_chunks,
+                    "found_chunks": found_chunks,
+                }),
+            ),
+            ApiError::TextQuotaExceeded {
+                text_uploads_per_day,
+            } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "text_quota_exceeded",
+                "too many text uploads today",
+                json!({ "text_uploads_per_day": text_uploads_per_day }),
+            ),
+            ApiError::NotYetAvailable { available_from } => (
+                StatusCode::FORBIDDEN,
+                "not_yet_available",
+                "file is not available yet",
+                json!({ "available_from": available_from }),
+            ),
+            ApiError::UploadIncomplete {
+                expected_chunks,
+                found_chunks,
+                expected_size,
+                found_size,
+            } => (
+                StatusCode::CONFLICT,
+                "upload_incomplete",
+                "chunks do not add up to declared size",
+                json!({
+                    "expected_chunks": expected_chunks,
+                    "found_chunks": found_chunks,
+                    "expected_size": expected_size,
+                    "found_size": found_size,
+                }),
+            ),
+            ApiError::ChunksMissing {
+                last_seq,
+                missing_chunks,
+                missing_seqs,
+            } => (
+                StatusCode::ACCEPTED,
+                "chunks_missing",
+                "last chunk is stored, but chunks before it are missing",
+                json!({
+                    "last_seq": last_seq,
+                    "missing_chunks": missing_chunks,
+                    "missing_seqs": missing_seqs,
+                }),
+            ),
+            ApiError::FileTooLarge { max_file_size } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "file_too_large",
+                "file is larger than server allows",
+                json!({ "max_file_size": max_file_size }),
+            ),
+            ApiError::UploadSizeExceeded {
+                max_size,
+                found_size,
+            } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "upload_size_exceeded",
+                "chunks add up to more than declared size or size limit",
+                json!({
+                    "max_size": max_size,
+                    "found_size": found_size,
+                }),
+            ),
+            ApiError::ApiKeyMaxSizeExceeded { max_size } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "api_key_max_size_exceeded",
+                "file is larger than API key allows",
+                json!({ "max_size": max_size }),
+            ),
+            ApiError::ApiKeyQuotaExceeded { max_files_per_day } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "api_key_quota_exceeded",
+                "API key uploaded as many files as allowed today",
+                json!({ "max_files_per_day": max_files_per_day }),
+            ),
+            ApiError::UploadInProgress => (
+                StatusCode::NOT_FOUND,
+                "upload_in_progress",
+                "file is still being uploaded",
+                Value::Null,
+            ),
+            ApiError::AdminDisabled => (
+                StatusCode::NOT_FOUND,
+                "admin_disabled",
+                "admin API is disabled, as no admin token is configured",
+                Value::Null,
+            ),
+            ApiError::ClientErrorsDisabled => (
+                StatusCode::NOT_FOUND,
+                "client_errors_disabled",
+                "client error reports are disabled",
+                Value::Null,
+            ),
+            ApiError::EmbedKeyMissing => (
+                StatusCode::NOT_FOUND,
+                "embed_key_missing",
+                "embed urls are disabled, as no embed url key is configured",
+                Value::Null,
+            ),
+            ApiError::RateLimited { retry_after } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                "too many requests, retry later",
+                json!({ "retry_after": retry_after }),
+            ),
+        }
+    }
+}
+
+// every error is JSON of `code`, `message` and `detail`, which is null for plain failures
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let request_id = REQUEST_ID.try_with(|id| id.clone()).ok();
+        let retry_after = match &self {
+            ApiError::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        };
+
+        let (status, code, message, detail) = self.parts();
+        let body = Json(json!({
+            "code": code,
+            "message": message,
+            "detail": detail,
+            "request_id": request_id,
+        }));
+        let mut resp = (status, body).into_response();
+        if let Some(retry_after) = retry_after {
+            resp.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        resp
+    }
+}
@@ -0,0 +1,138 @@
+use axum::{
+    http::{
+        header::{ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+
+use crate::config::Config;
+
+// languages of built-in pages, first one being the default
+const LANGUAGES: &[&str] = &["en", "ko"];
+
+fn builtin_not_found(lang: &str) -> &'static str {
+    match lang {
+        "ko" => include_str!("../pages/not_found.ko.html"),
+        _ => include_str!("../pages/not_found.en.html"),
+    }
+}
+
+fn builtin_unsupported(lang: &str) -> &'static str {
+    match lang {
+        "ko" => include_str!("../pages/unsupported.ko.html"),
+        _ => include_str!("../pages/unsupported.en.html"),
+    }
+}
+
+// pick most preferred language we have from `accept-language` header
+fn negotiate(headers: &HeaderMap) -> &'static str {
+    let accept = headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let mut ranges: Vec<(String, f32)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            // only primary subtag matters, e.g. `ko` of `ko-KR`
+            let primary = tag.split('-').next()?.to_lowercase();
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((primary, q))
+        })
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    // stable, so that order in header breaks ties
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    ranges
+        .iter()
+        .find_map(|(tag, _)| LANGUAGES.iter().find(|lang| **lang == tag.as_str()))
+        .copied()
+        .unwrap_or(LANGUAGES[0])
+}
+
+// page for paths which are neither api nor webapp. operator can replace it by putting
+// `not_found.<lang>.html` or `not_found.html` into error pages directory.
+pub fn not_found(config: &Config, headers: &HeaderMap) -> Response {
+    page(
+        config,
+        headers,
+        StatusCode::NOT_FOUND,
+        "not_found",
+        builtin_not_found,
+    )
+}
+
+// page without webapp for browsers which cannot run it, telling recipients to open their link
+// elsewhere. webapp index sends them here. replaced by `unsupported.<lang>.html` or
+// `unsupported.html` in error pages directory.
+pub fn unsupported(config: &Config, headers: &HeaderMap) -> Response {
+    page(
+        config,
+        headers,
+        StatusCode::OK,
+        "unsupported",
+        builtin_unsupported,
+    )
+}
+
+fn page(
+    config: &Config,
+    headers: &HeaderMap,
+    status: StatusCode,
+    name: &str,
+    builtin: fn(&str) -> &'static str,
+) -> Response {
+    let lang = negotiate(headers);
+
+    let custom = config.error_pages_dir.as_ref().and_then(|dir| {
+        [
+            format!("{}/{}.{}.html", dir, name, lang),
+            format!("{}/{}.html", dir, name),
+        ]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+    });
+    let body = custom.unwrap_or_else(|| builtin(lang).to_owned());
+
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    resp_headers.insert(CONTENT_LANGUAGE, HeaderValue::from_static(lang));
+    resp_headers.insert(VARY, HeaderValue::from_static("accept-language"));
+
+    (status, resp_headers, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::ACCEPT_LANGUAGE, HeaderMap, HeaderValue};
+
+    fn negotiate(accept: &str) -> &'static str {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_str(accept).unwrap());
+        super::negotiate(&headers)
+    }
+
+    #[test]
+    fn preferred_language_is_chosen() {
+        assert_eq!(negotiate("ko-KR,ko;q=0.9,en-US;q=0.8"), "ko");
+        assert_eq!(negotiate("en-US;q=0.5, ko;q=0.7"), "ko");
+        assert_eq!(negotiate("fr, en;q=0.1"), "en");
+    }
+
+    #[test]
+    fn default_language_is_used_otherwise() {
+        assert_eq!(negotiate("fr, de;q=0.5"), "en");
+        assert_eq!(negotiate("ko;q=0"), "en");
+        assert_eq!(super::negotiate(&HeaderMap::new()), "en");
+    }
+}
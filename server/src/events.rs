@@ -0,0 +1,257 @@
+use axum::async_trait;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, UnixStream},
+    sync::{broadcast, mpsc},
+};
+
+use crate::{
+    config::Config,
+    webhook::{self, Error, HttpClient},
+};
+
+// events waiting for sinks. once it is full, new events are dropped rather than slowing down
+// uploads and downloads
+const QUEUE_SIZE: usize = 1024;
+// how far a client watching files can fall behind before it misses events
+const WATCH_CAPACITY: usize = 64;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // prepared, and waiting for chunks
+    Created,
+    // every chunk is there, and file can be downloaded
+    Completed,
+    Downloaded,
+    Expired,
+    // by uploader, admin, last allowed download or abandoned upload purge
+    Deleted,
+}
+
+// lifecycle event of file. carries no content or metadata of file, only what happened to which id
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub event: Kind,
+    pub id: i64,
+    // unix timestamp
+    pub at: i64,
+}
+
+// destination of lifecycle events
+#[async_trait]
+trait Sink: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn send(&self, event: &Event) -> Result<(), Error>;
+}
+
+// handle to emit events with. only clients watching files hear them if no sink is configured
+pub struct Events {
+    sender: Option<mpsc::Sender<Event>>,
+    watchers: broadcast::Sender<Event>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let (watchers, _) = broadcast::channel(WATCH_CAPACITY);
+        Events {
+            sender: None,
+            watchers,
+        }
+    }
+}
+
+impl Events {
+    // start forwarding events to every sink configured. must be called on runtime
+    pub fn start(config: &Config) -> Self {
+        let sinks = from_config(config);
+        if sinks.is_empty() {
+            return Default::default();
+        }
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(forward(receiver, sinks));
+        Events {
+            sender: Some(sender),
+            ..Default::default()
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.watchers.subscribe()
+    }
+
+    // queue event after change is committed, so that sinks never hear of rolled back ones
+    pub fn emit(&self, event: Kind, id: i64, at: i64) {
+        let event = Event { event, id, at };
+        if self.watchers.receiver_count() > 0 {
+            // nobody may be listening anymore, which is fine
+            let _ = self.watchers.send(event.clone());
+        }
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        if let Err(err) = sender.try_send(event) {
+            log::error!("failed to queue event: {:?}", err);
+        }
+    }
+}
+
+fn from_config(config: &Config) -> Vec<Box<dyn Sink>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    if let Some(url) = config.event_webhook_url.clone() {
+        sinks.push(Box::new(WebhookSink {
+            client: webhook::client(),
+            url,
+        }));
+    }
+    if let Some(path) = config.event_socket.clone() {
+        sinks.push(Box::new(UnixSocketSink { path }));
+    }
+    if let Some(addr) = config.event_nats_addr.clone() {
+        sinks.push(Box::new(NatsSink {
+            addr,
+            subject: config.event_nats_subject.clone(),
+        }));
+    }
+    sinks
+}
+
+// events are sent one by one in order they happened, each to every sink
+async fn forward(mut receiver: mpsc::Receiver<Event>, sinks: Vec<Box<dyn Sink>>) {
+    while let Some(event) = receiver.recv().await {
+        for sink in &sinks {
+            if let Err(err) = sink.send(&event).await {
+                log::error!("failed to send event via {}: {:?}", sink.name(), err);
+            }
+        }
+    }
+}
+
+// event as JSON body of POST request
+struct WebhookSink {
+    client: HttpClient,
+    url: String,
+}
+
+#[async_trait]
+impl Sink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn send(&self, event: &Event) -> Result<(), Error> {
+        webhook::post_json(&self.client, &self.url, event).await
+    }
+}
+
+// event as one JSON line, over its own connection so that listener may come and go
+struct UnixSocketSink {
+    path: String,
+}
+
+#[async_trait]
+impl Sink for UnixSocketSink {
+    fn name(&self) -> &'static str {
+        "unix socket"
+    }
+
+    async fn send(&self, event: &Event) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut stream = UnixStream::connect(&self.path).await?;
+        stream.write_all(&line).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+// event published as JSON message over plain NATS protocol, without auth or TLS. connection is
+// made per event, and PING after PUB waits until server has processed it
+struct NatsSink {
+    addr: String,
+    subject: String,
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn send(&self, event: &Event) -> Result<(), Error> {
+        let payload = serde_json::to_vec(event)?;
+        let mut stream = BufReader::new(TcpStream::connect(&self.addr).await?);
+
+        // server greets with INFO first
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        if !line.starts_with("INFO") {
+            return Err(format!("unexpected greeting from nats: {}", line.trim_end()).into());
+        }
+
+        let mut msg = format!(
+            "CONNECT {{\"verbose\":false,\"pedantic\":false}}\r\nPUB {} {}\r\n",
+            self.subject,
+            payload.len()
+        )
+        .into_bytes();
+        msg.extend_from_slice(&payload);
+        msg.extend_from_slice(b"\r\nPING\r\n");
+        stream.get_mut().write_all(&msg).await?;
+
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await? == 0 {
+                return Err("nats closed connection before PONG".into());
+            }
+            if line.starts_with("-ERR") {
+                return Err(format!("nats responded with {}", line.trim_end()).into());
+            }
+            if line.starts_with("PONG") {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn event_is_flat_json_object() {
+        let event = Event {
+            event: Kind::Completed,
+            id: 3,
+            at: 1643587200,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"completed","id":3,"at":1643587200}"#
+        );
+    }
+
+    #[test]
+    fn events_are_dropped_without_sinks() {
+        let events = Events::start(&Config::parse_from(["hako"]));
+        assert!(events.sender.is_none());
+        events.emit(Kind::Created, 1, 0);
+    }
+
+    #[test]
+    fn watchers_hear_events_without_sinks() {
+        let events = Events::start(&Config::parse_from(["hako"]));
+        let mut watcher = events.subscribe();
+        events.emit(Kind::Deleted, 1, 0);
+        let event = Event {
+            event: Kind::Deleted,
+            id: 1,
+            at: 0,
+        };
+        assert_eq!(watcher.try_recv().unwrap(), event);
+    }
+}
@@ -0,0 +1,192 @@
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::repo::{InstanceRepo, StorageDay};
+
+// days of history kept, which is as far as forecast may look back
+pub const HISTORY_DAYS: i64 = 365;
+const DAY: i64 = 24 * 60 * 60;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Forecast {
+    growth_bytes_per_day: Option<i64>,
+    days_until_full: Option<i64>,
+    summary: String,
+}
+
+// bytes stored now, overwriting earlier snapshot of the same day. history past `HISTORY_DAYS` is
+// dropped at the same time.
+pub fn record_usage(
+    repo: &dyn InstanceRepo,
+    conn: &Connection,
+    now: i64,
+    stored_bytes: i64,
+) -> rusqlite::Result<()> {
+    repo.record_stored_bytes(conn, now, stored_bytes)?;
+    repo.forget_storage_history(conn, now - HISTORY_DAYS * DAY)
+}
+
+// files deleted by workers, added to totals of the day
+pub fn record_deletion(
+    repo: &dyn InstanceRepo,
+    conn: &Connection,
+    now: i64,
+    files: i64,
+    bytes: i64,
+) {
+    if files == 0 {
+        return;
+    }
+    // accounting failure should not fail deletion itself
+    if let Err(err) = repo.record_deletion(conn, now, files, bytes) {
+        log::error!("failed to record deleted files: {:?}", err);
+    }
+}
+
+// history of last `days` days, oldest first
+pub fn history(
+    repo: &dyn InstanceRepo,
+    conn: &Connection,
+    now: i64,
+    days: i64,
+) -> rusqlite::Result<Vec<StorageDay>> {
+    repo.storage_history(conn, now - days * DAY)
+}
+
+// linear forecast from first and last snapshot of history. `used` is compared against `capacity`,
+// as stored bytes do not count database overhead.
+pub fn forecast(history: &[StorageDay], used: u64, capacity: Option<u64>) -> Forecast {
+    let mut snapshots = history
+        .iter()
+        .filter_map(|day| Some((day.days_since_epoch, day.stored_bytes?)));
+    let (first, last) = match (snapshots.next(), snapshots.last()) {
+        (Some(first), Some(last)) if last.0 > first.0 => (first, last),
+        _ => {
+            return Forecast {
+                growth_bytes_per_day: None,
+                days_until_full: None,
+                summary: "not enough history to forecast yet".to_owned(),
+            }
+        }
+    };
+    let growth = (last.1 - first.1) / (last.0 - first.0);
+
+    let capacity = match capacity {
+        Some(capacity) => capacity,
+        None => {
+            return Forecast {
+                growth_bytes_per_day: Some(growth),
+                days_until_full: None,
+                summary: format!(
+                    "growing {} bytes per day. storage capacity is not configured",
+                    growth
+                ),
+            }
+        }
+    };
+    let (days_until_full, summary) = if used >= capacity {
+        (Some(0), "disk is full".to_owned())
+    } else if growth <= 0 {
+        (None, "storage is not growing at current rate".to_owned())
+    } else {
+        let left = (capacity - used) as i64;
+        let days = (left + growth - 1) / growth;
+        (
+            Some(days),
+            format!("at current growth, disk full in ~{} days", days),
+        )
+    };
+    Forecast {
+        growth_bytes_per_day: Some(growth),
+        days_until_full,
+        summary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{migrations, repo::SqliteInstanceRepo};
+
+    // 2022-01-31
+    const NOW: i64 = 1643587200;
+
+    #[test]
+    fn deletions_and_snapshots_share_day() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../schema.sql")).unwrap();
+        migrations::run(&mut conn).unwrap();
+
+        record_usage(&SqliteInstanceRepo, &conn, NOW - 2 * DAY, 100).unwrap();
+        record_deletion(&SqliteInstanceRepo, &conn, NOW - DAY, 2, 30);
+        record_usage(&SqliteInstanceRepo, &conn, NOW, 150).unwrap();
+        record_usage(&SqliteInstanceRepo, &conn, NOW + 60, 200).unwrap();
+        record_deletion(&SqliteInstanceRepo, &conn, NOW, 1, 10);
+        record_deletion(&SqliteInstanceRepo, &conn, NOW, 1, 20);
+
+        let history = history(&SqliteInstanceRepo, &conn, NOW, 30).unwrap();
+        let days = history
+            .iter()
+            .map(|day| {
+                (
+                    day.day.as_str(),
+                    day.stored_bytes,
+                    day.deleted_files,
+                    day.deleted_bytes,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            days,
+            vec![
+                ("2022-01-29", Some(100), 0, 0),
+                ("2022-01-30", None, 2, 30),
+                ("2022-01-31", Some(200), 2, 30),
+            ]
+        );
+        assert_eq!(history[2].days_since_epoch - history[0].days_since_epoch, 2);
+
+        // old history is dropped with next snapshot
+        record_usage(&SqliteInstanceRepo, &conn, NOW + HISTORY_DAYS * DAY, 200).unwrap();
+        let history = super::history(
+            &SqliteInstanceRepo,
+            &conn,
+            NOW + HISTORY_DAYS * DAY,
+            HISTORY_DAYS + 1,
+        )
+        .unwrap();
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn disk_fills_at_growth_between_first_and_last_snapshot() {
+        let day = |days_since_epoch, stored_bytes| StorageDay {
+            day: String::new(),
+            stored_bytes,
+            deleted_files: 0,
+            deleted_bytes: 0,
+            days_since_epoch,
+        };
+        let history = vec![day(10, Some(100)), day(11, None), day(14, Some(500))];
+
+        let forecast = forecast(&history, 1000, Some(2050));
+        assert_eq!(forecast.growth_bytes_per_day, Some(100));
+        assert_eq!(forecast.days_until_full, Some(11));
+        assert_eq!(forecast.summary, "at current growth, disk full in ~11 days");
+
+        assert_eq!(super::forecast(&history, 1000, None).days_until_full, None);
+        assert_eq!(
+            super::forecast(&history, 3000, Some(2050)).days_until_full,
+            Some(0)
+        );
+        let shrinking = vec![day(10, Some(500)), day(14, Some(100))];
+        assert_eq!(
+            super::forecast(&shrinking, 1000, Some(2050)).days_until_full,
+            None
+        );
+        assert_eq!(
+            super::forecast(&history[..2], 1000, Some(2050)).growth_bytes_per_day,
+            None
+        );
+    }
+}
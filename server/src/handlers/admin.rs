@@ -0,0 +1,848 @@
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+
+use axum::{
+    extract::{Extension, Query},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::Json,
+};
+use hako_core::PreparedFile;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::repo::{
+    AuditLogEntry, BandwidthUsage, ClientErrorCount, Deletable, FileSummary, IssuedApiKey,
+    NewApiKey, PendingUpload, StorageDay,
+};
+use crate::{
+    config::Config, db, embed, error::ApiError, events::Kind, growth, relay, share_id,
+    state::State, storage::Tx, utils, workers,
+};
+
+// hash of uploader address shown to admins, to tell sessions of same client apart without
+// listing addresses themselves. plain hash of address would be reversed by trying every ipv4
+// address, so it is keyed by secret of this instance.
+pub fn ip_hash(key: &[u8], ip: IpAddr) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts key of any length");
+    mac.update(ip.to_string().as_bytes());
+    let digest = mac.finalize().into_bytes();
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// admin API is enabled only when admin token is configured, and answers 404 otherwise
+fn authorize(config: &Config, headers: &HeaderMap) -> Result<(), ApiError> {
+    let token = match &config.admin_token {
+        Some(token) => token,
+        None => return Err(ApiError::AdminDisabled),
+    };
+
+    let given = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    if !utils::constant_time_eq(given.as_bytes(), token.as_bytes()) {
+        log::error!("admin request with invalid token");
+        return Err(StatusCode::UNAUTHORIZED.into());
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct UploadSession {
+    #[serde(flatten)]
+    upload: PendingUpload,
+    chunks: i64,
+    bytes: i64,
+}
+
+// files which are prepared but whose last chunk has not arrived yet
+pub async fn upload_sessions(
+    state: Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<UploadSession>>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<Vec<UploadSession>>, ApiError> {
+            let result = state
+                .files
+                .pending_uploads(conn, state.clock.now())
+                .map_err(db::Error::from)
+                .and_then(|uploads| {
+                    uploads
+                        .into_iter()
+                        .map(|upload| -> db::Result<_> {
+                            let chunks = state.storage.chunks(Tx::of(conn), upload.id, 1)?;
+                            Ok(UploadSession {
+                                upload,
+                                chunks: chunks.count,
+                                bytes: chunks.bytes,
+                            })
+                        })
+                        .collect::<db::Result<Vec<_>>>()
+                });
+            match result {
+                Ok(sessions) => Ok(Json(sessions)),
+                Err(err) => {
+                    log::error!("failed to query upload sessions: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+// drop incomplete upload with everything uploaded so far. chunks which were still in flight are
+// left without file, and reported by consistency check on next startup.
+pub async fn abort_upload_session(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<&'static str, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let id = match params.get("id").map(|id| id.parse::<i64>()) {
+        Some(Ok(id)) if id > 0 => id,
+        _ => {
+            log::error!("id should be positive integer");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+
+    db::blocking(
+        state.0.clone(),
+        move |state, conn| -> Result<&'static str, ApiError> {
+            let tx = match conn.transaction() {
+                Ok(tx) => tx,
+                Err(err) => {
+                    log::error!("could not build transaction object: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+
+            // only incomplete uploads can be aborted here
+            match db::delete_file(state, &tx, id, Deletable::IncompleteNotHeld) {
+                Ok(0) => return Err(StatusCode::NOT_FOUND.into()),
+                Ok(_) => {}
+                Err(err) => {
+                    log::error!("failed to delete file: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+
+            if let Err(err) = db::commit(tx) {
+                log::error!("failed to commit: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+
+            state.events.emit(Kind::Deleted, id, state.clock.now());
+            log::info!("aborted upload session: id={}", id);
+            Ok("ok")
+        },
+    )
+    .await
+}
+
+// files on legal hold are skipped by expiry worker and every way of deleting them, until hold is
+// cleared. each change is recorded in audit log with optional `reason` query parameter.
+pub async fn set_legal_hold(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<&'static str, ApiError> {
+    update_legal_hold(state, params, headers, true).await
+}
+
+pub async fn clear_legal_hold(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<&'static str, ApiError> {
+    update_legal_hold(state, params, headers, false).await
+}
+
+async fn update_legal_hold(
+    state: Extension<Arc<State>>,
+    params: HashMap<String, String>,
+    headers: HeaderMap,
+    hold: bool,
+) -> Result<&'static str, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let id = match params.get("id").map(|id| id.parse::<i64>()) {
+        Some(Ok(id)) if id > 0 => id,
+        _ => {
+            log::error!("id should be positive integer");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+    let reason = params.get("reason").cloned();
+    let action = if hold {
+        "set_legal_hold"
+    } else {
+        "clear_legal_hold"
+    };
+
+    db::blocking(
+        state.0.clone(),
+        move |state, conn| -> Result<&'static str, ApiError> {
+            let tx = match conn.transaction() {
+                Ok(tx) => tx,
+                Err(err) => {
+                    log::error!("could not build transaction object: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+
+            match state.files.set_legal_hold(&tx, id, hold) {
+                Ok(false) => return Err(StatusCode::NOT_FOUND.into()),
+                Ok(true) => {}
+                Err(err) => {
+                    log::error!("failed to update legal hold: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+
+            if let Err(err) = state.instance.audit(&tx, action, id, reason.as_deref()) {
+                log::error!("failed to write audit log: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+
+            if let Err(err) = db::commit(tx) {
+                log::error!("failed to commit: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+
+            log::info!("{}: id={}, reason={:?}", action, id, reason);
+            Ok("ok")
+        },
+    )
+    .await
+}
+
+pub async fn audit_log(
+    state: Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<AuditLogEntry>>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<Vec<AuditLogEntry>>, ApiError> {
+            match state.instance.audit_log(conn) {
+                Ok(entries) => Ok(Json(entries)),
+                Err(err) => {
+                    log::error!("failed to query audit log: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+// monthly bytes transferred for files of each uploader, optionally only of given `month` in
+// `YYYY-MM` form
+pub async fn bandwidth(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<BandwidthUsage>>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let month = params.get("month").cloned();
+    if let Some(month) = &month {
+        let valid = month.len() == 7
+            && month
+                .char_indices()
+                .all(|(i, c)| if i == 4 { c == '-' } else { c.is_ascii_digit() });
+        if !valid {
+            log::error!("month should be in YYYY-MM form: {}", month);
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    }
+
+    db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<Vec<BandwidthUsage>>, ApiError> {
+            match state.instance.bandwidth(conn, month.as_deref()) {
+                Ok(usage) => Ok(Json(usage)),
+                Err(err) => {
+                    log::error!("failed to query bandwidth usage: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+// error reports sent by webapp users, counted per kind, app version and browser
+pub async fn client_errors(
+    state: Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ClientErrorCount>>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<Vec<ClientErrorCount>>, ApiError> {
+            match state.instance.client_error_counts(conn) {
+                Ok(counts) => Ok(Json(counts)),
+                Err(err) => {
+                    log::error!("failed to query client errors: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+// files which expiry worker would delete if it ran now, or at unix timestamp `at`
+pub async fn expiry_preview(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<workers::ExpiredFile>>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let at = match params.get("at").map(|at| at.parse::<i64>()) {
+        Some(Ok(at)) => at,
+        Some(Err(_)) => {
+            log::error!("at should be unix timestamp");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+        None => state.0.clock.now(),
+    };
+
+    db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<Vec<workers::ExpiredFile>>, ApiError> {
+            match workers::expired_files(state, conn, at) {
+                Ok(files) => Ok(Json(files)),
+                Err(err) => {
+                    log::error!("failed to query expired files: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+#[derive(Serialize)]
+pub struct StoredFile {
+    #[serde(flatten)]
+    file: FileSummary,
+    chunks: i64,
+    bytes: i64,
+}
+
+// every stored file, newest first, paged by `limit` and `offset`
+pub async fn files(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<StoredFile>>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let limit = match params.get("limit").map(|n| n.parse::<i64>()) {
+        Some(Ok(n)) if n > 0 => n,
+        None => 100,
+        _ => {
+            log::error!("limit should be positive integer");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+    let offset = match params.get("offset").map(|n| n.parse::<i64>()) {
+        Some(Ok(n)) if n >= 0 => n,
+        None => 0,
+        _ => {
+            log::error!("offset should be non-negative integer");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+
+    db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<Vec<StoredFile>>, ApiError> {
+            let result = state
+                .files
+                .list(conn, limit, offset)
+                .map_err(db::Error::from)
+                .and_then(|files| {
+                    files
+                        .into_iter()
+                        .map(|file| -> db::Result<_> {
+                            let chunks = state.storage.chunks(Tx::of(conn), file.id, 1)?;
+                            Ok(StoredFile {
+                                file,
+                                chunks: chunks.count,
+                                bytes: chunks.bytes,
+                            })
+                        })
+                        .collect::<db::Result<Vec<_>>>()
+                });
+            match result {
+                Ok(files) => Ok(Json(files)),
+                Err(err) => {
+                    log::error!("failed to query files: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+// delete any file right away, except ones on legal hold. recorded in audit log with optional
+// `reason` query parameter.
+pub async fn delete_file(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<&'static str, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let id = match params.get("id").map(|id| id.parse::<i64>()) {
+        Some(Ok(id)) if id > 0 => id,
+        _ => {
+            log::error!("id should be positive integer");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+    let reason = params.get("reason").cloned();
+
+    db::blocking(
+        state.0.clone(),
+        move |state, conn| -> Result<&'static str, ApiError> {
+            let tx = match conn.transaction() {
+                Ok(tx) => tx,
+                Err(err) => {
+                    log::error!("could not build transaction object: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+
+            match state.files.legal_hold(&tx, id) {
+                Ok(Some(false)) => {}
+                Ok(Some(true)) => {
+                    log::error!("file is on legal hold: id={}", id);
+                    return Err(StatusCode::CONFLICT.into());
+                }
+                Ok(None) => return Err(StatusCode::NOT_FOUND.into()),
+                Err(err) => {
+                    log::error!("failed to query file: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+
+            if let Err(err) = db::delete_file(state, &tx, id, Deletable::NotHeld) {
+                log::error!("failed to delete file: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+
+            let audit = state
+                .instance
+                .audit(&tx, "delete_file", id, reason.as_deref());
+            if let Err(err) = audit {
+                log::error!("failed to write audit log: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+
+            if let Err(err) = db::commit(tx) {
+                log::error!("failed to commit: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+
+            state.events.emit(Kind::Deleted, id, state.clock.now());
+            log::info!("deleted file by admin: id={}, reason={:?}", id, reason);
+            Ok("ok")
+        },
+    )
+    .await
+}
+
+#[derive(Serialize)]
+pub struct InstanceStats {
+    files: i64,
+    available_files: i64,
+    incomplete_uploads: i64,
+    legal_holds: i64,
+    chunks: i64,
+    stored_bytes: i64,
+    db_file_size: Option<u64>,
+    uptime_seconds: i64,
+}
+
+pub async fn stats(
+    state: Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Result<Json<InstanceStats>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let now = state.0.clock.now();
+    let counts = db::read(state.0.clone(), move |state, conn| -> db::Result<_> {
+        let counts = state.files.counts(conn, now)?;
+        let usage = state.storage.usage(Tx::of(conn))?;
+        Ok((counts, usage))
+    })
+    .await;
+    let (counts, usage) = match counts {
+        Ok(counts) => counts,
+        Err(err) => {
+            log::error!("failed to query stats: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+
+    let db_file_size = match workers::db_file_size(&state.0.config.sqlite_db_filename) {
+        Ok(size) => Some(size),
+        Err(err) => {
+            log::error!("failed to get database file size: {:?}", err);
+            None
+        }
+    };
+
+    Ok(Json(InstanceStats {
+        files: counts.files,
+        available_files: counts.available,
+        incomplete_uploads: counts.incomplete,
+        legal_holds: counts.legal_holds,
+        chunks: usage.chunks,
+        stored_bytes: usage.bytes,
+        db_file_size,
+        uptime_seconds: state.0.clock.uptime(),
+    }))
+}
+
+#[derive(Serialize)]
+pub struct StorageForecast {
+    history: Vec<StorageDay>,
+    // database file size, which is what fills up disk
+    used_bytes: u64,
+    capacity_bytes: Option<u64>,
+    #[serde(flatten)]
+    forecast: growth::Forecast,
+}
+
+// daily stored bytes and deleted files of last `days` days (30 by default), and when storage
+// capacity runs out if it keeps growing as it did over them
+pub async fn storage_forecast(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<StorageForecast>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let days = match params.get("days").map(|days| days.parse::<i64>()) {
+        Some(Ok(days)) if (1..=growth::HISTORY_DAYS).contains(&days) => days,
+        None => 30,
+        _ => {
+            log::error!("days should be between 1 and {}", growth::HISTORY_DAYS);
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+
+    let history = db::read(state.0.clone(), move |state, conn| {
+        growth::history(&*state.instance, conn, state.clock.now(), days)
+    })
+    .await;
+    let history = match history {
+        Ok(history) => history,
+        Err(err) => {
+            log::error!("failed to query storage history: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+
+    let config = &state.0.config;
+    let used_bytes = match workers::db_file_size(&config.sqlite_db_filename) {
+        Ok(size) => size,
+        Err(err) => {
+            log::error!("failed to get database file size: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    let forecast = growth::forecast(&history, used_bytes, config.storage_capacity);
+
+    Ok(Json(StorageForecast {
+        history,
+        used_bytes,
+        capacity_bytes: config.storage_capacity,
+        forecast,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct Vacuumed {
+    reclaimed_bytes: i64,
+}
+
+// return space freed by deletions to filesystem now, e.g. after deleting many files. `full=true`
+// runs full vacuum, which blocks every request until whole database file is rewritten.
+pub async fn vacuum(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<Vacuumed>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let full = match params.get("full").map(|full| full.parse::<bool>()) {
+        Some(Ok(full)) => full,
+        Some(Err(_)) => {
+            log::error!("full should be true or false");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+        None => state.0.config.full_vacuum,
+    };
+
+    match db::blocking(state.0.clone(), move |_, conn| db::vacuum(conn, full)).await {
+        Ok(reclaimed_bytes) => {
+            log::info!("reclaimed {} bytes of database file", reclaimed_bytes);
+            Ok(Json(Vacuumed { reclaimed_bytes }))
+        }
+        Err(err) => {
+            log::error!("failed to vacuum database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RelaySource {
+    // share url of file on other hako instance. passphrase in its fragment is ignored
+    source: String,
+}
+
+// copy file of other hako instance here, e.g. before it expires there. recipients open the copy
+// with the same passphrase, at share url of this server which is returned with deletion token
+pub async fn relay_file(
+    state: Extension<Arc<State>>,
+    Query(params): Query<RelaySource>,
+    headers: HeaderMap,
+) -> Result<Json<PreparedFile>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let source = match relay::parse_source(&params.source) {
+        Some(source) => source,
+        None => {
+            log::error!("source should be share url: {}", params.source);
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+    relay::relay(state.0.clone(), &source)
+        .await
+        .map(Json)
+        .map_err(Into::into)
+}
+
+#[derive(Deserialize)]
+pub struct NewEmbedUrl {
+    // share id of file
+    id: String,
+    // origin of site embedding file, e.g. `https://example.com`
+    origin: String,
+    // seconds until url stops working
+    ttl: i64,
+}
+
+#[derive(Serialize)]
+pub struct EmbedUrl {
+    // query to append to /api/metadata, /api/download or /api/open
+    query: String,
+    expires_at: i64,
+}
+
+// sign url with which pages of given origin can fetch metadata and ciphertext of file, to decrypt
+// it themselves. passphrase is still needed, so this only lifts same origin policy.
+pub async fn create_embed_url(
+    state: Extension<Arc<State>>,
+    Query(new): Query<NewEmbedUrl>,
+    headers: HeaderMap,
+) -> Result<Json<EmbedUrl>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let key = match &state.0.config.embed_url_key {
+        Some(key) => key,
+        None => {
+            log::error!("embed url key is not configured");
+            return Err(ApiError::EmbedKeyMissing);
+        }
+    };
+    if !embed::is_origin(&new.origin) {
+        log::error!("invalid origin: {}", new.origin);
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    if new.ttl <= 0 {
+        log::error!("ttl should be positive");
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    let share_id = new.id.clone();
+    let id = db::read(state.0.clone(), move |state, conn| {
+        share_id::resolve(state, conn, &share_id)
+    })
+    .await?;
+
+    let expires_at = state.0.clock.now() + new.ttl;
+    let signature = embed::sign(key, id, &new.origin, expires_at);
+    let query = format!(
+        "id={}&origin={}&expires={}&signature={}",
+        new.id,
+        // origins hold no characters but these to escape
+        new.origin.replace(':', "%3A").replace('/', "%2F"),
+        expires_at,
+        signature
+    );
+    log::info!("issued embed url: id={}, origin={}", id, new.origin);
+    Ok(Json(EmbedUrl { query, expires_at }))
+}
+
+// API keys issued by admin API. keys themselves are not stored, only their hashes
+pub async fn api_keys(
+    state: Extension<Arc<State>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<IssuedApiKey>>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<Vec<IssuedApiKey>>, ApiError> {
+            match state.instance.api_keys(conn) {
+                Ok(keys) => Ok(Json(keys)),
+                Err(err) => {
+                    log::error!("failed to query API keys: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    id: i64,
+    // shown only once
+    key: String,
+}
+
+// issue API key for uploaders. once any issued key is usable, uploads need API key even if none
+// is configured
+pub async fn create_api_key(
+    state: Extension<Arc<State>>,
+    Query(new): Query<NewApiKey>,
+    headers: HeaderMap,
+) -> Result<Json<CreatedApiKey>, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let limits = [
+        new.max_size,
+        new.max_files_per_day,
+        new.min_expiry,
+        new.max_expiry,
+    ];
+    if limits.iter().flatten().any(|n| *n <= 0) {
+        log::error!("limits of API key should be positive");
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    if let (Some(min), Some(max)) = (new.min_expiry, new.max_expiry) {
+        if min > max {
+            log::error!("min_expiry should not be over max_expiry");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    }
+    if let (Some(from), Some(until)) = (new.valid_from, new.valid_until) {
+        if from >= until {
+            log::error!("valid_from should be before valid_until");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    }
+
+    let (key, key_hash) = super::random_token()?;
+
+    db::blocking(
+        state.0.clone(),
+        move |state, conn| -> Result<Json<CreatedApiKey>, ApiError> {
+            let result = state.instance.insert_api_key(conn, &new, &key_hash);
+            match result {
+                Ok(id) => {
+                    log::info!("issued API key: id={}, label={:?}", id, new.label);
+                    Ok(Json(CreatedApiKey { id, key }))
+                }
+                Err(err) => {
+                    log::error!("failed to insert API key: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+// revoked key is kept, so that files uploaded with it still refer to it
+pub async fn revoke_api_key(
+    state: Extension<Arc<State>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<&'static str, ApiError> {
+    authorize(&state.0.config, &headers)?;
+
+    let id = match params.get("id").map(|id| id.parse::<i64>()) {
+        Some(Ok(id)) if id > 0 => id,
+        _ => {
+            log::error!("id should be positive integer");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+
+    db::blocking(
+        state.0.clone(),
+        move |state, conn| -> Result<&'static str, ApiError> {
+            match state.instance.revoke_api_key(conn, id) {
+                Ok(false) => Err(StatusCode::NOT_FOUND.into()),
+                Ok(true) => {
+                    log::info!("revoked API key: id={}", id);
+                    Ok("ok")
+                }
+                Err(err) => {
+                    log::error!("failed to revoke API key: {:?}", err);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+                }
+            }
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use axum::http::HeaderMap;
+    use clap::Parser;
+
+    use super::{authorize, ip_hash};
+    use crate::{config::Config, error::ApiError};
+
+    #[test]
+    fn admin_api_without_token_is_disabled() {
+        let config = Config::parse_from(["hako"]);
+        let result = authorize(&config, &HeaderMap::new());
+        assert!(matches!(result, Err(ApiError::AdminDisabled)));
+    }
+
+    #[test]
+    fn ip_hash_depends_on_key() {
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(ip_hash(b"key", ip), ip_hash(b"key", ip));
+        assert_ne!(ip_hash(b"key", ip), ip_hash(b"other key", ip));
+        assert_eq!(ip_hash(b"key", ip).len(), 16);
+    }
+}
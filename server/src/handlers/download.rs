@@ -0,0 +1,362 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    body::{Body, Bytes, StreamBody},
+    extract::{Extension, Query},
+    http::{
+        header::{CONTENT_SECURITY_POLICY, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+};
+use futures::stream::StreamExt;
+use rusqlite::Connection;
+
+use super::{check_available_from, metadata::query_metadata, random_token, token_hash, FileId};
+use crate::events::Kind;
+use crate::repo::{Deletable, DownloadState};
+use crate::storage::{Chunks, Tx};
+use crate::{bandwidth, db, error::ApiError, request_id, state::State};
+
+// count download of file, refusing it if file was downloaded as many times as allowed. returns
+// whether this was the last download allowed, and then hides file from metadata so that nobody
+// else starts downloading it. caller should burn file after serving it.
+fn count_download(state: &State, conn: &Connection, id: i64) -> Result<bool, ApiError> {
+    let last = match state.files.count_download(conn, id) {
+        Ok(Some(true)) => {
+            if let Err(err) = state.files.set_available(conn, id, false) {
+                log::error!("failed to hide file after last download: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+            true
+        }
+        Ok(Some(false)) => false,
+        Ok(None) => {
+            log::error!("no downloads left: id={}", id);
+            return Err(StatusCode::GONE.into());
+        }
+        Err(err) => {
+            log::error!("failed to count download: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    state.events.emit(Kind::Downloaded, id, state.clock.now());
+    Ok(last)
+}
+
+// seconds in which ticket given by `open` can be used. passphrase is entered in between
+const DOWNLOAD_TICKET_TTL: i64 = 24 * 60 * 60;
+
+// ticket for rest of download counted by `open`
+fn issue_ticket(state: &State, conn: &Connection, id: i64) -> Result<String, ApiError> {
+    let now = state.clock.now();
+    if let Err(err) = state
+        .files
+        .forget_download_tickets(conn, now - DOWNLOAD_TICKET_TTL)
+    {
+        log::error!("failed to delete old download tickets: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    let (ticket, ticket_hash) = random_token()?;
+    if let Err(err) = state
+        .files
+        .issue_download_ticket(conn, id, &ticket_hash, now)
+    {
+        log::error!("failed to insert download ticket: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    Ok(ticket)
+}
+
+// delete file whose last allowed download was served
+fn burn(state: &State, conn: &mut Connection, id: i64) {
+    let result = (|| -> db::Result<_> {
+        let tx = conn.transaction()?;
+        // files on legal hold are kept, though they cannot be downloaded anymore
+        let deleted = db::delete_file(state, &tx, id, Deletable::NotHeld)?;
+        db::commit(tx)?;
+        Ok(deleted)
+    })();
+    match result {
+        Ok(0) => log::info!("kept file on legal hold after last download: id={}", id),
+        Ok(_) => {
+            state.events.emit(Kind::Deleted, id, state.clock.now());
+            log::info!("burned file after last download: id={}", id);
+        }
+        Err(err) => log::error!("failed to burn file: id={}, error={:?}", id, err),
+    }
+}
+
+pub async fn download(
+    state: Extension<Arc<State>>,
+    FileId(id): FileId,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<(HeaderMap, StreamBody<Body>), ApiError> {
+    // chunks before this were already fetched by client, e.g. from `open`
+    let from_seq = match params.get("from_seq") {
+        Some(seq) => match seq.parse::<i64>() {
+            Ok(seq) if seq > 0 => seq,
+            _ => {
+                log::error!("from_seq should be positive integer");
+                return Err(StatusCode::BAD_REQUEST.into());
+            }
+        },
+        None => 1,
+    };
+    // later chunks are served only within download counted by `open`, which gave this ticket
+    let ticket_hash = match params.get("ticket").map(|ticket| token_hash(ticket)) {
+        Some(Some(hash)) => Some(hash),
+        Some(None) => {
+            log::error!("ticket is not valid base64");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+        None if from_seq > 1 => {
+            log::error!(
+                "ticket is required for download from seq: from_seq={}",
+                from_seq
+            );
+            return Err(StatusCode::FORBIDDEN.into());
+        }
+        None => None,
+    };
+
+    // prepare sender
+    let (mut sender, body) = Body::channel();
+
+    // file is only read until download is counted, so that it does not wait for writer
+    let (file, last_seq) = db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<(DownloadState, i64), ApiError> {
+            let now = state.clock.now();
+            let file = match state.files.download_state(conn, id, now) {
+                Ok(Some(file)) if file.expired => {
+                    log::error!("file is expired: id={}", id);
+                    return Err(StatusCode::GONE.into());
+                }
+                Ok(Some(file)) => file,
+                Ok(None) => {
+                    log::error!("file not found: id={}", id);
+                    return Err(StatusCode::NOT_FOUND.into());
+                }
+                Err(err) => {
+                    log::error!("failed to query available_from: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+            // file is unavailable while it is uploaded, and also once its last download started.
+            // only the rest of that download is still served
+            if !file.available && !file.downloads_exhausted {
+                log::error!("file is still being uploaded: id={}", id);
+                return Err(ApiError::UploadInProgress);
+            }
+            check_available_from(now, file.available_from)?;
+
+            // check range of chunks up front, without reading their content
+            let result = state.storage.chunks(Tx::of(conn), id, from_seq);
+            let (first_seq, last_seq, count) = match result {
+                Ok(Chunks {
+                    count,
+                    first_seq: Some(first_seq),
+                    last_seq: Some(last_seq),
+                    ..
+                }) => (first_seq, last_seq, count),
+                Ok(_) if from_seq > 1 => {
+                    log::error!("no chunks from seq: id={}, from_seq={}", id, from_seq);
+                    return Err(StatusCode::NOT_FOUND.into());
+                }
+                Ok(_) => {
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+                Err(err) => {
+                    log::error!("failed to query: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+
+            // seq is contiguous, so any missing chunk makes count differ from seq range
+            let expected_chunks = last_seq - from_seq + 1;
+            if first_seq != from_seq || count != expected_chunks {
+                log::error!(
+                    "file incomplete: id={}, last_seq={}, chunks={}",
+                    id,
+                    last_seq,
+                    count
+                );
+                return Err(ApiError::FileIncomplete {
+                    expected_chunks,
+                    found_chunks: count,
+                });
+            }
+
+            Ok((file, last_seq))
+        },
+    )
+    .await?;
+
+    // download from first chunk counts. later chunks are served once for ticket of download
+    // counted by `open`, and file whose last download that was is burned after them.
+    let burn_after = db::blocking(state.0.clone(), move |state, conn| {
+        let ticket_hash = match ticket_hash {
+            Some(ticket_hash) if from_seq > 1 => ticket_hash,
+            _ => return count_download(state, conn, id),
+        };
+        let issued_after = state.clock.now() - DOWNLOAD_TICKET_TTL;
+        match state
+            .files
+            .take_download_ticket(conn, id, &ticket_hash, issued_after)
+        {
+            Ok(true) => Ok(file.downloads_exhausted),
+            Ok(false) => {
+                log::error!("download ticket is not valid: id={}", id);
+                Err(StatusCode::FORBIDDEN.into())
+            }
+            Err(err) => {
+                log::error!("failed to take download ticket: {:?}", err);
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into())
+            }
+        }
+    })
+    .await?;
+
+    // sender waits until client takes previous chunk, so chunks are read as client goes
+    let state = state.0.clone();
+    request_id::spawn(async move {
+        let mut chunks = state
+            .storage
+            .get_chunk_stream(state.clone(), id, from_seq..=last_seq);
+        let mut seq = from_seq;
+        let mut sent = 0;
+        let mut complete = true;
+        while let Some(content) = chunks.next().await {
+            let content = match content {
+                Ok(content) => content,
+                Err(err) => {
+                    // chunk is gone after range check, e.g. file expired meanwhile
+                    sender.abort();
+                    log::error!(
+                        "failed to read chunk: id={}, seq={}, error={:?}",
+                        id,
+                        seq,
+                        err
+                    );
+                    complete = false;
+                    break;
+                }
+            };
+            let len = content.len() as i64;
+
+            if let Err(e) = sender.send_data(Bytes::from(content)).await {
+                sender.abort();
+                log::error!(
+                    "failed to send chunk: id={}, seq={}, error={:?}",
+                    id,
+                    seq,
+                    e
+                );
+                complete = false;
+                break;
+            }
+            sent += len;
+            seq += 1;
+
+            // let other tasks run between chunks
+            tokio::task::yield_now().await;
+        }
+
+        // sent bytes are recorded once per download rather than per chunk, so that streaming
+        // does not take writer between chunks
+        db::blocking(state.clone(), move |state, conn| {
+            if sent > 0 {
+                bandwidth::record(&*state.instance, conn, id, state.clock.now(), 0, sent);
+            }
+            if complete && burn_after {
+                burn(state, conn, id);
+            }
+        })
+        .await;
+    });
+
+    // served bytes are ciphertext, which browser should never try to interpret or render in this
+    // origin
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+
+    Ok((headers, StreamBody::new(body)))
+}
+
+// metadata and first ciphertext chunk in one response, so that client can check passphrase against
+// first chunk without waiting for another round trip. body is 4-byte big-endian length of metadata
+// JSON, the JSON itself, and then first chunk as is.
+pub async fn open(
+    state: Extension<Arc<State>>,
+    FileId(id): FileId,
+) -> Result<(HeaderMap, Vec<u8>), ApiError> {
+    // first chunk may be as large as a block, so read it off runtime threads
+    let result = db::blocking(
+        state.0.clone(),
+        move |state, conn| -> Result<(Vec<u8>, Vec<u8>), ApiError> {
+            let mut metadata = query_metadata(state, conn, id, state.clock.now())?;
+            // `metadata` tells clients whether file is still being uploaded
+            if !metadata.upload_complete {
+                log::error!("file is still being uploaded: id={}", id);
+                return Err(ApiError::UploadInProgress);
+            }
+            let size = metadata.metadata.size;
+
+            let first_chunk = match state.storage.get_chunk(Tx::of(conn), id, 1) {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => {
+                    log::error!("first chunk not found: id={}", id);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+                Err(err) => {
+                    log::error!("failed to query: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+
+            bandwidth::record(
+                &*state.instance,
+                conn,
+                id,
+                state.clock.now(),
+                0,
+                first_chunk.len() as i64,
+            );
+
+            // serving first chunk counts as download. rest of file, if any, is left for
+            // `download` to serve to holder of ticket given here, and to burn
+            let last = count_download(state, conn, id)?;
+            if size > first_chunk.len() as i64 {
+                metadata.metadata.download_ticket = Some(issue_ticket(state, conn, id)?);
+            } else if last {
+                burn(state, conn, id);
+            }
+            let metadata =
+                serde_json::to_vec(&metadata).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            Ok((metadata, first_chunk))
+        },
+    )
+    .await;
+    let (metadata, first_chunk) = result?;
+
+    let mut body = Vec::with_capacity(4 + metadata.len() + first_chunk.len());
+    body.extend(&(metadata.len() as u32).to_be_bytes());
+    body.extend(metadata);
+    body.extend(first_chunk);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/octet-stream"),
+    );
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+
+    Ok((headers, body))
+}
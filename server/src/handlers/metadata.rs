@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Extension,
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+};
+use futures::{
+    future,
+    stream::{self, Stream},
+};
+use hako_core::Metadata;
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::{broadcast, watch};
+
+use super::{
+    check_available_from, etag, etag_matches, max_file_size, FileId, BLOCK_SIZE, FORMAT_VERSIONS,
+    PROTOCOL_VERSION,
+};
+use crate::api_key;
+use crate::events::Kind;
+use crate::storage::Tx;
+use crate::{db, error::ApiError, progress, request_id::REQUEST_ID, state::State};
+
+// advertise versions in headers, so that clients can check compatibility while the body stays
+// as it is for existing health checks
+pub async fn ping() -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-hako-server-version",
+        HeaderValue::from_static(env!("CARGO_PKG_VERSION")),
+    );
+    headers.insert(
+        "x-hako-protocol-version",
+        HeaderValue::from(PROTOCOL_VERSION),
+    );
+    headers.insert(
+        "x-hako-format-versions",
+        HeaderValue::from_static(FORMAT_VERSIONS),
+    );
+
+    (headers, "pong")
+}
+
+#[derive(Serialize)]
+pub struct AppConfigResp {
+    block_size: u64,
+    chunk_count_limit: u64,
+    max_file_size: u64,
+    forbidden_extensions: Vec<String>,
+    text_uploads_per_ip_per_day: Option<u64>,
+    api_key_required: bool,
+    // bounds of expiry clients may request in prepare_upload, if they can
+    min_expiry: Option<u64>,
+    max_expiry: Option<u64>,
+    // whether webapp may offer users to report their errors
+    client_error_reports: bool,
+}
+
+// limits of this server, so that clients can refuse files which will not fit before uploading
+pub async fn app_config(state: Extension<Arc<State>>) -> impl IntoResponse {
+    let config = &state.0.config;
+    let required = db::read(state.0.clone(), |state, conn| {
+        api_key::required(state, conn, state.clock.now())
+    })
+    .await;
+    let api_key_required = match required {
+        Ok(required) => required,
+        Err(err) => {
+            log::error!("failed to query API keys: {:?}", err);
+            true
+        }
+    };
+
+    Json(AppConfigResp {
+        block_size: BLOCK_SIZE,
+        chunk_count_limit: config.chunk_count_limit,
+        max_file_size: max_file_size(config),
+        forbidden_extensions: config
+            .forbidden_extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect(),
+        text_uploads_per_ip_per_day: config.text_uploads_per_ip_per_day,
+        api_key_required,
+        min_expiry: config.max_expiry.and(config.min_expiry),
+        max_expiry: config.max_expiry,
+        client_error_reports: config.client_error_reports,
+    })
+}
+
+// server-sent events of upload progress, so that recipients can wait for file which is still
+// being uploaded. current progress is sent first, and stream ends when upload completes, when file
+// is deleted or expires, and when server shuts down, after which client reconnects to another.
+pub async fn events(
+    state: Extension<Arc<State>>,
+    FileId(id): FileId,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    // subscribe before reading current progress, so that no event falls between them
+    let receiver = state.0.progress.subscribe();
+    let lifecycle = state.0.events.subscribe();
+    let shutdown = state.0.shutdown.clone();
+    let current = db::read(state.0.clone(), move |state, conn| {
+        progress::query(state, conn, id, state.clock.now())
+    })
+    .await;
+    let current = match current {
+        Ok(Some(current)) => current,
+        Ok(None) => return Err(StatusCode::NOT_FOUND.into()),
+        Err(err) => {
+            log::error!("failed to query upload progress: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+
+    let events = stream::unfold(
+        (Some(current), receiver, lifecycle, shutdown, false),
+        move |(next, mut receiver, mut lifecycle, mut shutdown, done)| async move {
+            if done {
+                return None;
+            }
+            let progress = match next {
+                Some(progress) => progress,
+                None => loop {
+                    tokio::select! {
+                        received = receiver.recv() => match received {
+                            Ok(progress) if progress.id == id => break progress,
+                            Ok(_) => continue,
+                            // next event tells latest progress anyway
+                            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => return None,
+                        },
+                        received = lifecycle.recv() => match received {
+                            Ok(event)
+                                if event.id == id
+                                    && matches!(event.event, Kind::Deleted | Kind::Expired) =>
+                            {
+                                let event = Event::default().event("gone").data("");
+                                let state = (None, receiver, lifecycle, shutdown, true);
+                                return Some((Ok(event), state));
+                            }
+                            // missed deletion leaves stream open until shutdown at worst
+                            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => return None,
+                        },
+                        _ = shutting_down(&mut shutdown) => return None,
+                    }
+                },
+            };
+
+            let done = progress.complete;
+            let name = if done { "complete" } else { "progress" };
+            let event = Event::default().event(name).json_data(&progress);
+            Some((event, (None, receiver, lifecycle, shutdown, done)))
+        },
+    );
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// resolves once server starts shutting down. never does if nothing can shut it down anymore
+async fn shutting_down(shutdown: &mut watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            future::pending::<()>().await;
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MetadataResp {
+    #[serde(flatten)]
+    pub(super) metadata: Metadata,
+    // false while file is still being uploaded. such files get upload progress instead
+    #[serde(skip)]
+    pub(super) upload_complete: bool,
+}
+
+pub async fn metadata(
+    state: Extension<Arc<State>>,
+    FileId(id): FileId,
+    req_headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let (metadata, progress) =
+        db::read(state.0.clone(), move |state, conn| -> Result<_, ApiError> {
+            let now = state.clock.now();
+            let metadata = query_metadata(state, conn, id, now)?;
+            let progress = if metadata.upload_complete {
+                None
+            } else {
+                match progress::query(state, conn, id, now) {
+                    Ok(progress) => progress,
+                    Err(err) => {
+                        log::error!("failed to query upload progress: {:?}", err);
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                    }
+                }
+            };
+            Ok((metadata, progress))
+        })
+        .await?;
+
+    // file exists but is still being uploaded, so tell clients how far it is instead of metadata
+    // which is not final yet
+    if let Some(progress) = progress {
+        let request_id = REQUEST_ID.try_with(|id| id.clone()).ok();
+        let body = json!({
+            "code": "upload_in_progress",
+            "received_chunks": progress.received_chunks,
+            "received_bytes": progress.received_bytes,
+            "expected_chunks": progress.expected_chunks,
+            "expected_size": progress.expected_size,
+            "request_id": request_id,
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        return Ok((StatusCode::ACCEPTED, headers, Json(body)).into_response());
+    }
+
+    let body = serde_json::to_vec(&metadata).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // metadata changes whenever any column of the row changes, so its hash works as row version
+    let etag = etag(&body);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    if let Ok(v) = HeaderValue::from_str(&etag) {
+        headers.insert(ETAG, v);
+    }
+
+    if etag_matches(&req_headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok((headers, body).into_response())
+}
+
+// metadata of available file. embargoed files are refused here, so callers never leak them. files
+// which expired or were downloaded as many times as allowed are gone, if their row is still there
+pub fn query_metadata(
+    state: &State,
+    conn: &Connection,
+    id: i64,
+    now: i64,
+) -> Result<MetadataResp, ApiError> {
+    let file = match state.files.get(conn, id, now) {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            log::error!("metadata not found: id={}", id);
+            return Err(StatusCode::NOT_FOUND.into());
+        }
+        Err(err) => {
+            log::error!("failed to query: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    // expired files are gone even before expiry worker hides them. other unavailable files are
+    // still being uploaded, unless they are downloaded as many times as allowed
+    if file.expired || (!file.available && file.finished) {
+        log::error!("file is gone: id={}", id);
+        return Err(StatusCode::GONE.into());
+    }
+    check_available_from(now, file.available_from)?;
+
+    let size = match state.storage.chunks(Tx::of(conn), id, 1) {
+        Ok(chunks) => chunks.bytes,
+        Err(err) => {
+            log::error!("failed to count chunks: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+
+    Ok(MetadataResp {
+        metadata: Metadata {
+            filename: file.filename,
+            salt: file.salt,
+            nonce: file.nonce,
+            filename_nonce: file.filename_nonce,
+            is_text: file.is_text,
+            size,
+            expires_at: file.expires_at,
+            format_version: file.format_version,
+            mirrors: state.config.download_mirrors.clone(),
+            downloads_left: file.downloads_left,
+            download_ticket: None,
+        },
+        upload_complete: file.available,
+    })
+}
@@ -0,0 +1,973 @@
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequest, Query, RequestParts},
+    http::{
+        header::{AUTHORIZATION, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+};
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, db, error::ApiError, share_id, state::State};
+
+pub mod admin;
+pub mod download;
+pub mod metadata;
+pub mod static_assets;
+pub mod upload;
+
+// version of HTTP API. bump this on incompatible changes to request or response layout
+pub const PROTOCOL_VERSION: u32 = 1;
+// encryption format versions which can be stored in `files.format_version`
+pub const FORMAT_VERSIONS: &str = "1";
+
+// random token returned to client in base64, and its hash to store
+pub fn random_token() -> Result<(String, Vec<u8>), StatusCode> {
+    let mut token = [0u8; 32];
+    if let Err(err) = getrandom::getrandom(&mut token) {
+        log::error!("cannot get random token: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let hash = Sha256::digest(&token).to_vec();
+    Ok((base64::encode_config(token, base64::URL_SAFE_NO_PAD), hash))
+}
+
+// hash of token given by `random_token`, to compare with stored one
+pub fn token_hash(token: &str) -> Option<Vec<u8>> {
+    base64::decode_config(token, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .map(|token| Sha256::digest(&token).to_vec())
+}
+
+// hash of token given in `authorization: bearer` header
+pub fn bearer_token_hash(headers: &HeaderMap) -> Option<Vec<u8>> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(token_hash)
+}
+
+// strong etag from hash of body
+pub fn etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+// whether `if-none-match` request header lists given etag
+pub fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .any(|v| v == "*" || v.trim_start_matches("W/") == etag)
+}
+
+// size of plaintext block encrypted into each chunk by webapp. keep in sync with webapp's utils.rs
+pub const BLOCK_SIZE: u64 = 10 * 1024 * 1024;
+// authentication tag added to each encrypted block
+pub const BLOCK_OVERHEAD: u64 = 16;
+
+// largest plaintext which fits in chunk count limit, and in size limit of server if any. every
+// chunk but the last one holds full block, and the last one holds the remainder which is shorter
+// than a block.
+pub fn max_file_size(config: &Config) -> u64 {
+    let max = (config.chunk_count_limit * BLOCK_SIZE).saturating_sub(1);
+    match config.max_file_size {
+        // size limit counts ciphertext, which has overhead in every block
+        Some(limit) => {
+            let blocks = limit / (BLOCK_SIZE + BLOCK_OVERHEAD) + 1;
+            max.min(limit.saturating_sub(blocks * BLOCK_OVERHEAD))
+        }
+        None => max,
+    }
+}
+
+// file id from `id` query parameter of download endpoints, given as it appears in share url
+pub struct FileId(pub i64);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for FileId {
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(state) = Extension::<Arc<State>>::from_request(req)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let Query(params) = Query::<HashMap<String, String>>::from_request(req)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        let id = match params.get("id") {
+            Some(id) => id,
+            None => {
+                log::error!("requires id");
+                return Err(StatusCode::BAD_REQUEST.into());
+            }
+        };
+
+        let share_id = id.clone();
+        db::read(state, move |state, conn| {
+            share_id::resolve(state, conn, &share_id)
+        })
+        .await
+        .map(FileId)
+        .map_err(|status| {
+            log::error!("invalid id: {}", id);
+            status.into()
+        })
+    }
+}
+
+// hash of token given in `authorization: bearer` header, or none if there is no such header
+pub struct BearerTokenHash(pub Option<Vec<u8>>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for BearerTokenHash {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(BearerTokenHash(bearer_token_hash(req.headers())))
+    }
+}
+
+// refuse files which are embargoed until later
+pub fn check_available_from(now: i64, available_from: Option<i64>) -> Result<(), ApiError> {
+    match available_from {
+        Some(available_from) if available_from > now => {
+            Err(ApiError::NotYetAvailable { available_from })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{
+            header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+            Method, Request, StatusCode,
+        },
+    };
+    use clap::Parser;
+    use rusqlite::Connection;
+    use sha2::{Digest, Sha256};
+    use tokio::sync::watch;
+    use tower::ServiceExt;
+
+    use super::{BLOCK_OVERHEAD, BLOCK_SIZE};
+    use crate::{
+        config::Config,
+        db::Writer,
+        events::Kind,
+        migrations,
+        rate_limit::RateLimits,
+        repo::{SqliteFileRepo, SqliteInstanceRepo},
+        state::State,
+        storage::SqliteStorage,
+        utils::Clock,
+    };
+
+    const BOUNDARY: &str = "hako-test-boundary";
+    const UPLOAD_TOKEN: &[u8] = b"upload token";
+
+    fn test_state() -> Arc<State> {
+        test_state_with_shutdown().0
+    }
+
+    // state whose shutdown is sent with returned sender
+    fn test_state_with_shutdown() -> (Arc<State>, watch::Sender<bool>) {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../../schema.sql"))
+            .unwrap();
+        migrations::run(&mut conn).unwrap();
+
+        let config = Config::parse_from(["hako"]);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let state = Arc::new(State {
+            conn: Writer::new(conn),
+            readers: Default::default(),
+            storage: Box::new(SqliteStorage),
+            files: Box::new(SqliteFileRepo),
+            instance: Box::new(SqliteInstanceRepo),
+            rate_limits: RateLimits::new(&config),
+            progress: Default::default(),
+            events: Default::default(),
+            config,
+            clock: Clock::start(),
+            ip_hash_key: b"ip hash key".to_vec(),
+            text_uploads: Default::default(),
+            expiry_last_run: Default::default(),
+            shutdown,
+        });
+        (state, shutdown_tx)
+    }
+
+    fn multipart_body(fields: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend_from_slice(b"--");
+            body.extend_from_slice(BOUNDARY.as_bytes());
+            body.extend_from_slice(b"\r\nContent-Disposition: form-data; name=\"");
+            body.extend_from_slice(name);
+            body.extend_from_slice(b"\"\r\n\r\n");
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--");
+        body.extend_from_slice(BOUNDARY.as_bytes());
+        body.extend_from_slice(b"--\r\n");
+        body
+    }
+
+    // state with file 1 prepared, with hash of given upload token if any
+    async fn prepared_state(upload_token: Option<&[u8]>) -> Arc<State> {
+        let state = test_state();
+        let upload_token_hash = upload_token.map(|token| Sha256::digest(token).to_vec());
+        state
+            .conn
+            .lock()
+            .await
+            .execute(
+                "insert into files (id, filename, salt, nonce, filename_nonce, upload_token_hash) values (1, x'', zeroblob(32), x'', x'', ?1)",
+                rusqlite::params![&upload_token_hash],
+            )
+            .unwrap();
+        state
+    }
+
+    async fn upload_status(fields: &[(&[u8], &[u8])]) -> StatusCode {
+        upload_status_with(test_state(), None, fields).await
+    }
+
+    async fn upload_status_with(
+        state: Arc<State>,
+        upload_token: Option<&[u8]>,
+        fields: &[(&[u8], &[u8])],
+    ) -> StatusCode {
+        let body = multipart_body(fields);
+        let mut req = Request::post("/api/upload")
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .header(CONTENT_LENGTH, body.len());
+        if let Some(token) = upload_token {
+            let token = base64::encode_config(token, base64::URL_SAFE_NO_PAD);
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = req.body(Body::from(body)).unwrap();
+
+        crate::app(state).oneshot(req).await.unwrap().status()
+    }
+
+    async fn first_chunk_status(state: Arc<State>, upload_token: Option<&[u8]>) -> StatusCode {
+        upload_status_with(
+            state,
+            upload_token,
+            &[
+                (b"id", &1_i64.to_be_bytes()),
+                (b"seq", &1_i64.to_be_bytes()),
+                (b"is_last", &[1]),
+                (b"content", b"ciphertext"),
+            ],
+        )
+        .await
+    }
+
+    async fn chunk_status(id: &[u8], seq: &[u8]) -> StatusCode {
+        upload_status(&[
+            (b"id", id),
+            (b"seq", seq),
+            (b"is_last", &[1]),
+            (b"content", b"ciphertext"),
+        ])
+        .await
+    }
+
+    #[tokio::test]
+    async fn valid_chunk_is_accepted() {
+        let state = prepared_state(Some(UPLOAD_TOKEN)).await;
+        let status = first_chunk_status(state, Some(UPLOAD_TOKEN)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn chunk_without_upload_token_is_unauthorized() {
+        let state = prepared_state(Some(UPLOAD_TOKEN)).await;
+        let status = first_chunk_status(state, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn chunk_with_wrong_upload_token_is_forbidden() {
+        let state = prepared_state(Some(UPLOAD_TOKEN)).await;
+        let status = first_chunk_status(state, Some(&b"wrong"[..])).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn chunk_of_unknown_file_is_not_found() {
+        let status = first_chunk_status(test_state(), Some(UPLOAD_TOKEN)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn chunk_larger_than_block_is_rejected() {
+        let state = prepared_state(None).await;
+        let content = vec![0; (BLOCK_SIZE + BLOCK_OVERHEAD) as usize + 1];
+        let status = upload_status_with(
+            state,
+            None,
+            &[
+                (b"id", &1_i64.to_be_bytes()),
+                (b"seq", &1_i64.to_be_bytes()),
+                (b"is_last", &[1]),
+                (b"content", &content),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn out_of_order_chunks_are_published_when_complete() {
+        let state = prepared_state(None).await;
+        let available = |state: Arc<State>| async move {
+            let conn = state.conn.lock().await;
+            conn.query_row("select available from files where id = 1", [], |row| {
+                row.get::<_, bool>(0)
+            })
+            .unwrap()
+        };
+
+        // last chunk ahead of others is stored, and client is told which are missing
+        for (seq, is_last, expected) in
+            [(3_i64, 1_u8, StatusCode::ACCEPTED), (1, 0, StatusCode::OK)]
+        {
+            let status = upload_status_with(
+                state.clone(),
+                None,
+                &[
+                    (b"id", &1_i64.to_be_bytes()),
+                    (b"seq", &seq.to_be_bytes()),
+                    (b"is_last", &[is_last]),
+                    (b"content", b"ciphertext"),
+                ],
+            )
+            .await;
+            assert_eq!(status, expected);
+            assert!(!available(state.clone()).await);
+        }
+
+        let status = upload_status_with(
+            state.clone(),
+            None,
+            &[
+                (b"id", &1_i64.to_be_bytes()),
+                (b"seq", &2_i64.to_be_bytes()),
+                (b"is_last", &[0]),
+                (b"content", b"ciphertext"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(available(state).await);
+    }
+
+    #[tokio::test]
+    async fn retried_chunk_is_stored_once() {
+        let state = prepared_state(None).await;
+        let chunk = |content: &'static [u8]| {
+            let state = state.clone();
+            async move {
+                upload_status_with(
+                    state,
+                    None,
+                    &[
+                        (b"id", &1_i64.to_be_bytes()),
+                        (b"seq", &1_i64.to_be_bytes()),
+                        (b"is_last", &[1]),
+                        (b"content", content),
+                    ],
+                )
+                .await
+            }
+        };
+
+        assert_eq!(chunk(b"ciphertext").await, StatusCode::OK);
+        assert_eq!(chunk(b"ciphertext").await, StatusCode::OK);
+        let count: i64 = state
+            .conn
+            .lock()
+            .await
+            .query_row(
+                "select count(*) from file_contents where file_id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+
+        // published file keeps its chunks
+        assert_eq!(chunk(b"other ciphertext").await, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn chunks_over_declared_size_are_rejected() {
+        let state = prepared_state(None).await;
+        state
+            .conn
+            .lock()
+            .await
+            .execute(
+                "update files set expected_chunks = 2, expected_size = 12 where id = 1",
+                [],
+            )
+            .unwrap();
+
+        for (seq, is_last, expected) in [
+            (1_i64, 0_u8, StatusCode::OK),
+            (2, 1, StatusCode::PAYLOAD_TOO_LARGE),
+        ] {
+            let status = upload_status_with(
+                state.clone(),
+                None,
+                &[
+                    (b"id", &1_i64.to_be_bytes()),
+                    (b"seq", &seq.to_be_bytes()),
+                    (b"is_last", &[is_last]),
+                    (b"content", b"ciphertext"),
+                ],
+            )
+            .await;
+            assert_eq!(status, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn small_upload_is_published_at_once() {
+        let state = test_state();
+        // retry with same idempotency key is answered without storing chunk again
+        for _ in 0..2 {
+            let body = multipart_body(&[
+                (b"salt", &[0; 32]),
+                (b"nonce", &[0; 24]),
+                (b"is_text", &[1]),
+                (b"idempotency_key", b"key"),
+                (b"content", b"ciphertext"),
+                (b"digest", Sha256::digest(b"ciphertext").as_slice()),
+            ]);
+            let req = Request::post("/api/upload_small")
+                .header(
+                    CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .header(CONTENT_LENGTH, body.len())
+                .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 12345))))
+                .body(Body::from(body))
+                .unwrap();
+
+            let status = crate::app(state.clone())
+                .oneshot(req)
+                .await
+                .unwrap()
+                .status();
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let conn = state.conn.lock().await;
+        let (count, available, content) = conn
+            .query_row(
+                "select count(*), available, content from files join file_contents on file_id = files.id",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, bool>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(available);
+        assert_eq!(content, b"ciphertext");
+    }
+
+    #[tokio::test]
+    async fn replayed_idempotency_key_returns_original_response() {
+        let state = test_state();
+        let prepare = || {
+            let state = state.clone();
+            async move {
+                let body = multipart_body(&[
+                    (b"salt", &[0; 32]),
+                    (b"nonce", &[0; 24]),
+                    (b"is_text", &[1]),
+                    (b"idempotency_key", b"key"),
+                ]);
+                let req = Request::post("/api/prepare_upload")
+                    .header(
+                        CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={}", BOUNDARY),
+                    )
+                    .header(CONTENT_LENGTH, body.len())
+                    .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 12345))))
+                    .body(Body::from(body))
+                    .unwrap();
+                let resp = crate::app(state).oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, prepared) = prepare().await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, replayed) = prepare().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replayed, prepared);
+
+        // no second file is prepared, and replayed token is valid for the first one
+        let encoded = replayed["upload_token"].as_str().unwrap();
+        let token = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).unwrap();
+        let conn = state.conn.lock().await;
+        let count: i64 = conn
+            .query_row("select count(*) from files", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        let stored: Vec<u8> = conn
+            .query_row("select upload_token_hash from files", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, Sha256::digest(&token).to_vec());
+
+        // tokens are not stored in the clear next to key
+        let response: Vec<u8> = conn
+            .query_row("select response from upload_idempotency_keys", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        let encoded = encoded.as_bytes();
+        assert!(!response
+            .windows(encoded.len())
+            .any(|window| window == encoded));
+    }
+
+    #[tokio::test]
+    async fn expired_file_is_gone() {
+        let state = prepared_state(None).await;
+        state
+            .conn
+            .lock()
+            .await
+            .execute(
+                "update files set available = false, expires_at = 1 where id = 1",
+                [],
+            )
+            .unwrap();
+
+        for uri in ["/api/metadata?id=1", "/api/download?id=1"] {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let status = crate::app(state.clone())
+                .oneshot(req)
+                .await
+                .unwrap()
+                .status();
+            assert_eq!(status, StatusCode::GONE, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn download_during_upload_is_refused_and_not_counted() {
+        let state = prepared_state(Some(UPLOAD_TOKEN)).await;
+        let status = upload_status_with(
+            state.clone(),
+            Some(UPLOAD_TOKEN),
+            &[
+                (b"id", &1_i64.to_be_bytes()),
+                (b"seq", &1_i64.to_be_bytes()),
+                (b"is_last", &[0]),
+                (b"content", b"ciphertext"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        for uri in ["/api/download?id=1", "/api/open?id=1"] {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let status = crate::app(state.clone())
+                .oneshot(req)
+                .await
+                .unwrap()
+                .status();
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+        }
+
+        let download_count: i64 = state
+            .conn
+            .lock()
+            .await
+            .query_row("select download_count from files where id = 1", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(download_count, 0);
+    }
+
+    #[tokio::test]
+    async fn available_file_past_expiry_is_gone_unless_held() {
+        let state = prepared_state(None).await;
+        state
+            .conn
+            .lock()
+            .await
+            .execute(
+                "update files set available = true, expires_at = 1 where id = 1",
+                [],
+            )
+            .unwrap();
+
+        // expiry worker has not run, so row and chunks are still there
+        for (hold, expected) in [(false, StatusCode::GONE), (true, StatusCode::OK)] {
+            state
+                .conn
+                .lock()
+                .await
+                .execute(
+                    "update files set legal_hold = ?1 where id = 1",
+                    rusqlite::params![&hold],
+                )
+                .unwrap();
+            let req = Request::get("/api/metadata?id=1")
+                .body(Body::empty())
+                .unwrap();
+            let status = crate::app(state.clone())
+                .oneshot(req)
+                .await
+                .unwrap()
+                .status();
+            assert_eq!(status, expected, "legal_hold={}", hold);
+        }
+
+        state
+            .conn
+            .lock()
+            .await
+            .execute("update files set legal_hold = false where id = 1", [])
+            .unwrap();
+        for uri in ["/api/download?id=1", "/api/verify?id=1"] {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let status = crate::app(state.clone())
+                .oneshot(req)
+                .await
+                .unwrap()
+                .status();
+            assert_eq!(status, StatusCode::GONE, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn rest_of_file_is_served_once_per_counted_download() {
+        for hold in [false, true] {
+            let state = prepared_state(None).await;
+            for (seq, is_last, content) in [(1_i64, 0_u8, b"first"), (2, 1, b"other")] {
+                let status = upload_status_with(
+                    state.clone(),
+                    None,
+                    &[
+                        (b"id", &1_i64.to_be_bytes()),
+                        (b"seq", &seq.to_be_bytes()),
+                        (b"is_last", &[is_last]),
+                        (b"content", content),
+                    ],
+                )
+                .await;
+                assert_eq!(status, StatusCode::OK);
+            }
+            state
+                .conn
+                .lock()
+                .await
+                .execute(
+                    "update files set max_downloads = 2, legal_hold = ?1 where id = 1",
+                    rusqlite::params![&hold],
+                )
+                .unwrap();
+
+            let get = |uri: String| {
+                let state = state.clone();
+                async move {
+                    let req = Request::get(uri).body(Body::empty()).unwrap();
+                    let resp = crate::app(state).oneshot(req).await.unwrap();
+                    let status = resp.status();
+                    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                    (status, body.to_vec())
+                }
+            };
+            let open = || async {
+                let (status, body) = get("/api/open?id=1".to_owned()).await;
+                assert_eq!(status, StatusCode::OK);
+                let len = u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize;
+                let metadata: serde_json::Value =
+                    serde_json::from_slice(&body[4..4 + len]).unwrap();
+                assert_eq!(&body[4 + len..], b"first");
+                metadata["download_ticket"].as_str().unwrap().to_owned()
+            };
+            let rest =
+                |ticket: &str| get(format!("/api/download?id=1&from_seq=2&ticket={}", ticket));
+
+            // rest of file is not served outside of counted download
+            let (status, _) = get("/api/download?id=1&from_seq=2".to_owned()).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+
+            for _ in 0..2 {
+                let ticket = open().await;
+                assert_eq!(rest(&ticket).await, (StatusCode::OK, b"other".to_vec()));
+                let (status, _) = rest(&ticket).await;
+                assert_eq!(status, StatusCode::FORBIDDEN);
+            }
+
+            // burning after last download runs once its chunks are sent
+            let mut files = 1;
+            for _ in 0..100 {
+                files = state
+                    .conn
+                    .lock()
+                    .await
+                    .query_row("select count(*) from files", [], |row| row.get::<_, i64>(0))
+                    .unwrap();
+                if files == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            assert_eq!(files, if hold { 1 } else { 0 }, "legal_hold={}", hold);
+
+            // held file is kept, but neither its first chunk nor the rest is served anymore
+            let (status, _) = get("/api/open?id=1".to_owned()).await;
+            assert_ne!(status, StatusCode::OK, "legal_hold={}", hold);
+            let (status, _) = get("/api/download?id=1".to_owned()).await;
+            assert_ne!(status, StatusCode::OK, "legal_hold={}", hold);
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_events_end_when_file_is_deleted_or_server_shuts_down() {
+        let (state, shutdown) = test_state_with_shutdown();
+        state
+            .conn
+            .lock()
+            .await
+            .execute(
+                "insert into files (id, filename, salt, nonce, filename_nonce) values (1, x'', zeroblob(32), x'', x'')",
+                [],
+            )
+            .unwrap();
+        let watch = || async {
+            let req = Request::get("/api/events?id=1")
+                .body(Body::empty())
+                .unwrap();
+            let resp = crate::app(state.clone()).oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            resp.into_body()
+        };
+        let rest = |body: Body| async {
+            let body = tokio::time::timeout(Duration::from_secs(5), hyper::body::to_bytes(body))
+                .await
+                .expect("stream did not end")
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        // deletion of other file does not end stream
+        let body = watch().await;
+        state.events.emit(Kind::Deleted, 2, 0);
+        state.events.emit(Kind::Deleted, 1, 0);
+        let body = rest(body).await;
+        assert!(body.starts_with("event: progress\n"), "{}", body);
+        assert!(body.contains("event: gone\n"), "{}", body);
+
+        let body = watch().await;
+        shutdown.send(true).unwrap();
+        let body = rest(body).await;
+        assert!(!body.contains("event: gone\n"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn file_with_slug_is_not_found_by_id() {
+        let state = prepared_state(None).await;
+        state
+            .conn
+            .lock()
+            .await
+            .execute("update files set slug = 'aB3dE9xYz1' where id = 1", [])
+            .unwrap();
+
+        for (uri, found) in [
+            ("/api/metadata?id=aB3dE9xYz1", true),
+            ("/api/metadata?id=1", false),
+        ] {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let status = crate::app(state.clone())
+                .oneshot(req)
+                .await
+                .unwrap()
+                .status();
+            assert_eq!(status != StatusCode::NOT_FOUND, found, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn plain_failure_has_code_in_json() {
+        let req = Request::get("/api/metadata?id=1")
+            .body(Body::empty())
+            .unwrap();
+        let resp = crate::app(test_state()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["message"], "file does not exist");
+        assert!(body["detail"].is_null());
+    }
+
+    #[tokio::test]
+    async fn failure_without_body_gets_code_and_request_id() {
+        let req = Request::post("/api/admin/stats")
+            .header("x-request-id", "ref-1")
+            .body(Body::empty())
+            .unwrap();
+        let resp = crate::app(test_state()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "method_not_allowed");
+        assert_eq!(body["message"], "method is not allowed here");
+        assert_eq!(body["request_id"], "ref-1");
+    }
+
+    #[tokio::test]
+    async fn issued_api_key_is_limited_to_files_per_day() {
+        let state = test_state();
+        state
+            .conn
+            .lock()
+            .await
+            .execute(
+                "insert into api_keys (key_hash, max_files_per_day) values (?1, 1)",
+                rusqlite::params![&Sha256::digest(b"api key").to_vec()],
+            )
+            .unwrap();
+
+        let small_upload_status = |api_key: Option<&'static [u8]>| {
+            let state = state.clone();
+            async move {
+                let body = multipart_body(&[
+                    (b"salt", &[0; 32]),
+                    (b"nonce", &[0; 24]),
+                    (b"is_text", &[1]),
+                    (b"content", b"ciphertext"),
+                ]);
+                let mut req = Request::post("/api/upload_small")
+                    .header(
+                        CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={}", BOUNDARY),
+                    )
+                    .header(CONTENT_LENGTH, body.len())
+                    .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 12345))));
+                if let Some(key) = api_key {
+                    let key = base64::encode_config(key, base64::URL_SAFE_NO_PAD);
+                    req = req.header(AUTHORIZATION, format!("Bearer {}", key));
+                }
+                let req = req.body(Body::from(body)).unwrap();
+                crate::app(state).oneshot(req).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(small_upload_status(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(small_upload_status(Some(b"api key")).await, StatusCode::OK);
+        assert_eq!(
+            small_upload_status(Some(b"api key")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn routes_in_openapi_spec_are_served() {
+        let spec = crate::openapi::spec();
+        for (path, operations) in spec["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+                // requests lack id or body, which only handlers of routes refuse
+                let req = Request::builder()
+                    .method(method.clone())
+                    .uri(path.as_str())
+                    .body(Body::empty())
+                    .unwrap();
+                let status = crate::app(test_state())
+                    .oneshot(req)
+                    .await
+                    .unwrap()
+                    .status();
+                assert_ne!(status, StatusCode::NOT_FOUND, "{} {}", method, path);
+                assert_ne!(
+                    status,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {}",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn short_id_is_bad_request() {
+        let status = chunk_status(&[0, 0, 0, 0, 0, 0, 1], &1_i64.to_be_bytes()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn long_id_is_bad_request() {
+        let status = chunk_status(&[0, 0, 0, 0, 0, 0, 0, 0, 1], &1_i64.to_be_bytes()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn zero_seq_is_bad_request() {
+        let status = chunk_status(&1_i64.to_be_bytes(), &0_i64.to_be_bytes()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn negative_seq_is_bad_request() {
+        let status = chunk_status(&1_i64.to_be_bytes(), &(-1_i64).to_be_bytes()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn non_utf8_field_name_is_bad_request() {
+        let status = upload_status(&[
+            (b"\xff\xfe", b"garbage"),
+            (b"id", &1_i64.to_be_bytes()),
+            (b"seq", &1_i64.to_be_bytes()),
+            (b"is_last", &[1]),
+            (b"content", b"ciphertext"),
+        ])
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+use axum::{
+    extract::Extension,
+    http::{
+        header::{ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, VARY},
+        HeaderMap, HeaderValue, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+};
+use include_dir::{include_dir, Dir, DirEntry};
+use mime_guess::mime;
+
+use super::{etag, etag_matches};
+use crate::{error_page, share_id, state::State};
+
+static STATIC_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../webapp/dist");
+const INDEX_FILENAME: &str = "index.html";
+
+// etags of embedded files by path, computed once as they never change while running
+static ETAGS: OnceLock<HashMap<&'static str, String>> = OnceLock::new();
+
+// precompressed variants put next to assets by post_build.sh, in order of preference
+const ENCODINGS: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+// hash embedded files ahead, so that first requests do not pay for it
+pub fn hash_assets() {
+    let count = etags().len();
+    log::info!("hashed {} static files", count);
+}
+
+fn etags() -> &'static HashMap<&'static str, String> {
+    ETAGS.get_or_init(|| {
+        let mut etags = HashMap::new();
+        let mut dirs = vec![&STATIC_DIR];
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.entries() {
+                match entry {
+                    DirEntry::Dir(dir) => dirs.push(dir),
+                    DirEntry::File(file) => {
+                        if let Some(path) = file.path().to_str() {
+                            etags.insert(path, etag(file.contents()));
+                        }
+                    }
+                }
+            }
+        }
+        etags
+    })
+}
+
+// trunk names assets as `<name>-<16 hex digits>.<ext>`, and wasm as `<name>-<hash>_bg.wasm`.
+// such names change whenever content changes, so they can be cached forever
+fn is_hashed(filename: &str) -> bool {
+    let stem = match filename.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => return false,
+    };
+    let stem = stem.strip_suffix("_bg").unwrap_or(stem);
+    match stem.rsplit_once('-') {
+        Some((name, hash)) => {
+            !name.is_empty() && hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+// whether `accept-encoding` allows encoding, ignoring ones refused with zero quality
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| {
+            let mut parts = v.split(';').map(|part| part.trim());
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .map(|q| q <= 0.0)
+                    .unwrap_or(false)
+            });
+            name.eq_ignore_ascii_case(encoding) && !refused
+        })
+}
+
+// compressed variant of file which client accepts, with its encoding
+fn precompressed(filename: &str, req_headers: &HeaderMap) -> Option<(&'static str, &'static [u8])> {
+    ENCODINGS.iter().find_map(|(encoding, suffix)| {
+        if !accepts_encoding(req_headers, encoding) {
+            return None;
+        }
+        STATIC_DIR
+            .get_file(format!("{}{}", filename, suffix))
+            .map(|file| (*encoding, file.contents()))
+    })
+}
+
+// operator stylesheet overriding theme of webapp. read on every request so that it can be edited
+// without restart, and empty if not configured as webapp always links it
+pub async fn custom_css(state: Extension<Arc<State>>) -> Result<impl IntoResponse, StatusCode> {
+    let css = match &state.0.config.custom_css {
+        Some(path) => match tokio::fs::read_to_string(path).await {
+            Ok(css) => css,
+            Err(err) => {
+                log::error!("failed to read custom css {}: {:?}", path, err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        },
+        None => String::new(),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/css; charset=utf-8"),
+    );
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+    Ok((headers, css))
+}
+
+// fallback of webapp for browsers without WebAssembly
+pub async fn unsupported(state: Extension<Arc<State>>, req_headers: HeaderMap) -> Response {
+    error_page::unsupported(&state.0.config, &req_headers)
+}
+
+pub async fn static_files(
+    state: Extension<Arc<State>>,
+    uri: Uri,
+    req_headers: HeaderMap,
+) -> Response {
+    let config = &state.0.config;
+    let filename = match strip_base_path(uri.path(), &config.base_path) {
+        Some(path) => path.trim_matches('/').to_string(),
+        None => return error_page::not_found(config, &req_headers),
+    };
+
+    match serve_static(&filename, &config.base_path, &req_headers) {
+        Ok(resp) => resp,
+        // api clients get JSON error body from request id layer, and people get readable page
+        Err(StatusCode::NOT_FOUND) if !filename.starts_with("api/") => {
+            error_page::not_found(config, &req_headers)
+        }
+        Err(status) => status.into_response(),
+    }
+}
+
+// rest of path under base path, or none if it is outside
+fn strip_base_path<'a>(path: &'a str, base_path: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(base_path)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+// content type of embedded file guessed from its extension. text assets are utf-8, as trunk
+// writes them so
+fn content_type(filename: &str) -> HeaderValue {
+    let mime = mime_guess::from_path(filename).first_or_octet_stream();
+    let textual = mime.type_() == mime::TEXT
+        || mime.subtype() == mime::JAVASCRIPT
+        || mime.subtype() == mime::JSON
+        || mime.suffix() == Some(mime::XML)
+        || mime.suffix() == Some(mime::JSON);
+    let content_type = if textual {
+        format!("{}; charset=utf-8", mime.essence_str())
+    } else {
+        mime.essence_str().to_owned()
+    };
+    match HeaderValue::from_str(&content_type) {
+        Ok(content_type) => content_type,
+        Err(_) => HeaderValue::from_static("application/octet-stream"),
+    }
+}
+
+// index page with base path told to webapp, and links of trunk, which are absolute, put under it.
+// trunk rewrites html, so only attributes of meta tag are matched
+fn index_under(index: &[u8], base_path: &str) -> Vec<u8> {
+    let index = String::from_utf8_lossy(index);
+    index
+        .replace(
+            r#"name="hako-base-path" content="""#,
+            &format!(r#"name="hako-base-path" content="{}""#, base_path),
+        )
+        .replace(r#"="/"#, &format!(r#"="{}/"#, base_path))
+        .replace("'/", &format!("'{}/", base_path))
+        .into_bytes()
+}
+
+fn serve_static(
+    filename: &str,
+    base_path: &str,
+    req_headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let filename = filename.to_owned();
+
+    if filename.len() > 1000 {
+        // ignore too long filename
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // if given path is share id, then return index file
+    if share_id::is_well_formed(&filename) {
+        return try_return_file(INDEX_FILENAME.to_owned(), base_path, req_headers);
+    }
+
+    // if empty path, then return index file
+    if filename.is_empty() {
+        return try_return_file(INDEX_FILENAME.to_owned(), base_path, req_headers);
+    }
+
+    // other paths without extension are routes of webapp, which renders its own error page for
+    // unknown ones. unknown api paths are still not found.
+    if !filename.contains('.') && !filename.starts_with("api/") {
+        return try_return_file(INDEX_FILENAME.to_owned(), base_path, req_headers);
+    }
+
+    try_return_file(filename, base_path, req_headers)
+}
+
+fn try_return_file(
+    filename: String,
+    base_path: &str,
+    req_headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let mut headers = HeaderMap::new();
+
+    if !filename.contains('.') {
+        // if no extension, then return NOT_FOUND
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    headers.insert(CONTENT_TYPE, content_type(&filename));
+
+    let file = match STATIC_DIR.get_file(&filename) {
+        Some(file) => file,
+        None => {
+            log::error!("static file not found: {}", filename);
+            return Err(StatusCode::NOT_FOUND);
+        }
+    };
+
+    // index links hashed assets, so it is revalidated every time to pick up new builds
+    let cache_control = if is_hashed(&filename) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+
+    // index under base path differs from embedded one, so it is hashed on each request and sent
+    // uncompressed
+    let rewritten = if filename == INDEX_FILENAME && !base_path.is_empty() {
+        Some(index_under(file.contents(), base_path))
+    } else {
+        None
+    };
+    let etag = match &rewritten {
+        Some(contents) => etag(contents),
+        None => {
+            // variants are compressed from the same file, so they share its etag
+            headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+            match etags().get(filename.as_str()) {
+                Some(etag) => etag.clone(),
+                None => etag(file.contents()),
+            }
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(ETAG, value);
+    }
+    if etag_matches(req_headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    if let Some(contents) = rewritten {
+        return Ok((headers, contents).into_response());
+    }
+
+    match precompressed(&filename, req_headers) {
+        Some((encoding, contents)) => {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+            Ok((headers, contents).into_response())
+        }
+        None => Ok((headers, file.contents()).into_response()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_outside_base_path_are_not_served() {
+        assert_eq!(strip_base_path("/hako/123", "/hako"), Some("/123"));
+        assert_eq!(strip_base_path("/hako", "/hako"), Some(""));
+        assert_eq!(strip_base_path("/hakoniwa", "/hako"), None);
+        assert_eq!(strip_base_path("/123", "/hako"), None);
+        assert_eq!(strip_base_path("/123", ""), Some("/123"));
+    }
+
+    #[test]
+    fn only_names_with_trunk_hash_are_immutable() {
+        assert!(is_hashed("index-0123456789abcdef.css"));
+        assert!(is_hashed("hako_webapp-0123456789abcdef_bg.wasm"));
+        assert!(is_hashed("hako_webapp-0123456789abcdef.js"));
+        assert!(!is_hashed("index.html"));
+        assert!(!is_hashed("custom.css"));
+        assert!(!is_hashed("hako-webapp.js"));
+        assert!(!is_hashed("-0123456789abcdef.js"));
+    }
+
+    #[test]
+    fn refused_encodings_are_not_sent() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, deflate, br;q=0"),
+        );
+        assert!(accepts_encoding(&headers, "gzip"));
+        assert!(!accepts_encoding(&headers, "br"));
+        assert!(!accepts_encoding(&HeaderMap::new(), "gzip"));
+    }
+
+    #[test]
+    fn content_type_is_guessed_from_extension() {
+        assert_eq!(content_type("index.html"), "text/html; charset=utf-8");
+        assert_eq!(content_type("favicon.svg"), "image/svg+xml; charset=utf-8");
+        assert_eq!(
+            content_type("manifest.webmanifest"),
+            "application/manifest+json; charset=utf-8"
+        );
+        assert_eq!(
+            content_type("hako_webapp-0123456789abcdef_bg.wasm"),
+            "application/wasm"
+        );
+        assert_eq!(content_type("font.woff2"), "font/woff2");
+        assert_eq!(content_type("unknown.xyz"), "application/octet-stream");
+    }
+
+    #[test]
+    fn links_of_index_are_put_under_base_path() {
+        let index = br#"<head><meta name="hako-base-path" content=""><link href="/index-1.css" rel="stylesheet"/><link href="custom.css" rel="stylesheet" /><script type="module">import init from '/hako_webapp-1.js';init('/hako_webapp-1_bg.wasm');</script></head>"#;
+        let index = String::from_utf8(index_under(index, "/hako")).unwrap();
+        assert!(index.contains(r#"<meta name="hako-base-path" content="/hako">"#));
+        assert!(index.contains(r#"href="/hako/index-1.css""#));
+        assert!(index.contains(r#"href="custom.css""#));
+        assert!(index.contains("'/hako/hako_webapp-1.js'"));
+        assert!(index.contains("init('/hako/hako_webapp-1_bg.wasm')"));
+    }
+}
@@ -0,0 +1,1183 @@
+use std::{
+    convert::TryInto,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, ContentLengthLimit, Extension, FromRequest, Multipart, RequestParts},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use futures::stream::StreamExt;
+use hako_core::{
+    field, PreparedFile, DIGEST_LEN, MAX_IDEMPOTENCY_KEY_LEN, NONCE_LEN, SALT_LEN, STREAM_NONCE_LEN,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    admin, bearer_token_hash, max_file_size, random_token, BearerTokenHash, FileId, BLOCK_OVERHEAD,
+    BLOCK_SIZE,
+};
+use crate::api_key::{self, ApiKey};
+use crate::events::Kind;
+use crate::repo::{Deletable, NewFile, UploadState};
+use crate::storage::Tx;
+use crate::{bandwidth, db, error::ApiError, idempotency, rate_limit, share_id, state::State};
+
+// 10MiB
+const PREPARE_LENGTH_LIMIT: u64 = 10 * 1024 * 1024;
+
+// body of prepare_upload. scripted clients may send JSON instead of building multipart form
+pub enum PrepareUploadBody {
+    Multipart(Multipart),
+    Json(PrepareUploadJson),
+}
+
+#[async_trait]
+impl FromRequest<Body> for PrepareUploadBody {
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map_or(false, |v| v.starts_with("application/json"));
+
+        if is_json {
+            let Json(json) = Json::<PrepareUploadJson>::from_request(req)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(PrepareUploadBody::Json(json))
+        } else {
+            let multipart = Multipart::from_request(req)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(PrepareUploadBody::Multipart(multipart))
+        }
+    }
+}
+
+// same fields as multipart form. binary fields are encoded in base64
+#[derive(Deserialize)]
+pub struct PrepareUploadJson {
+    salt: Option<String>,
+    nonce: Option<String>,
+    filename_nonce: Option<String>,
+    filename: Option<String>,
+    #[serde(default)]
+    is_text: bool,
+    format_version: Option<u8>,
+    idempotency_key: Option<String>,
+    available_from: Option<i64>,
+    expected_chunks: Option<i64>,
+    expected_size: Option<i64>,
+    max_downloads: Option<i64>,
+    expiry: Option<i64>,
+}
+
+impl PrepareUploadJson {
+    // convert to fields as they would be sent in multipart form
+    fn into_fields(self) -> Result<Vec<(&'static str, Bytes)>, StatusCode> {
+        let mut fields = Vec::new();
+        let encoded = [
+            (field::SALT, self.salt),
+            (field::NONCE, self.nonce),
+            (field::FILENAME_NONCE, self.filename_nonce),
+            (field::FILENAME, self.filename),
+            (field::IDEMPOTENCY_KEY, self.idempotency_key),
+        ];
+        for (name, v) in encoded {
+            if let Some(v) = v {
+                let bytes = base64::decode(v).map_err(|err| {
+                    log::error!("invalid base64 in {}: {:?}", name, err);
+                    StatusCode::BAD_REQUEST
+                })?;
+                fields.push((name, Bytes::from(bytes)));
+            }
+        }
+        fields.push((field::IS_TEXT, Bytes::from(vec![self.is_text as u8])));
+        if let Some(v) = self.format_version {
+            fields.push((field::FORMAT_VERSION, Bytes::from(vec![v])));
+        }
+        let integers = [
+            (field::AVAILABLE_FROM, self.available_from),
+            (field::EXPECTED_CHUNKS, self.expected_chunks),
+            (field::EXPECTED_SIZE, self.expected_size),
+            (field::MAX_DOWNLOADS, self.max_downloads),
+            (field::EXPIRY, self.expiry),
+        ];
+        for (name, v) in integers {
+            if let Some(v) = v {
+                fields.push((name, Bytes::copy_from_slice(&v.to_be_bytes())));
+            }
+        }
+
+        Ok(fields)
+    }
+}
+
+pub async fn prepare_upload(
+    state: Extension<Arc<State>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req_headers: HeaderMap,
+    body: ContentLengthLimit<PrepareUploadBody, PREPARE_LENGTH_LIMIT>,
+) -> Result<Json<PreparedFile>, ApiError> {
+    let headers = req_headers.clone();
+    let api_key = db::blocking(state.0.clone(), move |state, conn| {
+        api_key::check(state, conn, &headers, state.clock.now())
+    })
+    .await?;
+
+    let mut fields = Vec::new();
+    match body.0 {
+        PrepareUploadBody::Multipart(mut multipart) => {
+            while let Ok(field) = multipart.next_field().await {
+                if let Some(field) = field {
+                    let name = {
+                        if let Some(name) = field.name() {
+                            name.to_owned()
+                        } else {
+                            return Err(StatusCode::BAD_REQUEST.into());
+                        }
+                    };
+
+                    // check field name first, then read body
+                    if !field::PREPARE.contains(&name.as_str()) {
+                        // unallowed part. ignore
+                        continue;
+                    }
+
+                    // now read some body
+                    let bytes = {
+                        if let Ok(bytes) = field.bytes().await {
+                            bytes
+                        } else {
+                            return Err(StatusCode::BAD_REQUEST.into());
+                        }
+                    };
+
+                    fields.push((name, bytes));
+                } else {
+                    break;
+                }
+            }
+        }
+        PrepareUploadBody::Json(json) => {
+            for (name, bytes) in json.into_fields()? {
+                fields.push((name.to_owned(), bytes));
+            }
+        }
+    }
+
+    let ip = rate_limit::client_ip(&state.0.config, addr.ip(), &req_headers);
+    db::blocking(state.0.clone(), move |state, conn| {
+        prepare(state, conn, ip, api_key, fields, None)
+    })
+    .await
+    .map(Json)
+}
+
+// creates file row from fields of prepare_upload form, which are already read from request body.
+// `content` of small upload is stored as its only chunk in the same transaction, so that file is
+// never left without it. runs on blocking thread pool with connection locked, see `db::blocking`
+fn prepare(
+    state: &State,
+    conn: &mut Connection,
+    ip: IpAddr,
+    api_key: Option<ApiKey>,
+    fields: Vec<(String, Bytes)>,
+    content: Option<Bytes>,
+) -> Result<PreparedFile, ApiError> {
+    let mut salt: Option<Bytes> = None;
+    let mut nonce: Option<Bytes> = None;
+    let mut filename_nonce: Option<Bytes> = None;
+    let mut filename: Option<Bytes> = None;
+    let mut is_text: bool = false;
+    // encryption format used by the client. increased by clients on incompatible changes
+    let mut format_version: u8 = 1;
+    // given by client to make retried requests return the file prepared by earlier attempt
+    let mut idempotency_key: Option<Bytes> = None;
+    // unix timestamp before which file cannot be fetched
+    let mut available_from: Option<i64> = None;
+    // number of chunks and their total length the client is going to upload, if it knows them up
+    // front. checked when last chunk arrives.
+    let mut expected_chunks: Option<i64> = None;
+    let mut expected_size: Option<i64> = None;
+    // file is deleted after this many downloads
+    let mut max_downloads: Option<i64> = None;
+    // seconds until file expires, if uploader wants other than server default
+    let mut expiry: Option<u64> = None;
+
+    // check body validity
+    let mut set_field = |name: &str, bytes: Bytes| -> Result<(), StatusCode> {
+        match name {
+            field::SALT => {
+                // salt should have 32 bytes length
+                if bytes.len() != SALT_LEN {
+                    log::error!("invalid salt length: {}", bytes.len());
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                salt = Some(bytes);
+            }
+            field::NONCE => {
+                // stream nonce should have 19 bytes length
+                // or, if text mode, then should have 24 bytes length
+                if bytes.len() != STREAM_NONCE_LEN && bytes.len() != NONCE_LEN {
+                    log::error!("invalid nonce length: {}", bytes.len());
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                nonce = Some(bytes);
+            }
+            field::FILENAME_NONCE => {
+                // filename nonce should have 24 bytes length
+                if bytes.len() != NONCE_LEN {
+                    log::error!("invalid filename nonce length: {}", bytes.len());
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                filename_nonce = Some(bytes);
+            }
+            field::FILENAME => {
+                filename = Some(bytes);
+            }
+            field::IS_TEXT => {
+                if bytes.len() != 1 {
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                is_text = bytes.to_vec()[0] != 0;
+            }
+            field::FORMAT_VERSION => {
+                // format version should have 1 byte length, and starts from 1
+                if bytes.len() != 1 || bytes[0] == 0 {
+                    log::error!("invalid format version: {:?}", bytes);
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                format_version = bytes[0];
+            }
+            field::IDEMPOTENCY_KEY => {
+                if bytes.is_empty() || bytes.len() > MAX_IDEMPOTENCY_KEY_LEN {
+                    log::error!("invalid idempotency key length: {}", bytes.len());
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+                idempotency_key = Some(bytes);
+            }
+            field::AVAILABLE_FROM => {
+                // available_from should be positive integer of 8 bytes length
+                match parse_i64(&bytes).filter(|t| *t > 0) {
+                    Some(t) => available_from = Some(t),
+                    None => {
+                        log::error!("invalid available_from: {:?}", bytes);
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                }
+            }
+            field::EXPECTED_CHUNKS => {
+                // expected_chunks should be positive integer of 8 bytes length
+                match parse_i64(&bytes).filter(|n| *n > 0) {
+                    Some(n) => expected_chunks = Some(n),
+                    None => {
+                        log::error!("invalid expected_chunks: {:?}", bytes);
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                }
+            }
+            field::EXPECTED_SIZE => {
+                // expected_size should be non-negative integer of 8 bytes length
+                match parse_i64(&bytes).filter(|n| *n >= 0) {
+                    Some(n) => expected_size = Some(n),
+                    None => {
+                        log::error!("invalid expected_size: {:?}", bytes);
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                }
+            }
+            field::MAX_DOWNLOADS => {
+                // max_downloads should be positive integer of 8 bytes length
+                match parse_i64(&bytes).filter(|n| *n > 0) {
+                    Some(n) => max_downloads = Some(n),
+                    None => {
+                        log::error!("invalid max_downloads: {:?}", bytes);
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                }
+            }
+            field::EXPIRY => {
+                // expiry should be positive integer of 8 bytes length
+                match parse_i64(&bytes).filter(|n| *n > 0) {
+                    Some(n) => expiry = Some(n as u64),
+                    None => {
+                        log::error!("invalid expiry: {:?}", bytes);
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    };
+
+    for (name, bytes) in fields {
+        set_field(&name, bytes)?;
+    }
+
+    if !is_text {
+        if [&salt, &nonce, &filename_nonce, &filename]
+            .iter()
+            .any(|o| o.is_none())
+        {
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    } else if [&salt, &nonce].iter().any(|o| o.is_none()) {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+
+    // both or neither of them should be given
+    let expected = match (expected_chunks, expected_size) {
+        (Some(chunks), Some(size)) => Some((chunks, size)),
+        (None, None) => None,
+        _ => {
+            log::error!("expected_chunks and expected_size should be given together");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    };
+    if let Some((chunks, size)) = expected {
+        // refuse oversize upload before any chunk is sent
+        let chunk_count_limit = state.config.chunk_count_limit;
+        if chunks as u64 > chunk_count_limit {
+            log::error!("expected chunks too many: {}", chunks);
+            return Err(ApiError::ChunkCountExceeded {
+                chunk_count_limit,
+                max_file_size: max_file_size(&state.config),
+            });
+        }
+        if size as u64 > chunks as u64 * (BLOCK_SIZE + BLOCK_OVERHEAD) {
+            log::error!("expected size too large for {} chunks: {}", chunks, size);
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+        if let Some(max) = state.config.max_file_size {
+            if size as u64 > max {
+                log::error!("expected size over limit: {}", size);
+                return Err(ApiError::FileTooLarge {
+                    max_file_size: max_file_size(&state.config),
+                });
+            }
+        }
+    }
+
+    let (deletion_token, deletion_token_hash) = random_token()?;
+    let (upload_token, upload_token_hash) = random_token()?;
+
+    // expiry is fixed at prepare time by server clock
+    let now = state.clock.now();
+    let mut expiry = state.config.upload_expiry(expiry);
+    if let Some(api_key) = &api_key {
+        expiry = api_key.clamp_expiry(expiry);
+    }
+    let expires_at = expiry.map(|expiry| now + expiry as i64);
+
+    // begin transaction
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(err) => {
+            log::error!("could not build transaction object: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+
+    if let Some(key) = &idempotency_key {
+        // forget keys out of window
+        let window_start = now - state.config.idempotency_window as i64;
+        if let Err(err) = state.files.forget_idempotency_keys(&tx, window_start) {
+            log::error!("failed to delete old idempotency keys: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+
+        // file was prepared by earlier request with same key, whose response was lost on its way
+        // to client. only client can open stored response, as it is sealed under key it sent.
+        let sealed = state
+            .files
+            .idempotent_response(&tx, &idempotency::key_hash(key))
+            .map_err(|err| {
+                log::error!("failed to query idempotency key: {:?}", err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        if let Some(sealed) = sealed {
+            let prepared = match idempotency::open(key, &sealed) {
+                Some(prepared) => prepared,
+                None => {
+                    log::error!("cannot open stored response of idempotency key");
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+            if let Err(err) = db::commit(tx) {
+                log::error!("failed to commit: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+            log::info!("replaying prepared file: id={}", prepared.id);
+            return Ok(prepared);
+        }
+    }
+
+    if is_text {
+        if let Some(limit) = state.config.text_uploads_per_ip_per_day {
+            if !count_text_upload(state, ip, now, limit) {
+                log::error!("text upload quota exceeded: ip={}", ip);
+                return Err(ApiError::TextQuotaExceeded {
+                    text_uploads_per_day: limit,
+                });
+            }
+        }
+    }
+
+    if let Some(api_key) = &api_key {
+        api_key.check_upload(state, &tx, expected_size, now)?;
+    }
+
+    let mut file = NewFile {
+        slug: String::new(),
+        filename: filename.unwrap_or_default().to_vec(),
+        salt: salt.unwrap().to_vec(),
+        nonce: nonce.unwrap().to_vec(),
+        filename_nonce: filename_nonce.unwrap_or_default().to_vec(),
+        is_text,
+        expires_at,
+        format_version,
+        available_from,
+        expected_chunks,
+        expected_size,
+        uploader_ip_hash: admin::ip_hash(&state.ip_hash_key, ip),
+        deletion_token_hash,
+        max_downloads,
+        upload_token_hash,
+        api_key_id: api_key.as_ref().map(|api_key| api_key.id),
+    };
+    let id = share_id::insert(state, &tx, &mut file)?;
+    let prepared = PreparedFile {
+        id,
+        share_id: Some(file.slug),
+        // only hash of tokens is stored
+        deletion_token: Some(deletion_token),
+        upload_token: Some(upload_token),
+    };
+
+    if let Some(key) = idempotency_key {
+        let sealed = match idempotency::seal(&key, &prepared) {
+            Some(sealed) => sealed,
+            None => {
+                log::error!("cannot seal response for idempotency key");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+        };
+        let key_hash = idempotency::key_hash(&key);
+        if let Err(err) = state
+            .files
+            .remember_idempotency_key(&tx, &key_hash, id, &sealed, now)
+        {
+            log::error!("failed to insert idempotency key: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    }
+
+    if let Some(content) = &content {
+        if let Err(err) = state.storage.put_chunk(Tx::of(&tx), id, 1, content) {
+            log::error!("failed to store chunk: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+        bandwidth::record(&*state.instance, &tx, id, now, content.len() as i64, 0);
+
+        let result = state
+            .files
+            .set_last_seq(&tx, id, 1)
+            .and_then(|_| state.files.set_available(&tx, id, true));
+        if let Err(err) = result {
+            log::error!("failed to query: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    }
+
+    // commit
+    if let Err(err) = db::commit(tx) {
+        log::error!("failed to commit: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    state.events.emit(Kind::Created, id, now);
+    if content.is_some() {
+        state.progress.publish(state, conn, id, now);
+        state.events.emit(Kind::Completed, id, now);
+    }
+
+    Ok(prepared)
+}
+
+// big-endian integer from field, which should have exactly 8 bytes length
+fn parse_i64(bytes: &[u8]) -> Option<i64> {
+    bytes.try_into().ok().map(i64::from_be_bytes)
+}
+
+// count text upload of given client for today. returns false if it already made as many as limit.
+fn count_text_upload(state: &State, ip: IpAddr, now: i64, limit: u64) -> bool {
+    let today = now / (60 * 60 * 24);
+    let mut text_uploads = state.text_uploads.lock().unwrap();
+    // counts of previous days are not needed anymore
+    text_uploads.retain(|_, (day, _)| *day == today);
+
+    let (_, count) = text_uploads.entry(ip).or_insert((today, 0));
+    if *count >= limit {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+// 100MiB
+const UPLOAD_LENGTH_LIMIT: u64 = 100 * 1024 * 1024;
+// missing seqs listed when last chunk arrives before others. the rest are only counted
+const MISSING_SEQS_LIMIT: i64 = 100;
+
+pub async fn upload(
+    state: Extension<Arc<State>>,
+    req_headers: HeaderMap,
+    mut multipart: ContentLengthLimit<Multipart, UPLOAD_LENGTH_LIMIT>,
+) -> Result<&'static str, ApiError> {
+    let mut id: Option<i64> = None;
+    let mut seq: Option<i64> = None;
+    let mut is_last: Option<bool> = None;
+    let mut content: Option<Bytes> = None;
+    let mut digest: Option<Bytes> = None;
+
+    let config = &state.0.config;
+    while let Ok(field) = multipart.0.next_field().await {
+        if let Some(field) = field {
+            let name = {
+                if let Some(name) = field.name() {
+                    name.to_owned()
+                } else {
+                    return Err(StatusCode::BAD_REQUEST.into());
+                }
+            };
+
+            // check field name first, then read body
+            match name.as_ref() {
+                field::ID | field::SEQ | field::IS_LAST | field::CONTENT | field::DIGEST => {}
+                _ => {
+                    // unallowed part. ignore
+                    continue;
+                }
+            }
+            let bytes = {
+                if let Ok(bytes) = field.bytes().await {
+                    bytes
+                } else {
+                    return Err(StatusCode::BAD_REQUEST.into());
+                }
+            };
+
+            match name.as_ref() {
+                field::ID => {
+                    // id should be positive integer of 8 bytes length
+                    match parse_i64(&bytes).filter(|id| *id > 0) {
+                        Some(v) => id = Some(v),
+                        None => {
+                            log::error!("invalid id: {:?}", bytes);
+                            return Err(StatusCode::BAD_REQUEST.into());
+                        }
+                    }
+                }
+                field::SEQ => {
+                    // seq should be positive integer of 8 bytes length, as it starts from 1
+                    let v = match parse_i64(&bytes).filter(|seq| *seq > 0) {
+                        Some(v) => v,
+                        None => {
+                            log::error!("invalid seq: {:?}", bytes);
+                            return Err(StatusCode::BAD_REQUEST.into());
+                        }
+                    };
+                    // check if chunk sequence is too big
+                    if v as u64 > config.chunk_count_limit {
+                        log::error!("seq too large: {}", v);
+                        return Err(ApiError::ChunkCountExceeded {
+                            chunk_count_limit: config.chunk_count_limit,
+                            max_file_size: max_file_size(config),
+                        });
+                    }
+
+                    seq = Some(v);
+                }
+                field::IS_LAST => {
+                    // is_last should have 1 bytes length
+                    if bytes.len() != 1 {
+                        log::error!("invalid is_last length: {}", bytes.len());
+                        return Err(StatusCode::BAD_REQUEST.into());
+                    }
+                    is_last = Some(bytes[0] != 0);
+                }
+                field::CONTENT => {
+                    // chunks are encrypted blocks, so that one chunk cannot take more storage
+                    // than quota of its file accounts for
+                    if bytes.len() as u64 > BLOCK_SIZE + BLOCK_OVERHEAD {
+                        log::error!("chunk larger than block: {}", bytes.len());
+                        return Err(StatusCode::BAD_REQUEST.into());
+                    }
+                    content = Some(bytes);
+                }
+                field::DIGEST => {
+                    // digest is sha256 of content, which should have 32 bytes length
+                    if bytes.len() != DIGEST_LEN {
+                        log::error!("invalid digest length: {}", bytes.len());
+                        return Err(StatusCode::BAD_REQUEST.into());
+                    }
+                    digest = Some(bytes);
+                }
+                _ => {}
+            }
+        } else {
+            break;
+        }
+    }
+
+    let (id, seq, is_last, content) = match (id, seq, is_last, content) {
+        (Some(id), Some(seq), Some(is_last), Some(content)) => (id, seq, is_last, content),
+        _ => return Err(StatusCode::BAD_REQUEST.into()),
+    };
+
+    // verify content if client gave its digest, to catch corruption in transit
+    if let Some(digest) = digest {
+        if Sha256::digest(&content).as_slice() != digest.as_ref() {
+            log::error!("digest mismatch: id={}, seq={}", id, seq);
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    }
+
+    let state = state.0.clone();
+    let missing = db::blocking(state, move |state, conn| {
+        store_chunk(state, conn, &req_headers, id, seq, is_last, &content)
+    })
+    .await?;
+    if let Some(missing) = missing {
+        return Err(missing);
+    }
+
+    Ok("ok")
+}
+
+// store chunk of prepared file and publish file if it is complete now. runs on blocking thread
+// pool, as chunks are large. if last chunk arrived but earlier ones are missing, returns error
+// telling which, to respond with after chunk is committed.
+fn store_chunk(
+    state: &State,
+    conn: &mut Connection,
+    req_headers: &HeaderMap,
+    id: i64,
+    seq: i64,
+    is_last: bool,
+    content: &[u8],
+) -> Result<Option<ApiError>, ApiError> {
+    // make transaction object
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(err) => {
+            log::error!("could not build transaction object: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+
+    // chunk count and size declared at prepare time, and seq of last chunk if it already arrived.
+    // chunks may arrive in any order, so file is published only when every seq up to the
+    // declared count is there, not when the last one arrives.
+    let declared = state.files.upload_state(&tx, id);
+    let UploadState {
+        expected_chunks,
+        expected_size,
+        last_seq,
+        upload_token_hash,
+    } = match declared {
+        Ok(Some(declared)) => declared,
+        Ok(None) => {
+            log::error!("chunk for unknown file: id={}", id);
+            return Err(StatusCode::NOT_FOUND.into());
+        }
+        Err(err) => {
+            log::error!("failed to query expected chunks: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    let expected = expected_chunks.zip(expected_size);
+
+    // upload token is given only to clients which passed API key check in prepare_upload. files
+    // prepared before upload tokens take chunks without one, but need API key instead if required
+    match upload_token_hash {
+        None => {
+            api_key::check(state, &tx, req_headers, state.clock.now())?;
+        }
+        Some(upload_token_hash) => {
+            match bearer_token_hash(req_headers) {
+                // comparing hashes does not leak token through timing
+                Some(given) if given == upload_token_hash => {}
+                Some(_) => {
+                    log::error!("invalid upload token: id={}", id);
+                    return Err(StatusCode::FORBIDDEN.into());
+                }
+                None => {
+                    log::error!("requires upload token: id={}", id);
+                    return Err(StatusCode::UNAUTHORIZED.into());
+                }
+            }
+        }
+    }
+
+    if let Some(chunks) = expected.map(|(chunks, _)| chunks).or(last_seq) {
+        if seq > chunks {
+            log::error!("seq beyond expected chunks: id={}, seq={}", id, seq);
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+        if is_last && seq != chunks {
+            log::error!(
+                "last chunk does not match expected chunks: id={}, seq={}",
+                id,
+                seq
+            );
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    }
+
+    // chunk may be stored already, if response to earlier attempt was lost and client retried
+    let stored = match state.storage.get_chunk(Tx::of(&tx), id, seq) {
+        Ok(stored) => stored,
+        Err(err) => {
+            log::error!("failed to query chunk: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+    if let Some(stored) = stored {
+        if stored == content {
+            log::info!("chunk is already stored: id={}, seq={}", id, seq);
+            return Ok(None);
+        }
+        // different chunk replaces earlier one until file is published, but never after
+        match state.files.is_available(&tx, id) {
+            Ok(Some(false)) => {}
+            Ok(_) => {
+                log::error!("chunk of published file differs: id={}, seq={}", id, seq);
+                return Err(StatusCode::CONFLICT.into());
+            }
+            Err(err) => {
+                log::error!("failed to query: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+        }
+    }
+
+    if let Err(err) = state.storage.put_chunk(Tx::of(&tx), id, seq, content) {
+        log::error!("failed to store chunk: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    bandwidth::record(
+        &*state.instance,
+        &tx,
+        id,
+        state.clock.now(),
+        content.len() as i64,
+        0,
+    );
+
+    if is_last && last_seq.is_none() {
+        if let Err(err) = state.files.set_last_seq(&tx, id, seq) {
+            log::error!("failed to record last chunk: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    }
+
+    let found = state.storage.chunks(Tx::of(&tx), id, 1);
+    let (found_chunks, max_seq, found_size) = match found {
+        Ok(found) => (found.count, found.last_seq.unwrap_or(0), found.bytes),
+        Err(err) => {
+            log::error!("failed to count chunks: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+        }
+    };
+
+    // bytes are counted as chunks arrive, so that upload cannot grow past its declared size or
+    // size limit of server, whichever is smaller, by sending more chunks than chunk count tells
+    let max_size = expected_size
+        .into_iter()
+        .chain(state.config.max_file_size.map(|max| max as i64))
+        .min();
+    if let Some(max_size) = max_size {
+        if found_size > max_size {
+            log::error!(
+                "upload larger than allowed: id={}, size={}/{}",
+                id,
+                found_size,
+                max_size
+            );
+            return Err(ApiError::UploadSizeExceeded {
+                max_size,
+                found_size,
+            });
+        }
+    }
+
+    let total_chunks = match expected {
+        Some((chunks, _)) => Some(chunks),
+        None if is_last => Some(seq),
+        None => last_seq,
+    };
+    let mut published = false;
+    let mut missing = None;
+    if let Some(total_chunks) = total_chunks {
+        // seqs are unique and positive, so count equal to max means 1..=max are all there
+        if max_seq > total_chunks {
+            log::error!(
+                "chunk beyond last seq: id={}, last_seq={}, max_seq={}",
+                id,
+                total_chunks,
+                max_seq
+            );
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+
+        let complete = found_chunks == total_chunks;
+        if let (true, Some((expected_chunks, expected_size))) = (complete, expected) {
+            // every declared chunk is there, so sizes must add up as well
+            if found_size != expected_size {
+                log::error!(
+                    "upload size mismatch: id={}, size={}/{}",
+                    id,
+                    found_size,
+                    expected_size
+                );
+                return Err(ApiError::UploadIncomplete {
+                    expected_chunks,
+                    found_chunks,
+                    expected_size,
+                    found_size,
+                });
+            }
+        }
+
+        if complete {
+            if let Err(err) = state.files.set_available(&tx, id, true) {
+                log::error!("failed to query: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+            published = true;
+        } else if is_last {
+            // seqs are checked here, in the same transaction, so that client uploading in order
+            // learns of dropped chunk now rather than from file which never gets published
+            let result =
+                state
+                    .storage
+                    .missing_seqs(Tx::of(&tx), id, total_chunks, MISSING_SEQS_LIMIT);
+            let missing_seqs = match result {
+                Ok(missing_seqs) => missing_seqs,
+                Err(err) => {
+                    log::error!("failed to query missing chunks: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+            log::info!(
+                "last chunk arrived before others: id={}, missing_chunks={}",
+                id,
+                total_chunks - found_chunks
+            );
+            missing = Some(ApiError::ChunksMissing {
+                last_seq: total_chunks,
+                missing_chunks: total_chunks - found_chunks,
+                missing_seqs,
+            });
+        }
+    }
+
+    // commit
+    if let Err(err) = db::commit(tx) {
+        log::error!("failed to commit: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+    }
+    state
+        .progress
+        .publish(&*state.storage, conn, id, state.clock.now());
+    if published {
+        state.events.emit(Kind::Completed, id, state.clock.now());
+    }
+
+    Ok(missing)
+}
+
+// prepare_upload and upload of its only chunk in one request, which saves round trips for texts
+// and small files. fields are those of prepare_upload plus `content` and `digest` of upload.
+pub async fn upload_small(
+    state: Extension<Arc<State>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req_headers: HeaderMap,
+    mut multipart: ContentLengthLimit<Multipart, UPLOAD_LENGTH_LIMIT>,
+) -> Result<Json<PreparedFile>, ApiError> {
+    let headers = req_headers.clone();
+    let api_key = db::blocking(state.0.clone(), move |state, conn| {
+        api_key::check(state, conn, &headers, state.clock.now())
+    })
+    .await?;
+
+    let mut fields = Vec::new();
+    let mut content: Option<Bytes> = None;
+    let mut digest: Option<Bytes> = None;
+    while let Ok(field) = multipart.0.next_field().await {
+        if let Some(field) = field {
+            let name = {
+                if let Some(name) = field.name() {
+                    name.to_owned()
+                } else {
+                    return Err(StatusCode::BAD_REQUEST.into());
+                }
+            };
+
+            // check field name first, then read body
+            if name != field::CONTENT
+                && name != field::DIGEST
+                && !field::PREPARE.contains(&name.as_str())
+            {
+                // unallowed part. ignore
+                continue;
+            }
+            let bytes = {
+                if let Ok(bytes) = field.bytes().await {
+                    bytes
+                } else {
+                    return Err(StatusCode::BAD_REQUEST.into());
+                }
+            };
+
+            match name.as_ref() {
+                field::CONTENT => {
+                    // whole file should fit in one chunk
+                    if bytes.len() as u64 > BLOCK_SIZE + BLOCK_OVERHEAD {
+                        log::error!("content too large for one chunk: {}", bytes.len());
+                        return Err(StatusCode::BAD_REQUEST.into());
+                    }
+                    content = Some(bytes);
+                }
+                field::DIGEST => {
+                    // digest is sha256 of content, which should have 32 bytes length
+                    if bytes.len() != DIGEST_LEN {
+                        log::error!("invalid digest length: {}", bytes.len());
+                        return Err(StatusCode::BAD_REQUEST.into());
+                    }
+                    digest = Some(bytes);
+                }
+                _ => fields.push((name, bytes)),
+            }
+        } else {
+            break;
+        }
+    }
+
+    let content = match content {
+        Some(content) => content,
+        None => return Err(StatusCode::BAD_REQUEST.into()),
+    };
+
+    // verify content if client gave its digest, to catch corruption in transit
+    if let Some(digest) = digest {
+        if Sha256::digest(&content).as_slice() != digest.as_ref() {
+            log::error!("digest mismatch of small upload");
+            return Err(StatusCode::BAD_REQUEST.into());
+        }
+    }
+
+    // declared chunk count and size should describe this chunk, if they are given
+    fields.retain(|(name, _)| name != field::EXPECTED_CHUNKS && name != field::EXPECTED_SIZE);
+    fields.push((
+        field::EXPECTED_CHUNKS.to_owned(),
+        Bytes::copy_from_slice(&1i64.to_be_bytes()),
+    ));
+    fields.push((
+        field::EXPECTED_SIZE.to_owned(),
+        Bytes::copy_from_slice(&(content.len() as i64).to_be_bytes()),
+    ));
+
+    // chunk may be as large as a block, so store it off runtime threads
+    let ip = rate_limit::client_ip(&state.0.config, addr.ip(), &req_headers);
+    db::blocking(state.0.clone(), move |state, conn| {
+        prepare(state, conn, ip, api_key, fields, Some(content))
+    })
+    .await
+    .map(Json)
+}
+
+#[derive(Serialize)]
+pub struct VerifyResp {
+    algorithm: &'static str,
+    digest: String,
+    chunks: i64,
+    size: i64,
+}
+
+// hash of stored ciphertext, for uploader to compare with what it sent and catch corrupted
+// storage right away. chunks are read and hashed one at a time like in `download`.
+pub async fn verify(
+    state: Extension<Arc<State>>,
+    FileId(id): FileId,
+) -> Result<Json<VerifyResp>, ApiError> {
+    let last_seq = db::read(
+        state.0.clone(),
+        move |state, conn| -> Result<i64, ApiError> {
+            match state.files.is_available(conn, id) {
+                Ok(Some(true)) => {}
+                Ok(_) => return Err(StatusCode::NOT_FOUND.into()),
+                Err(err) => {
+                    log::error!("failed to query: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+            match state.files.download_state(conn, id, state.clock.now()) {
+                Ok(Some(file)) if file.expired => {
+                    log::error!("file is expired: id={}", id);
+                    return Err(StatusCode::GONE.into());
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    log::error!("failed to query: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+
+            let result = state
+                .storage
+                .chunks(Tx::of(conn), id, 1)
+                .map(|chunks| (chunks.last_seq, chunks.count));
+            match result {
+                Ok((Some(last_seq), count)) if count == last_seq => Ok(last_seq),
+                Ok((Some(last_seq), count)) => {
+                    log::error!(
+                        "file incomplete: id={}, last_seq={}, chunks={}",
+                        id,
+                        last_seq,
+                        count
+                    );
+                    return Err(ApiError::FileIncomplete {
+                        expected_chunks: last_seq,
+                        found_chunks: count,
+                    });
+                }
+                Ok((None, _)) => return Err(StatusCode::NOT_FOUND.into()),
+                Err(err) => {
+                    log::error!("failed to query: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+        },
+    )
+    .await?;
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut chunks = state
+        .storage
+        .get_chunk_stream(state.0.clone(), id, 1..=last_seq);
+    let mut seq = 0;
+    while let Some(content) = chunks.next().await {
+        seq += 1;
+        match content {
+            Ok(content) => {
+                size += content.len() as i64;
+                hasher.update(&content);
+            }
+            Err(err) => {
+                // chunk is gone after range check, e.g. file expired meanwhile
+                log::error!(
+                    "failed to read chunk: id={}, seq={}, error={:?}",
+                    id,
+                    seq,
+                    err
+                );
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+        }
+
+        // let other tasks run between chunks
+        tokio::task::yield_now().await;
+    }
+
+    Ok(Json(VerifyResp {
+        algorithm: "sha256",
+        digest: hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+        chunks: last_seq,
+        size,
+    }))
+}
+
+// delete file before it expires, with deletion token given to uploader by prepare_upload in
+// `authorization: bearer` header
+pub async fn delete_file(
+    state: Extension<Arc<State>>,
+    FileId(id): FileId,
+    BearerTokenHash(token_hash): BearerTokenHash,
+) -> Result<&'static str, ApiError> {
+    let token_hash = match token_hash {
+        Some(token_hash) => token_hash,
+        None => {
+            log::error!("requires deletion token");
+            return Err(StatusCode::UNAUTHORIZED.into());
+        }
+    };
+
+    db::blocking(
+        state.0.clone(),
+        move |state, conn| -> Result<&'static str, ApiError> {
+            let tx = match conn.transaction() {
+                Ok(tx) => tx,
+                Err(err) => {
+                    log::error!("could not build transaction object: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            };
+
+            let stored = state.files.deletion_token_hash(&tx, id).map_err(|err| {
+                log::error!("failed to query deletion token: {:?}", err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            match stored {
+                // comparing hashes does not leak token through timing
+                Some(Some(stored)) if stored == token_hash => {}
+                // files uploaded before deletion tokens cannot be deleted this way
+                Some(_) => {
+                    log::error!("invalid deletion token: id={}", id);
+                    return Err(StatusCode::FORBIDDEN.into());
+                }
+                None => {
+                    return Err(StatusCode::NOT_FOUND.into());
+                }
+            }
+
+            match db::delete_file(state, &tx, id, Deletable::NotHeld) {
+                Ok(0) => {
+                    log::error!("file is on legal hold: id={}", id);
+                    return Err(StatusCode::CONFLICT.into());
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    log::error!("failed to delete file: {:?}", err);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+                }
+            }
+
+            if let Err(err) = db::commit(tx) {
+                log::error!("failed to commit: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR.into());
+            }
+
+            state.events.emit(Kind::Deleted, id, state.clock.now());
+            log::info!("deleted file by uploader: id={}", id);
+            Ok("ok")
+        },
+    )
+    .await
+}
@@ -0,0 +1,106 @@
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use axum::{extract::Extension, http::StatusCode, response::Json};
+use serde::Serialize;
+
+use crate::state::State;
+
+// database lock is held during uploads of big chunks, so allow some wait before calling it down
+const DB_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+// expiry worker is stale when it missed this many runs in a row
+const MISSED_RUNS: i64 = 3;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Ok,
+    Starting,
+    Stale,
+    Down,
+}
+
+impl Status {
+    fn healthy(&self) -> bool {
+        !matches!(self, Status::Stale | Status::Down)
+    }
+}
+
+#[derive(Serialize)]
+struct Subsystem {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_run: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct HealthResp {
+    status: Status,
+    database: Subsystem,
+    expiry_worker: Subsystem,
+}
+
+// status of each subsystem for liveness and readiness probes. unlike ping, this touches database,
+// and responds with 503 if anything is unhealthy.
+pub async fn health(state: Extension<Arc<State>>) -> (StatusCode, Json<HealthResp>) {
+    let database = match tokio::time::timeout(DB_LOCK_TIMEOUT, state.0.conn.lock()).await {
+        Ok(conn) => match conn.query_row("select 1", [], |row| row.get::<_, i64>(0)) {
+            Ok(_) => Subsystem {
+                status: Status::Ok,
+                error: None,
+                last_run: None,
+            },
+            Err(err) => {
+                log::error!("health check query failed: {:?}", err);
+                Subsystem {
+                    status: Status::Down,
+                    error: Some(err.to_string()),
+                    last_run: None,
+                }
+            }
+        },
+        Err(_) => Subsystem {
+            status: Status::Down,
+            error: Some("timed out waiting for database lock".to_owned()),
+            last_run: None,
+        },
+    };
+
+    let config = &state.0.config;
+    let last_run = state.0.expiry_last_run.load(Ordering::Relaxed);
+    let last_run = if last_run > 0 { Some(last_run) } else { None };
+    let max_age = config.delete_interval as i64 * MISSED_RUNS;
+    let now = state.0.clock.now();
+    let expiry_status = match last_run {
+        Some(t) if now - t > max_age => Status::Stale,
+        Some(_) => Status::Ok,
+        // not run yet since startup
+        None if state.0.clock.uptime() > max_age => Status::Stale,
+        None => Status::Starting,
+    };
+    let expiry_worker = Subsystem {
+        status: expiry_status,
+        error: None,
+        last_run,
+    };
+
+    let healthy = database.status.healthy() && expiry_worker.status.healthy();
+    let (code, status) = if healthy {
+        (StatusCode::OK, Status::Ok)
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Status::Down)
+    };
+
+    (
+        code,
+        Json(HealthResp {
+            status,
+            database,
+            expiry_worker,
+        }),
+    )
+}
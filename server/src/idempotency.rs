@@ -0,0 +1,81 @@
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hako_core::{PreparedFile, NONCE_LEN};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+// response of prepare_upload is kept for the idempotency window, so that request retried after
+// its answer was lost gets the same file and tokens again. database holds neither idempotency key
+// nor tokens in the clear: rows are looked up by hash of key, and response is encrypted under
+// another key derived from it, which only the client knows.
+
+fn derive(key: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts key of any length");
+    mac.update(purpose);
+    let mut derived = [0u8; 32];
+    derived.copy_from_slice(&mac.finalize().into_bytes());
+    derived
+}
+
+fn cipher(key: &[u8]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(&derive(key, b"hako prepared file")))
+}
+
+// what stored response is looked up by
+pub fn key_hash(key: &[u8]) -> Vec<u8> {
+    derive(key, b"hako idempotency key").to_vec()
+}
+
+// random nonce followed by encrypted JSON of response
+pub fn seal(key: &[u8], prepared: &PreparedFile) -> Option<Vec<u8>> {
+    let plaintext = serde_json::to_vec(prepared).ok()?;
+    let mut nonce = [0u8; NONCE_LEN];
+    if let Err(err) = getrandom::getrandom(&mut nonce) {
+        log::error!("cannot get random nonce: {:?}", err);
+        return None;
+    }
+    let ciphertext = cipher(key)
+        .encrypt(XNonce::from_slice(&nonce), plaintext.as_ref())
+        .ok()?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Some(sealed)
+}
+
+pub fn open(key: &[u8], sealed: &[u8]) -> Option<PreparedFile> {
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let plaintext = cipher(key)
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .ok()?;
+    serde_json::from_slice(&plaintext).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_is_opened_only_with_its_key() {
+        let prepared = PreparedFile {
+            id: 1,
+            share_id: Some("aB3dE9xYz1".to_owned()),
+            deletion_token: Some("deletion".to_owned()),
+            upload_token: Some("upload".to_owned()),
+        };
+        let sealed = seal(b"key", &prepared).unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"upload"));
+
+        let opened = open(b"key", &sealed).unwrap();
+        assert_eq!(opened.id, 1);
+        assert_eq!(opened.upload_token.as_deref(), Some("upload"));
+        assert!(open(b"other key", &sealed).is_none());
+        assert!(open(b"key", &sealed[..NONCE_LEN]).is_none());
+
+        assert_ne!(key_hash(b"key"), b"key".to_vec());
+        assert_ne!(key_hash(b"key"), key_hash(b"other key"));
+    }
+}
@@ -1,65 +1,285 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, post, put},
     Extension, Router,
 };
 use clap::Parser;
+use handlers::{admin, download, metadata, static_assets, upload};
+use repo::InstanceRepo;
 use rusqlite::Connection;
 use simple_logger::SimpleLogger;
 use state::State;
-use tokio::sync::Mutex;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 
+mod api_key;
+mod bandwidth;
+mod client_error;
 mod config;
+mod consistency;
+mod db;
+mod embed;
+mod error;
+mod error_page;
+mod events;
+mod growth;
 mod handlers;
+mod health;
+mod idempotency;
+mod metrics;
+mod migrations;
+mod notifier;
+mod openapi;
+mod policy;
+mod progress;
+mod rate_limit;
+mod relay;
+mod repo;
+mod request_id;
+mod share_id;
+mod smtp;
 mod state;
+mod storage;
 mod utils;
+mod webhook;
 mod workers;
 
 #[tokio::main]
 async fn main() {
-    SimpleLogger::new()
+    let logger = SimpleLogger::new()
         .with_utc_timestamps()
-        .with_level(log::LevelFilter::Info)
-        .init()
-        .unwrap();
-    let config = config::Config::parse();
+        .with_level(log::LevelFilter::Info);
+    log::set_boxed_logger(Box::new(request_id::Logger(logger))).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    config::load_file_into_env().expect("failed to read config file");
+    let mut config = config::Config::parse();
+    config
+        .load_api_keys_file()
+        .expect("failed to read API keys file");
 
     // setup database connetion
-    let conn = Connection::open(config.sqlite_db_filename.clone()).unwrap();
+    let mut conn = Connection::open(config.sqlite_db_filename.clone()).unwrap();
+    db::tune(&conn, &config).expect("failed to apply sqlite options");
     let bootstrap_sql = include_str!("../schema.sql");
     conn.execute_batch(bootstrap_sql).unwrap();
-    let conn = Mutex::new(conn);
+    migrations::run(&mut conn).unwrap();
+    if config.vacuum_interval.is_some() && !config.full_vacuum {
+        db::enable_incremental_vacuum(&conn).expect("failed to enable incremental vacuum");
+    }
+    if let Err(err) = consistency::check(&mut conn, config.repair_on_startup) {
+        log::error!("failed to check database consistency: {:?}", err);
+    }
+    let mut fresh_key = [0u8; 32];
+    getrandom::getrandom(&mut fresh_key).expect("failed to get random key");
+    let instance = repo::SqliteInstanceRepo;
+    let ip_hash_key = instance
+        .secret(&conn, "ip_hash", &fresh_key)
+        .expect("failed to load key of uploader address hashes");
+    let readers = db::ReadPool::open(&config, &conn).expect("failed to open read connections");
+    let conn = db::Writer::new(conn);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
     let shared_state = Arc::new(State {
         conn,
+        readers,
+        storage: Box::new(storage::SqliteStorage),
+        files: Box::new(repo::SqliteFileRepo),
+        instance: Box::new(instance),
         config: config.clone(),
+        clock: utils::Clock::start(),
+        ip_hash_key,
+        text_uploads: Default::default(),
+        expiry_last_run: Default::default(),
+        rate_limits: rate_limit::RateLimits::new(&config),
+        progress: Default::default(),
+        events: events::Events::start(&config),
+        shutdown: shutdown_rx.clone(),
     });
     let worker_state = shared_state.clone();
 
-    let app = Router::new()
-        .route("/api/metadata", get(handlers::metadata))
-        .route("/api/download", get(handlers::download))
-        .route("/api/ping", get(handlers::ping))
-        .route("/api/prepare_upload", post(handlers::prepare_upload))
-        .route("/api/upload", post(handlers::upload))
-        .fallback(get(handlers::static_files))
-        .layer(
-            ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(Extension(shared_state)),
-        );
+    let app = app(shared_state.clone());
 
     let addr: SocketAddr = config.bind_addr.parse().expect("invalid bind addr");
 
-    // start worker
-    tokio::spawn(workers::delete_expired(worker_state, config));
+    // start workers. those writing to database stop between their runs on shutdown, and the
+    // others are just cut off
+    let monitor = tokio::spawn(workers::monitor_storage(
+        worker_state.clone(),
+        config.clone(),
+    ));
+    let workers = vec![
+        tokio::spawn(workers::purge_abandoned(
+            worker_state.clone(),
+            config.clone(),
+            shutdown_rx.clone(),
+        )),
+        tokio::spawn(workers::reclaim_space(
+            worker_state.clone(),
+            config.clone(),
+            shutdown_rx.clone(),
+        )),
+        tokio::spawn(workers::delete_expired(
+            worker_state.clone(),
+            config.clone(),
+            shutdown_rx.clone(),
+        )),
+        tokio::spawn(workers::record_growth(worker_state, shutdown_rx.clone())),
+    ];
+
+    static_assets::hash_assets();
 
     log::info!("starting server at {}...", addr.to_string());
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let mut server_shutdown = shutdown_rx;
+    let mut server = tokio::spawn(
+        axum::Server::bind(&addr)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(async move {
+                let _ = server_shutdown.changed().await;
+            }),
+    );
+    tokio::select! {
+        result = &mut server => {
+            log::error!("server stopped: {:?}", result);
+            return;
+        }
+        _ = shutdown_signal() => {}
+    }
+
+    // stop accepting connections, and let in-flight uploads and downloads finish
+    log::info!("shutting down, waiting for in-flight requests...");
+    let _ = shutdown_tx.send(true);
+    let timeout = Duration::from_secs(config.shutdown_timeout);
+    let deadline = tokio::time::Instant::now() + timeout;
+    match tokio::time::timeout_at(deadline, &mut server).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(err))) => log::error!("server failed while shutting down: {:?}", err),
+        Ok(Err(err)) => log::error!("server task failed: {:?}", err),
+        Err(_) => {
+            log::error!("in-flight requests did not finish in time. cutting them off");
+            server.abort();
+            let _ = server.await;
+        }
+    }
+    for worker in workers {
+        if tokio::time::timeout_at(deadline, worker).await.is_err() {
+            log::error!("worker did not stop in time. cutting it off");
+        }
+    }
+    monitor.abort();
+    let _ = monitor.await;
+
+    // connection is closed only when nothing else holds state, as blocking tasks cannot be
+    // cut off. sqlite recovers from unclosed connection anyway, on next start
+    match Arc::try_unwrap(shared_state) {
+        Ok(state) => match state.conn.into_inner().close() {
+            Ok(()) => log::info!("closed database"),
+            Err((_, err)) => log::error!("failed to close database: {:?}", err),
+        },
+        Err(_) => log::error!("database is still in use. exiting without closing it"),
+    }
+}
+
+// resolves on ctrl-c, or SIGTERM sent by service managers and container runtimes
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+fn app(shared_state: Arc<State>) -> Router {
+    let routes = Router::new()
+        .route(
+            "/api/metadata",
+            get(metadata::metadata)
+                .route_layer(middleware::from_fn(rate_limit::metadata))
+                .route_layer(middleware::from_fn(embed::cors)),
+        )
+        .route(
+            "/api/download",
+            get(download::download).route_layer(middleware::from_fn(embed::cors)),
+        )
+        .route(
+            "/api/open",
+            get(download::open).route_layer(middleware::from_fn(embed::cors)),
+        )
+        .route("/api/verify", get(upload::verify))
+        .route(
+            "/api/events",
+            get(metadata::events).route_layer(middleware::from_fn(rate_limit::events)),
+        )
+        .route("/api/ping", get(metadata::ping))
+        .route("/api/health", get(health::health))
+        .route("/api/app_config", get(metadata::app_config))
+        .route("/api/metrics", get(metrics::metrics))
+        .route("/api/openapi.json", get(openapi::openapi))
+        .route("/api/policy", get(policy::policy))
+        .route("/custom.css", get(static_assets::custom_css))
+        .route("/unsupported", get(static_assets::unsupported))
+        .route(
+            "/api/client_error",
+            post(client_error::report).route_layer(middleware::from_fn(rate_limit::client_error)),
+        )
+        .route(
+            "/api/prepare_upload",
+            post(upload::prepare_upload)
+                .route_layer(middleware::from_fn(rate_limit::prepare_upload)),
+        )
+        .route(
+            "/api/upload_small",
+            post(upload::upload_small).route_layer(middleware::from_fn(rate_limit::prepare_upload)),
+        )
+        .route(
+            "/api/upload",
+            post(upload::upload).route_layer(middleware::from_fn(rate_limit::upload)),
+        )
+        .route("/api/file", delete(upload::delete_file))
+        .route(
+            "/api/admin/upload_sessions",
+            get(admin::upload_sessions).delete(admin::abort_upload_session),
+        )
+        .route(
+            "/api/admin/legal_hold",
+            put(admin::set_legal_hold).delete(admin::clear_legal_hold),
+        )
+        .route("/api/admin/audit_log", get(admin::audit_log))
+        .route("/api/admin/bandwidth", get(admin::bandwidth))
+        .route("/api/admin/expiry_preview", get(admin::expiry_preview))
+        .route(
+            "/api/admin/files",
+            get(admin::files).delete(admin::delete_file),
+        )
+        .route("/api/admin/stats", get(admin::stats))
+        .route("/api/admin/storage_forecast", get(admin::storage_forecast))
+        .route("/api/admin/vacuum", post(admin::vacuum))
+        .route("/api/admin/embed_url", post(admin::create_embed_url))
+        .route("/api/admin/relay", post(admin::relay_file))
+        .route("/api/admin/client_errors", get(admin::client_errors))
+        .route(
+            "/api/admin/api_keys",
+            get(admin::api_keys)
+                .post(admin::create_api_key)
+                .delete(admin::revoke_api_key),
+        );
+
+    // static files and pages of webapp go through fallback, which takes off base path by itself
+    let base_path = &shared_state.config.base_path;
+    let router = if base_path.is_empty() {
+        routes
+    } else {
+        Router::new().nest(base_path, routes)
+    };
+    router.fallback(get(static_assets::static_files)).layer(
+        ServiceBuilder::new()
+            .layer(TraceLayer::new_for_http())
+            .layer(middleware::from_fn(request_id::request_id))
+            .layer(Extension(shared_state)),
+    )
 }
@@ -0,0 +1,129 @@
+use std::{fmt::Write, sync::Arc, time::SystemTime};
+
+use axum::{
+    extract::Extension,
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+
+use crate::{db, state::State};
+
+fn file_len(path: &str) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|m| m.len())
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+// database health gauges in prometheus text format, so that operators can alert on growth before
+// disk fills up
+pub async fn metrics(state: Extension<Arc<State>>) -> impl IntoResponse {
+    let config = &state.0.config;
+
+    let (page_count, freelist_count) = {
+        let conn = state.0.conn.lock().await;
+        let page_count = conn.query_row("pragma page_count", [], |row| row.get::<_, i64>(0));
+        let freelist_count =
+            conn.query_row("pragma freelist_count", [], |row| row.get::<_, i64>(0));
+        match (page_count, freelist_count) {
+            (Ok(p), Ok(f)) => (p as u64, f as u64),
+            (Err(err), _) | (_, Err(err)) => {
+                log::error!("failed to query page counts: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    };
+
+    let mut out = String::new();
+    if let Some(size) = file_len(&config.sqlite_db_filename) {
+        gauge(
+            &mut out,
+            "hako_db_file_bytes",
+            "Size of database file.",
+            size,
+        );
+    }
+    let wal_size = file_len(&format!("{}-wal", config.sqlite_db_filename)).unwrap_or(0);
+    gauge(
+        &mut out,
+        "hako_db_wal_bytes",
+        "Size of write-ahead log of database.",
+        wal_size,
+    );
+    gauge(
+        &mut out,
+        "hako_db_page_count",
+        "Pages in database file.",
+        page_count,
+    );
+    gauge(
+        &mut out,
+        "hako_db_freelist_pages",
+        "Unused pages in database file.",
+        freelist_count,
+    );
+
+    // waits add up when requests serialize on connections, e.g. downloads behind uploads
+    let waits = db::wait_stats();
+    counter(
+        &mut out,
+        "hako_db_writer_locks_total",
+        "Times connection of writer was taken.",
+        waits.writer_locks as f64,
+    );
+    counter(
+        &mut out,
+        "hako_db_writer_wait_seconds_total",
+        "Time spent waiting for connection of writer.",
+        waits.writer_wait_seconds,
+    );
+    counter(
+        &mut out,
+        "hako_db_reader_locks_total",
+        "Times read-only connection was taken.",
+        waits.reader_locks as f64,
+    );
+    counter(
+        &mut out,
+        "hako_db_reader_wait_seconds_total",
+        "Time spent waiting for read-only connection.",
+        waits.reader_wait_seconds,
+    );
+
+    // backup job touches marker file after each successful run
+    if let Some(marker) = &config.backup_marker_file {
+        let modified = std::fs::metadata(marker).and_then(|m| m.modified());
+        match modified {
+            Ok(modified) => {
+                let age = SystemTime::now()
+                    .duration_since(modified)
+                    .map_or(0, |age| age.as_secs());
+                gauge(
+                    &mut out,
+                    "hako_db_last_backup_age_seconds",
+                    "Seconds since backup marker file was modified.",
+                    age,
+                );
+            }
+            Err(err) => {
+                log::error!("failed to read backup marker file: {:?}", err);
+            }
+        }
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    Ok((headers, out))
+}
@@ -0,0 +1,40 @@
+use rusqlite::Connection;
+
+// schema changes applied on top of schema.sql, in order. `user_version` pragma of the database
+// records how many of them are already applied, so never reorder or edit released entries.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/0001_expires_at.sql"),
+    include_str!("../migrations/0002_format_version.sql"),
+    include_str!("../migrations/0003_idempotency_keys.sql"),
+    include_str!("../migrations/0004_available_from.sql"),
+    include_str!("../migrations/0005_expected_chunks.sql"),
+    include_str!("../migrations/0006_uploader_ip_hash.sql"),
+    include_str!("../migrations/0007_deletion_token.sql"),
+    include_str!("../migrations/0008_max_downloads.sql"),
+    include_str!("../migrations/0009_last_seq.sql"),
+    include_str!("../migrations/0010_legal_hold.sql"),
+    include_str!("../migrations/0011_bandwidth_usage.sql"),
+    include_str!("../migrations/0012_upload_token.sql"),
+    include_str!("../migrations/0013_client_errors.sql"),
+    include_str!("../migrations/0014_api_keys.sql"),
+    include_str!("../migrations/0015_storage_history.sql"),
+    include_str!("../migrations/0016_slug.sql"),
+    include_str!("../migrations/0017_instance_secrets.sql"),
+    include_str!("../migrations/0018_idempotency_responses.sql"),
+    include_str!("../migrations/0019_download_tickets.sql"),
+];
+
+pub fn run(conn: &mut Connection) -> rusqlite::Result<()> {
+    let applied: i64 = conn.query_row("pragma user_version", [], |row| row.get(0))?;
+
+    for (idx, sql) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        let version = idx + 1;
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.execute_batch(&format!("pragma user_version = {}", version))?;
+        tx.commit()?;
+        log::info!("applied database migration {}", version);
+    }
+
+    Ok(())
+}
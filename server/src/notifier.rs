@@ -0,0 +1,193 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    async_trait,
+    http::{header::AUTHORIZATION, Request},
+};
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    smtp::{self, Credentials, Relay},
+    webhook::{self, Error, HttpClient, TextMessage},
+};
+
+// destination of operator notifications
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn notify(&self, text: &str) -> Result<(), Error>;
+}
+
+// build every notifier configured. misconfigured ones are logged and left out, so that one typo
+// does not silence the others.
+pub fn from_config(config: &Config) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+    if let Some(url) = config.alert_webhook_url.clone() {
+        notifiers.push(Box::new(WebhookNotifier {
+            client: webhook::client(),
+            url,
+        }));
+    }
+
+    match (
+        config.matrix_homeserver_url.clone(),
+        config.matrix_room_id.clone(),
+        config.matrix_access_token.clone(),
+    ) {
+        (Some(homeserver_url), Some(room_id), Some(access_token)) => {
+            notifiers.push(Box::new(MatrixNotifier {
+                client: webhook::client(),
+                homeserver_url,
+                room_id,
+                access_token,
+            }));
+        }
+        (None, None, None) => {}
+        _ => {
+            log::error!("matrix notifier requires homeserver url, room id and access token");
+        }
+    }
+
+    match (&config.smtp_host, &config.email_from, &config.email_to) {
+        (Some(host), Some(from), Some(to)) => match EmailNotifier::new(config, host, from, to) {
+            Ok(notifier) => notifiers.push(Box::new(notifier)),
+            Err(err) => {
+                log::error!("failed to set up email notifier: {:?}", err);
+            }
+        },
+        (None, None, None) => {}
+        _ => {
+            log::error!("email notifier requires smtp host, from address and to address");
+        }
+    }
+
+    notifiers
+}
+
+// send text to every notifier, logging failures
+pub async fn notify_all(notifiers: &[Box<dyn Notifier>], text: &str) {
+    for notifier in notifiers {
+        if let Err(err) = notifier.notify(text).await {
+            log::error!("failed to notify via {}: {:?}", notifier.name(), err);
+        }
+    }
+}
+
+// slack incoming webhook or compatible bridge
+struct WebhookNotifier {
+    client: HttpClient,
+    url: String,
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn notify(&self, text: &str) -> Result<(), Error> {
+        webhook::post_json(&self.client, &self.url, &TextMessage { text }).await
+    }
+}
+
+// posts to matrix room directly through client-server API, with access token of bot account
+struct MatrixNotifier {
+    client: HttpClient,
+    homeserver_url: String,
+    room_id: String,
+    access_token: String,
+}
+
+#[derive(Serialize)]
+struct MatrixMessage<'a> {
+    msgtype: &'static str,
+    body: &'a str,
+}
+
+// transaction ids only need to be unique per access token, to let homeserver drop retried sends
+static MATRIX_TXN: AtomicU64 = AtomicU64::new(0);
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    async fn notify(&self, text: &str) -> Result<(), Error> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let txn_id = format!(
+            "hako-{}-{}",
+            started,
+            MATRIX_TXN.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver_url.trim_end_matches('/'),
+            percent_encode(&self.room_id),
+            txn_id
+        );
+        let req = Request::put(url).header(AUTHORIZATION, format!("Bearer {}", self.access_token));
+        let body = MatrixMessage {
+            msgtype: "m.text",
+            body: text,
+        };
+        webhook::send_json(&self.client, req, &body).await
+    }
+}
+
+// room ids look like `!abc:example.org`, which should be escaped in path
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// mail through SMTP relay with STARTTLS
+struct EmailNotifier {
+    relay: Relay,
+    from: String,
+    to: String,
+}
+
+impl EmailNotifier {
+    fn new(config: &Config, host: &str, from: &str, to: &str) -> Result<Self, Error> {
+        let credentials = match (&config.smtp_username, &config.smtp_password) {
+            (Some(username), Some(password)) => Some(Credentials {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            relay: Relay::new(host, config.smtp_port, credentials)?,
+            from: smtp::address(from)?,
+            to: smtp::address(to)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn notify(&self, text: &str) -> Result<(), Error> {
+        self.relay
+            .send(&self.from, &self.to, "hako notification", text)
+            .await
+    }
+}
@@ -0,0 +1,484 @@
+use axum::response::{IntoResponse, Json};
+use serde_json::{json, Value};
+
+use crate::handlers::PROTOCOL_VERSION;
+
+// multipart field of 8 bytes, holding big-endian integer
+fn integer_field(description: &str) -> Value {
+    json!({
+        "type": "string",
+        "format": "binary",
+        "description": format!("{}. 8 bytes, big-endian integer", description),
+    })
+}
+
+fn binary_field(description: &str) -> Value {
+    json!({
+        "type": "string",
+        "format": "binary",
+        "description": description,
+    })
+}
+
+// fields of prepare_upload form, which upload_small takes as well
+fn prepare_fields() -> Value {
+    json!({
+        "salt": binary_field("salt of key derivation. 32 bytes"),
+        "nonce": binary_field("stream nonce of 19 bytes, or nonce of 24 bytes if text"),
+        "filename_nonce": binary_field("nonce of encrypted filename. 24 bytes. not for text"),
+        "filename": binary_field("encrypted filename. not for text"),
+        "is_text": binary_field("1 byte, nonzero if text"),
+        "format_version": binary_field("encryption format version. 1 byte, starts from 1"),
+        "idempotency_key": binary_field(
+            "up to 64 bytes. retried request with same key returns the file prepared by earlier one"
+        ),
+        "available_from": integer_field("unix timestamp before which file cannot be fetched"),
+        "expected_chunks": integer_field("number of chunks to be uploaded"),
+        "expected_size": integer_field("total length of chunks to be uploaded"),
+        "max_downloads": integer_field("file is deleted after this many downloads"),
+        "expiry": integer_field("seconds until file expires, within bounds of app_config"),
+    })
+}
+
+fn id_param() -> Value {
+    json!({
+        "name": "id",
+        "in": "query",
+        "required": true,
+        "description": "share id of file",
+        "schema": { "type": "string" },
+    })
+}
+
+// query parameters of embed url, issued by admin API so that pages of other origin can fetch file
+fn embed_params() -> [Value; 3] {
+    let param = |name: &str, description: &str| {
+        json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "description": format!("{}. part of embed url", description),
+            "schema": { "type": "string" },
+        })
+    };
+    [
+        param("origin", "origin allowed to read response"),
+        param("expires", "unix timestamp until which embed url works"),
+        param("signature", "signature of embed url by server"),
+    ]
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/Error" } },
+        },
+    })
+}
+
+fn prepare_resp() -> Value {
+    json!({
+        "description": "file is prepared",
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/PreparedFile" } },
+        },
+    })
+}
+
+fn prepare_upload() -> Value {
+    let request_body = json!({
+        "required": true,
+        "content": {
+            "multipart/form-data": {
+                "schema": {
+                    "type": "object",
+                    "required": ["salt", "nonce"],
+                    "properties": prepare_fields(),
+                },
+            },
+            "application/json": {
+                "schema": { "type": "object", "required": ["salt", "nonce"] },
+            },
+        },
+    });
+    json!({
+        "post": {
+            "summary": "create file to upload chunks of",
+            "description": "binary fields are encoded in base64 and integers are plain numbers if sent as JSON. `authorization: bearer` header carries API key if server requires one",
+            "requestBody": request_body,
+            "responses": {
+                "200": prepare_resp(),
+                "400": error_response("invalid field"),
+                "401": error_response("API key is required"),
+                "413": error_response("declared chunks or size are over limit of server or API key"),
+                "429": error_response("quota or rate limit exceeded"),
+            },
+        },
+    })
+}
+
+fn upload() -> Value {
+    let properties = json!({
+        "id": integer_field("id of prepared file"),
+        "seq": integer_field("sequence number of chunk, starts from 1"),
+        "is_last": binary_field("1 byte, nonzero if last chunk"),
+        "content": binary_field("ciphertext of chunk"),
+        "digest": binary_field("sha256 of content. 32 bytes"),
+    });
+    let request_body = json!({
+        "required": true,
+        "content": {
+            "multipart/form-data": {
+                "schema": {
+                    "type": "object",
+                    "required": ["id", "seq", "is_last", "content"],
+                    "properties": properties,
+                },
+            },
+        },
+    });
+    json!({
+        "post": {
+            "summary": "upload chunk of prepared file",
+            "description": "chunks may arrive in any order. file is published when every chunk up to the last one is there. retried chunk of the same seq replaces earlier one until then. `authorization: bearer` header carries upload token of prepared file",
+            "requestBody": request_body,
+            "responses": {
+                "200": { "description": "chunk is stored" },
+                "202": error_response("last chunk is stored, but chunks before it are missing. file is published once they arrive"),
+                "400": error_response("invalid field or chunk sequence"),
+                "401": error_response("upload token is required"),
+                "403": error_response("upload token is invalid"),
+                "404": error_response("file is not prepared"),
+                "409": error_response("chunks do not add up to declared size, or chunk differs from one of published file"),
+                "413": error_response("chunk count, or bytes stored so far, are over declared size or limit"),
+            },
+        },
+    })
+}
+
+fn upload_small() -> Value {
+    let mut properties = prepare_fields();
+    properties["content"] = binary_field("ciphertext of the only chunk");
+    properties["digest"] = binary_field("sha256 of content. 32 bytes");
+    let request_body = json!({
+        "required": true,
+        "content": {
+            "multipart/form-data": {
+                "schema": {
+                    "type": "object",
+                    "required": ["salt", "nonce", "content"],
+                    "properties": properties,
+                },
+            },
+        },
+    });
+    json!({
+        "post": {
+            "summary": "prepare file and upload its only chunk at once",
+            "requestBody": request_body,
+            "responses": {
+                "200": prepare_resp(),
+                "400": error_response("invalid field"),
+                "401": error_response("API key is required"),
+                "429": error_response("quota or rate limit exceeded"),
+            },
+        },
+    })
+}
+
+fn metadata() -> Value {
+    let mut parameters = vec![id_param()];
+    parameters.extend(embed_params());
+    let ok = json!({
+        "description": "metadata of uploaded file",
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/Metadata" } },
+        },
+    });
+    json!({
+        "get": {
+            "summary": "metadata to decrypt file with",
+            "parameters": parameters,
+            "responses": {
+                "200": ok,
+                "202": error_response("file is still being uploaded, with its progress"),
+                "403": error_response("file is not available yet, or embed url is invalid"),
+                "404": error_response("file does not exist"),
+                "410": error_response("file is expired or downloaded as many times as allowed"),
+            },
+        },
+    })
+}
+
+fn download() -> Value {
+    let from_seq = json!({
+        "name": "from_seq",
+        "in": "query",
+        "required": false,
+        "description": "first chunk to send. chunks after first are sent only with ticket",
+        "schema": { "type": "integer", "minimum": 1 },
+    });
+    let ticket = json!({
+        "name": "ticket",
+        "in": "query",
+        "required": false,
+        "description": "`download_ticket` given by open, for rest of download it counted. used once",
+        "schema": { "type": "string" },
+    });
+    let mut parameters = vec![id_param(), from_seq, ticket];
+    parameters.extend(embed_params());
+    let ok = json!({
+        "description": "ciphertext",
+        "content": {
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } },
+        },
+    });
+    json!({
+        "get": {
+            "summary": "ciphertext of file, which is its chunks concatenated in order",
+            "parameters": parameters,
+            "responses": {
+                "200": ok,
+                "403": error_response("file is not available yet, embed url or ticket is invalid"),
+                "404": error_response("file does not exist, or is still being uploaded"),
+                "410": error_response("file is expired or downloaded as many times as allowed"),
+            },
+        },
+    })
+}
+
+fn open() -> Value {
+    let mut parameters = vec![id_param()];
+    parameters.extend(embed_params());
+    let ok = json!({
+        "description": "4-byte big-endian length of metadata JSON, the JSON, and first chunk as is",
+        "content": {
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } },
+        },
+    });
+    json!({
+        "get": {
+            "summary": "metadata and first chunk at once. counts as download",
+            "description": "metadata is as in `Metadata`, along with `download_ticket` if file has more chunks",
+            "parameters": parameters,
+            "responses": {
+                "200": ok,
+                "403": error_response("file is not available yet, or embed url is invalid"),
+                "404": error_response("file does not exist, or is still being uploaded"),
+                "410": error_response("file is expired or downloaded as many times as allowed"),
+            },
+        },
+    })
+}
+
+fn verify() -> Value {
+    let ok = json!({
+        "description": "digest of stored ciphertext",
+        "content": {
+            "application/json": {
+                "schema": {
+                    "type": "object",
+                    "properties": {
+                        "algorithm": { "type": "string", "description": "`sha256`" },
+                        "digest": { "type": "string", "description": "hex of digest" },
+                        "chunks": { "type": "integer" },
+                        "size": { "type": "integer" },
+                    },
+                },
+            },
+        },
+    });
+    json!({
+        "get": {
+            "summary": "hash of stored ciphertext, for uploader to compare with what it sent",
+            "parameters": [id_param()],
+            "responses": {
+                "200": ok,
+                "404": error_response("file does not exist, or is still being uploaded"),
+                "409": error_response("some chunks of file are missing"),
+                "410": error_response("file is expired"),
+            },
+        },
+    })
+}
+
+fn events() -> Value {
+    let ok = json!({
+        "description": "`progress` events while file is uploaded and `complete` at the end, with received and expected chunks and bytes in JSON. `gone` if file is deleted or expires first",
+        "content": { "text/event-stream": { "schema": { "type": "string" } } },
+    });
+    json!({
+        "get": {
+            "summary": "server-sent events of upload progress. current progress is sent first",
+            "description": "stream also ends when server shuts down, and clients should reconnect",
+            "parameters": [id_param()],
+            "responses": {
+                "200": ok,
+                "404": error_response("file does not exist or is expired"),
+                "429": error_response("rate limit exceeded"),
+            },
+        },
+    })
+}
+
+fn file() -> Value {
+    json!({
+        "delete": {
+            "summary": "delete file before it expires",
+            "description": "`authorization: bearer` header carries deletion token given by prepare_upload",
+            "parameters": [id_param()],
+            "responses": {
+                "200": { "description": "deleted" },
+                "401": error_response("deletion token is required"),
+                "403": error_response("deletion token is invalid"),
+                "404": error_response("file does not exist"),
+                "409": error_response("file is on legal hold"),
+            },
+        },
+    })
+}
+
+fn schemas() -> Value {
+    let prepared_file = json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "integer", "description": "id to upload chunks with" },
+            "share_id": { "type": "string", "description": "id to put in share url" },
+            "deletion_token": { "type": "string" },
+            "upload_token": { "type": "string" },
+        },
+    });
+    let metadata = json!({
+        "type": "object",
+        "properties": {
+            "filename": { "type": "string", "format": "byte" },
+            "salt": { "type": "string", "format": "byte" },
+            "nonce": { "type": "string", "format": "byte" },
+            "filename_nonce": { "type": "string", "format": "byte" },
+            "is_text": { "type": "boolean" },
+            "size": { "type": "integer" },
+            "expires_at": { "type": "integer", "nullable": true },
+            "format_version": { "type": "integer" },
+            "mirrors": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "base urls serving /api/download as well, which may be faster",
+            },
+            "downloads_left": {
+                "type": "integer",
+                "nullable": true,
+                "description": "downloads allowed before file is gone, null if not limited",
+            },
+            "download_ticket": {
+                "type": "string",
+                "description": "given by open if file has more chunks, for fetching them from download",
+            },
+        },
+    });
+    let error = json!({
+        "type": "object",
+        "properties": {
+            "code": { "type": "string", "description": "machine-readable, e.g. `not_found`" },
+            "message": { "type": "string", "description": "for people, may change" },
+            "detail": {
+                "type": "object",
+                "nullable": true,
+                "description": "fields particular to code, null for plain failures",
+            },
+            "request_id": { "type": "string" },
+        },
+    });
+    json!({
+        "PreparedFile": prepared_file,
+        "Metadata": metadata,
+        "Error": error,
+    })
+}
+
+pub fn spec() -> Value {
+    let ping = json!({
+        "get": {
+            "summary": "liveness check. protocol and format versions are in headers",
+            "responses": { "200": { "description": "pong" } },
+        },
+    });
+    let policy = json!({
+        "get": {
+            "summary": "limits, retention, coarse free capacity and protocol versions. cacheable",
+            "responses": { "200": { "description": "policy in JSON" } },
+        },
+    });
+    let app_config = json!({
+        "get": {
+            "summary": "limits of this server",
+            "responses": { "200": { "description": "limits in JSON" } },
+        },
+    });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "hako",
+            "version": PROTOCOL_VERSION.to_string(),
+            "description": "files are encrypted by clients, and server only stores ciphertext",
+        },
+        "paths": {
+            "/api/ping": ping,
+            "/api/app_config": app_config,
+            "/api/policy": policy,
+            "/api/prepare_upload": prepare_upload(),
+            "/api/upload": upload(),
+            "/api/upload_small": upload_small(),
+            "/api/metadata": metadata(),
+            "/api/download": download(),
+            "/api/open": open(),
+            "/api/verify": verify(),
+            "/api/events": events(),
+            "/api/file": file(),
+        },
+        "components": { "schemas": schemas() },
+    })
+}
+
+// layout of HTTP API for third-party clients. written by hand, so keep it along with handlers
+pub async fn openapi() -> impl IntoResponse {
+    Json(spec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // routes which third-party clients do not use, and are left out of spec on purpose
+    const UNDOCUMENTED: &[&str] = &[
+        "/api/health",
+        "/api/metrics",
+        "/api/openapi.json",
+        "/api/client_error",
+    ];
+
+    #[test]
+    fn public_routes_are_documented() {
+        let spec = spec();
+        // routes are all registered with `.route("/api/...", ...)` in main.rs
+        let main = include_str!("main.rs");
+        let routes = main
+            .split(".route(")
+            .skip(1)
+            .filter_map(|route| route.trim_start().strip_prefix('"')?.split('"').next());
+        for route in routes {
+            if !route.starts_with("/api/")
+                || route.starts_with("/api/admin/")
+                || UNDOCUMENTED.contains(&route)
+            {
+                continue;
+            }
+            assert!(
+                spec["paths"].get(route).is_some(),
+                "{} is not in spec",
+                route
+            );
+        }
+    }
+}
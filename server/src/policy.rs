@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Extension,
+    http::{header::CACHE_CONTROL, HeaderMap, HeaderValue},
+    response::{IntoResponse, Json},
+};
+use serde::Serialize;
+
+use crate::{
+    api_key,
+    handlers::{max_file_size, BLOCK_SIZE, FORMAT_VERSIONS, PROTOCOL_VERSION},
+    state::State,
+    workers,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Capacity {
+    Plenty,
+    Limited,
+    Full,
+    Unknown,
+}
+
+// exact usage is not given out, so that uploads cannot be watched through this feed
+fn capacity(used: u64, capacity: u64) -> Capacity {
+    if used >= capacity {
+        Capacity::Full
+    } else if used * 10 >= capacity * 9 {
+        Capacity::Limited
+    } else {
+        Capacity::Plenty
+    }
+}
+
+#[derive(Serialize)]
+struct Limits {
+    block_size: u64,
+    chunk_count_limit: u64,
+    max_file_size: u64,
+    text_uploads_per_ip_per_day: Option<u64>,
+    api_key_required: bool,
+}
+
+#[derive(Serialize)]
+struct Retention {
+    // seconds files are kept without per-upload expiry. kept until deleted if not set
+    default_expiry: Option<u64>,
+    min_expiry: Option<u64>,
+    max_expiry: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct PolicyResp {
+    protocol_version: u32,
+    format_versions: Vec<u8>,
+    limits: Limits,
+    retention: Retention,
+    capacity: Capacity,
+}
+
+// policy of this instance for directory sites and clients choosing where to upload. same for
+// everyone, so proxies may cache it for a while.
+pub async fn policy(state: Extension<Arc<State>>) -> impl IntoResponse {
+    let config = &state.0.config;
+    let api_key_required = {
+        let conn = state.0.conn.lock().await;
+        match api_key::required(&state.0, &conn, state.0.clock.now()) {
+            Ok(required) => required,
+            Err(err) => {
+                log::error!("failed to query API keys: {:?}", err);
+                true
+            }
+        }
+    };
+
+    let capacity = match config.storage_capacity {
+        Some(total) => match workers::db_file_size(&config.sqlite_db_filename) {
+            Ok(used) => capacity(used, total),
+            Err(err) => {
+                log::error!("failed to get database file size: {:?}", err);
+                Capacity::Unknown
+            }
+        },
+        None => Capacity::Unknown,
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=300"),
+    );
+
+    (
+        headers,
+        Json(PolicyResp {
+            protocol_version: PROTOCOL_VERSION,
+            format_versions: FORMAT_VERSIONS
+                .split(',')
+                .filter_map(|v| v.trim().parse().ok())
+                .collect(),
+            limits: Limits {
+                block_size: BLOCK_SIZE,
+                chunk_count_limit: config.chunk_count_limit,
+                max_file_size: max_file_size(config),
+                text_uploads_per_ip_per_day: config.text_uploads_per_ip_per_day,
+                api_key_required,
+            },
+            retention: Retention {
+                default_expiry: config.expiry.filter(|e| *e > 0).map(|e| e as u64),
+                min_expiry: config.max_expiry.and(config.min_expiry),
+                max_expiry: config.max_expiry,
+            },
+            capacity,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capacity_is_bucketed() {
+        assert!(matches!(capacity(0, 100), Capacity::Plenty));
+        assert!(matches!(capacity(89, 100), Capacity::Plenty));
+        assert!(matches!(capacity(90, 100), Capacity::Limited));
+        assert!(matches!(capacity(100, 100), Capacity::Full));
+        assert!(matches!(capacity(150, 100), Capacity::Full));
+    }
+}
@@ -0,0 +1,79 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{db, state::State, storage::Tx};
+
+// how far a watcher can fall behind before it misses events. only the latest one matters, so
+// missing some is fine.
+const CHANNEL_CAPACITY: usize = 64;
+
+// progress of upload, broadcast to clients watching file while it is being uploaded
+#[derive(Clone, Serialize)]
+pub struct UploadProgress {
+    #[serde(skip)]
+    pub id: i64,
+    pub received_chunks: i64,
+    pub received_bytes: i64,
+    // declared at prepare time, or known after last chunk arrives
+    pub expected_chunks: Option<i64>,
+    pub expected_size: Option<i64>,
+    pub complete: bool,
+}
+
+pub struct Progress {
+    sender: broadcast::Sender<UploadProgress>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Progress { sender }
+    }
+}
+
+impl Progress {
+    pub fn subscribe(&self) -> broadcast::Receiver<UploadProgress> {
+        self.sender.subscribe()
+    }
+
+    // broadcast current progress of file, if anyone is watching
+    pub fn publish(&self, state: &State, conn: &Connection, id: i64, now: i64) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        match query(state, conn, id, now) {
+            Ok(Some(progress)) => {
+                // nobody may be listening anymore, which is fine
+                let _ = self.sender.send(progress);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                log::error!("failed to query upload progress: {:?}", err);
+            }
+        }
+    }
+}
+
+// progress of file which is not expired yet
+pub fn query(
+    state: &State,
+    conn: &Connection,
+    id: i64,
+    now: i64,
+) -> db::Result<Option<UploadProgress>> {
+    let target = match state.files.upload_target(conn, id, now)? {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+
+    let chunks = state.storage.chunks(Tx::of(conn), id, 1)?;
+    Ok(Some(UploadProgress {
+        id,
+        received_chunks: chunks.count,
+        received_bytes: chunks.bytes,
+        expected_chunks: target.expected_chunks,
+        expected_size: target.expected_size,
+        complete: target.complete,
+    }))
+}
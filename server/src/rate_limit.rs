@@ -0,0 +1,352 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{header::FORWARDED, HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{config::Config, error::ApiError, state::State};
+
+// what is left of limit for a client, sent back in `x-ratelimit-*` headers so that clients can
+// slow down before being refused
+#[derive(Debug, PartialEq)]
+pub struct Quota {
+    pub limit: u32,
+    pub remaining: u32,
+    // seconds until bucket is full again
+    pub reset: u64,
+}
+
+impl Quota {
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(self.reset));
+    }
+}
+
+// bucket refills in a minute, so one untouched for longer is full and can be forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// token bucket of each client ip. bucket holds up to `per_minute` requests and refills at the same
+// rate, so that clients can burst up to the limit and then go on at steady rate.
+pub struct RateLimiter {
+    per_minute: Option<u32>,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    tokens: HashMap<IpAddr, (f64, Instant)>,
+    // when full buckets were last forgotten
+    swept_at: Option<Instant>,
+}
+
+impl RateLimiter {
+    fn new(per_minute: Option<u32>) -> Self {
+        RateLimiter {
+            per_minute: per_minute.filter(|n| *n > 0),
+            buckets: Default::default(),
+        }
+    }
+
+    // take one request from bucket of given ip, or return seconds until there is one again
+    fn acquire(&self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        let capacity = match self.per_minute {
+            Some(per_minute) => per_minute as f64,
+            None => return Ok(()),
+        };
+        let refilled = |tokens: f64, at: Instant| {
+            let elapsed = now.saturating_duration_since(at).as_secs_f64();
+            (tokens + elapsed * capacity / 60.0).min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        // full buckets are same as missing ones, so forget them to keep map small. going over
+        // every bucket once a minute keeps requests in between from paying for it.
+        let swept_at = *buckets.swept_at.get_or_insert(now);
+        if now.saturating_duration_since(swept_at) >= SWEEP_INTERVAL {
+            buckets
+                .tokens
+                .retain(|_, (tokens, at)| refilled(*tokens, *at) < capacity);
+            buckets.swept_at = Some(now);
+        }
+
+        let (tokens, at) = buckets.tokens.entry(ip).or_insert((capacity, now));
+        *tokens = refilled(*tokens, *at);
+        *at = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - *tokens) * 60.0 / capacity).ceil() as u64)
+        }
+    }
+
+    // quota left in bucket of given ip, without taking from it. none if there is no limit
+    fn quota(&self, ip: IpAddr, now: Instant) -> Option<Quota> {
+        let per_minute = self.per_minute?;
+        let capacity = per_minute as f64;
+        let buckets = self.buckets.lock().unwrap();
+        // missing bucket is a full one
+        let tokens = buckets.tokens.get(&ip).map_or(capacity, |(tokens, at)| {
+            let elapsed = now.saturating_duration_since(*at).as_secs_f64();
+            (tokens + elapsed * capacity / 60.0).min(capacity)
+        });
+        Some(Quota {
+            limit: per_minute,
+            remaining: tokens.floor() as u32,
+            reset: ((capacity - tokens) * 60.0 / capacity).ceil() as u64,
+        })
+    }
+}
+
+// limiters of rate limited routes, each configured separately
+pub struct RateLimits {
+    prepare_upload: RateLimiter,
+    upload: RateLimiter,
+    metadata: RateLimiter,
+    events: RateLimiter,
+    client_error: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(config: &Config) -> Self {
+        RateLimits {
+            prepare_upload: RateLimiter::new(config.prepare_upload_rate_limit),
+            upload: RateLimiter::new(config.upload_rate_limit),
+            metadata: RateLimiter::new(config.metadata_rate_limit),
+            events: RateLimiter::new(config.events_rate_limit),
+            client_error: RateLimiter::new(config.client_error_rate_limit),
+        }
+    }
+}
+
+pub async fn prepare_upload<B>(req: Request<B>, next: Next<B>) -> Response {
+    limit(req, next, "prepare_upload", |limits| &limits.prepare_upload).await
+}
+
+pub async fn upload<B>(req: Request<B>, next: Next<B>) -> Response {
+    limit(req, next, "upload", |limits| &limits.upload).await
+}
+
+pub async fn metadata<B>(req: Request<B>, next: Next<B>) -> Response {
+    limit(req, next, "metadata", |limits| &limits.metadata).await
+}
+
+pub async fn events<B>(req: Request<B>, next: Next<B>) -> Response {
+    limit(req, next, "events", |limits| &limits.events).await
+}
+
+pub async fn client_error<B>(req: Request<B>, next: Next<B>) -> Response {
+    limit(req, next, "client_error", |limits| &limits.client_error).await
+}
+
+// address of client. behind trusted proxies it is taken from `forwarded` or `x-forwarded-for`
+// header, where each proxy appends address it got request from. so list is walked from its end,
+// skipping proxies which are trusted too, and stops at first address which cannot be parsed, as
+// anything before that may be made up by client.
+pub fn client_ip(config: &Config, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let mut client = peer;
+    if !config.trusted_proxies.contains(&client) {
+        return client;
+    }
+
+    for ip in forwarded_for(headers).into_iter().rev() {
+        match ip {
+            Some(ip) => client = ip,
+            None => break,
+        }
+        if !config.trusted_proxies.contains(&client) {
+            break;
+        }
+    }
+    client
+}
+
+// addresses listed in `for` of standard `forwarded` header, or in `x-forwarded-for` if there is
+// no such header. none for addresses which cannot be parsed, e.g. obfuscated ones.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<_> = headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then(|| parse_node(value))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+// address with optional port, and in brackets if ipv6 with port, as proxies write it
+fn parse_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| value.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+async fn limit<B>(
+    req: Request<B>,
+    next: Next<B>,
+    route: &str,
+    limiter: fn(&RateLimits) -> &RateLimiter,
+) -> Response {
+    let state = req.extensions().get::<Arc<State>>().cloned();
+    // connection info is missing only when app is called without server, e.g. in tests
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let (state, peer) = match (state, peer) {
+        (Some(state), Some(peer)) => (state, peer),
+        _ => return next.run(req).await,
+    };
+    let ip = client_ip(&state.config, peer, req.headers());
+
+    let limiter = limiter(&state.rate_limits);
+    let now = Instant::now();
+    let acquired = limiter.acquire(ip, now);
+    let quota = limiter.quota(ip, now);
+    let mut resp = match acquired {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => {
+            log::error!("rate limited: route={}, ip={}", route, ip);
+            ApiError::RateLimited { retry_after }.into_response()
+        }
+    };
+    if let Some(quota) = quota {
+        quota.insert_headers(resp.headers_mut());
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn bucket_refills_at_limit_rate() {
+        let limiter = RateLimiter::new(Some(2));
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire(ip, start), Ok(()));
+        assert_eq!(limiter.acquire(ip, start), Ok(()));
+        assert_eq!(limiter.acquire(ip, start), Err(30));
+        // other clients have their own buckets
+        assert_eq!(limiter.acquire("127.0.0.2".parse().unwrap(), start), Ok(()));
+
+        assert_eq!(limiter.acquire(ip, start + Duration::from_secs(30)), Ok(()));
+        assert_eq!(
+            limiter.acquire(ip, start + Duration::from_secs(45)),
+            Err(15)
+        );
+    }
+
+    #[test]
+    fn quota_tells_remaining_requests() {
+        let limiter = RateLimiter::new(Some(2));
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        let full = Quota {
+            limit: 2,
+            remaining: 2,
+            reset: 0,
+        };
+        assert_eq!(limiter.quota(ip, start), Some(full));
+        limiter.acquire(ip, start).unwrap();
+        let quota = Quota {
+            limit: 2,
+            remaining: 1,
+            reset: 30,
+        };
+        assert_eq!(limiter.quota(ip, start), Some(quota));
+        assert_eq!(RateLimiter::new(None).quota(ip, start), None);
+    }
+
+    #[test]
+    fn unset_limit_allows_everything() {
+        let limiter = RateLimiter::new(None);
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert_eq!(limiter.acquire(ip, now), Ok(()));
+        }
+    }
+
+    #[test]
+    fn full_buckets_are_forgotten_once_a_minute() {
+        let limiter = RateLimiter::new(Some(2));
+        let first: IpAddr = "127.0.0.1".parse().unwrap();
+        let second: IpAddr = "127.0.0.2".parse().unwrap();
+        let start = Instant::now();
+        limiter.acquire(first, start).unwrap();
+        limiter
+            .acquire(second, start + Duration::from_secs(50))
+            .unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().tokens.len(), 2);
+
+        // bucket of first client refilled by now, and second is still used
+        limiter
+            .acquire(second, start + Duration::from_secs(61))
+            .unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().tokens.len(), 1);
+    }
+
+    #[test]
+    fn client_ip_is_forwarded_only_by_trusted_proxy() {
+        let config = Config::parse_from(["hako", "--trusted-proxies", "10.0.0.1,10.0.0.2"]);
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let ip = |peer: IpAddr, name: &'static str, value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_static(value));
+            client_ip(&config, peer, &headers).to_string()
+        };
+
+        assert_eq!(ip(proxy, "x-forwarded-for", "192.0.2.1"), "192.0.2.1");
+        // client may send its own header, which proxy appends to
+        assert_eq!(
+            ip(
+                proxy,
+                "x-forwarded-for",
+                "198.51.100.1, 192.0.2.1, 10.0.0.2"
+            ),
+            "192.0.2.1"
+        );
+        assert_eq!(
+            ip(proxy, "forwarded", "for=\"[2001:db8::1]:4711\";proto=https"),
+            "2001:db8::1"
+        );
+        assert_eq!(ip(proxy, "forwarded", "for=unknown"), "10.0.0.1");
+        // header of untrusted peer is ignored
+        let peer: IpAddr = "192.0.2.9".parse().unwrap();
+        assert_eq!(ip(peer, "x-forwarded-for", "192.0.2.1"), "192.0.2.9");
+    }
+}
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use axum::http::{Request, StatusCode};
+use futures::StreamExt;
+use hako_core::{Metadata, PreparedFile};
+use hyper::Body;
+
+use crate::handlers::{random_token, BLOCK_OVERHEAD, BLOCK_SIZE, FORMAT_VERSIONS};
+use crate::repo::{Deletable, NewFile};
+use crate::webhook::{self, HttpClient};
+use crate::{bandwidth, db, events::Kind, share_id, state::State, storage::Tx};
+
+// largest chunk of ciphertext, which holds one full block
+const CHUNK_SIZE: usize = (BLOCK_SIZE + BLOCK_OVERHEAD) as usize;
+
+// file on another hako instance, as in its share url
+#[derive(Debug, PartialEq)]
+pub struct Source {
+    base_url: String,
+    share_id: String,
+}
+
+// base url and share id from share url, e.g. `https://example.com/hako/123-ab12cd34#passphrase`.
+// passphrase in fragment is dropped, as it is never needed to copy ciphertext
+pub fn parse_source(link: &str) -> Option<Source> {
+    let link = link.split('#').next().unwrap_or_default();
+    if !link.starts_with("https://") && !link.starts_with("http://") {
+        return None;
+    }
+    let (base_url, share_id) = link.trim_end_matches('/').rsplit_once('/')?;
+    let plain = share_id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if share_id.is_empty() || !plain || base_url.ends_with('/') {
+        return None;
+    }
+    Some(Source {
+        base_url: base_url.to_owned(),
+        share_id: share_id.to_owned(),
+    })
+}
+
+// body of successful response. files still being uploaded are accepted with other status, and
+// are not there to copy yet
+async fn get(client: &HttpClient, url: &str) -> Result<Body, webhook::Error> {
+    let req = Request::get(url).body(Body::empty())?;
+    let resp = client.request(req).await?;
+    if resp.status() != StatusCode::OK {
+        return Err(format!("{} responded with {}", url, resp.status()).into());
+    }
+    Ok(resp.into_body())
+}
+
+// copy file of other instance here. encrypted filename, salt, nonces and ciphertext are copied as
+// they are, so the same passphrase opens the copy and this server never sees it. copy expires as
+// configured here, and gets downloads left on source minus the one spent by relaying.
+pub async fn relay(state: Arc<State>, source: &Source) -> Result<PreparedFile, StatusCode> {
+    let client = webhook::client();
+
+    let url = format!("{}/api/metadata?id={}", source.base_url, source.share_id);
+    let metadata = match get(&client, &url).await {
+        Ok(body) => hyper::body::to_bytes(body).await.map_err(Into::into),
+        Err(err) => Err(err),
+    };
+    let metadata: Metadata = match metadata.and_then(|body| Ok(serde_json::from_slice(&body)?)) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            log::error!("failed to fetch metadata of relayed file: {:?}", err);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    let version = metadata.format_version.to_string();
+    if !FORMAT_VERSIONS.split(',').any(|v| v == version) {
+        log::error!("relayed file has unsupported format version: {}", version);
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let chunks = (metadata.size as u64 + CHUNK_SIZE as u64 - 1) / CHUNK_SIZE as u64;
+    if metadata.size <= 0 || chunks > state.config.chunk_count_limit {
+        log::error!("relayed file has unacceptable size: {}", metadata.size);
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let url = format!("{}/api/download?id={}", source.base_url, source.share_id);
+    let mut ciphertext = match get(&client, &url).await {
+        Ok(body) => body,
+        Err(err) => {
+            log::error!("failed to download relayed file: {:?}", err);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    let (deletion_token, deletion_token_hash) = random_token()?;
+    let (_, upload_token_hash) = random_token()?;
+    let now = state.clock.now();
+    let mut file = NewFile {
+        slug: String::new(),
+        filename: metadata.filename,
+        salt: metadata.salt,
+        nonce: metadata.nonce,
+        filename_nonce: metadata.filename_nonce,
+        is_text: metadata.is_text,
+        expires_at: state
+            .config
+            .upload_expiry(None)
+            .map(|expiry| now + expiry as i64),
+        format_version: metadata.format_version,
+        available_from: None,
+        expected_chunks: Some(chunks as i64),
+        expected_size: Some(metadata.size),
+        uploader_ip_hash: "relay".to_owned(),
+        deletion_token_hash,
+        max_downloads: metadata.downloads_left.map(|left| (left - 1).max(1)),
+        upload_token_hash,
+        api_key_id: None,
+    };
+    let id = {
+        let conn = state.conn.lock().await;
+        share_id::insert(&state, &conn, &mut file)?
+    };
+    state.events.emit(Kind::Created, id, now);
+
+    // cut ciphertext back into chunks it was uploaded in, every one of them but the last holding
+    // full block. chunk is stored as soon as it is complete, so that memory use stays at a block
+    let mut buf = Vec::with_capacity(CHUNK_SIZE);
+    let mut seq = 0;
+    let mut received = 0;
+    let mut done = false;
+    while !done {
+        let next = ciphertext.next().await;
+        if let Some(Err(err)) = &next {
+            log::error!("failed to download relayed file: id={}, {:?}", id, err);
+            discard(state.clone(), id).await;
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+        let mut data = match next {
+            Some(Ok(data)) => data,
+            _ => {
+                done = true;
+                Default::default()
+            }
+        };
+        received += data.len() as i64;
+        if received > metadata.size {
+            log::error!("relayed file is larger than told: id={}", id);
+            discard(state.clone(), id).await;
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+
+        while !data.is_empty() || (done && !buf.is_empty()) {
+            let take = data.len().min(CHUNK_SIZE - buf.len());
+            buf.extend_from_slice(&data.split_to(take));
+            if buf.len() < CHUNK_SIZE && !done {
+                break;
+            }
+            seq += 1;
+            let content = std::mem::replace(&mut buf, Vec::with_capacity(CHUNK_SIZE));
+            if let Err(err) = store_chunk(state.clone(), id, seq, content).await {
+                log::error!("failed to store relayed chunk: id={}, {:?}", id, err);
+                discard(state.clone(), id).await;
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    if received != metadata.size {
+        log::error!("relayed file is shorter than told: id={}", id);
+        discard(state.clone(), id).await;
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    let result = db::blocking(state.clone(), move |state, conn| {
+        state
+            .files
+            .set_last_seq(conn, id, seq)
+            .and_then(|_| state.files.set_available(conn, id, true))
+    })
+    .await;
+    if let Err(err) = result {
+        log::error!("failed to publish relayed file: id={}, {:?}", id, err);
+        discard(state.clone(), id).await;
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    state.events.emit(Kind::Completed, id, state.clock.now());
+
+    log::info!(
+        "relayed file: id={}, from={}/{}",
+        id,
+        source.base_url,
+        source.share_id
+    );
+    Ok(PreparedFile {
+        id,
+        share_id: Some(file.slug),
+        deletion_token: Some(deletion_token),
+        upload_token: None,
+    })
+}
+
+// chunk may be as large as a block, so store it off runtime threads
+async fn store_chunk(state: Arc<State>, id: i64, seq: i64, content: Vec<u8>) -> db::Result<()> {
+    db::blocking(state, move |state, conn| -> db::Result<()> {
+        let tx = conn.transaction()?;
+        state.storage.put_chunk(Tx::of(&tx), id, seq, &content)?;
+        bandwidth::record(
+            &*state.instance,
+            &tx,
+            id,
+            state.clock.now(),
+            content.len() as i64,
+            0,
+        );
+        db::commit(tx)?;
+        Ok(())
+    })
+    .await
+}
+
+// drop partial copy, which would otherwise wait for abandoned upload purge
+async fn discard(state: Arc<State>, id: i64) {
+    let result = db::blocking(state.clone(), move |state, conn| -> db::Result<()> {
+        let tx = conn.transaction()?;
+        db::delete_file(state, &tx, id, Deletable::Incomplete)?;
+        db::commit(tx)?;
+        Ok(())
+    })
+    .await;
+    match result {
+        Ok(()) => state.events.emit(Kind::Deleted, id, state.clock.now()),
+        Err(err) => log::error!(
+            "failed to delete partial relayed file: id={}, {:?}",
+            id,
+            err
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_is_parsed_from_share_url() {
+        assert_eq!(
+            parse_source("https://example.com/hako/123-ab12cd34#correct-horse"),
+            Some(Source {
+                base_url: "https://example.com/hako".to_owned(),
+                share_id: "123-ab12cd34".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_source("http://localhost:12321/5"),
+            Some(Source {
+                base_url: "http://localhost:12321".to_owned(),
+                share_id: "5".to_owned(),
+            })
+        );
+        assert_eq!(parse_source("https://example.com/"), None);
+        assert_eq!(parse_source("https://example.com/5?id=6"), None);
+        assert_eq!(parse_source("example.com/5"), None);
+    }
+}
@@ -0,0 +1,425 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+mod sqlite;
+
+pub use sqlite::{SqliteFileRepo, SqliteInstanceRepo};
+
+// file to insert at prepare time
+pub struct NewFile {
+    // random share id, see `share_id::insert`
+    pub slug: String,
+    pub filename: Vec<u8>,
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub filename_nonce: Vec<u8>,
+    pub is_text: bool,
+    pub expires_at: Option<i64>,
+    pub format_version: u8,
+    pub available_from: Option<i64>,
+    pub expected_chunks: Option<i64>,
+    pub expected_size: Option<i64>,
+    pub uploader_ip_hash: String,
+    pub deletion_token_hash: Vec<u8>,
+    pub max_downloads: Option<i64>,
+    pub upload_token_hash: Vec<u8>,
+    pub api_key_id: Option<i64>,
+}
+
+// metadata of file as clients need it to decrypt
+pub struct FileRecord {
+    pub filename: Vec<u8>,
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub filename_nonce: Vec<u8>,
+    pub is_text: bool,
+    pub expires_at: Option<i64>,
+    pub format_version: u8,
+    pub available_from: Option<i64>,
+    pub available: bool,
+    // past expiry and not under legal hold, whether or not expiry worker has hidden it yet
+    pub expired: bool,
+    // expired or downloaded as many times as allowed
+    pub finished: bool,
+    // none if downloads are not limited
+    pub downloads_left: Option<i64>,
+}
+
+// what upload of chunk checks against. chunk count and size are declared at prepare time, and
+// last seq is known once last chunk arrives
+pub struct UploadState {
+    pub expected_chunks: Option<i64>,
+    pub expected_size: Option<i64>,
+    pub last_seq: Option<i64>,
+    pub upload_token_hash: Option<Vec<u8>>,
+}
+
+pub struct DownloadState {
+    pub available: bool,
+    pub available_from: Option<i64>,
+    pub downloads_exhausted: bool,
+    // past expiry and not under legal hold. chunks may be gone already, with row kept so that it
+    // reads as expired
+    pub expired: bool,
+}
+
+// which files `FileRepo::delete` deletes, besides matching id
+#[derive(Clone, Copy)]
+pub enum Deletable {
+    // any file not on legal hold
+    NotHeld,
+    // incomplete upload not on legal hold
+    IncompleteNotHeld,
+    // incomplete upload, even on legal hold
+    Incomplete,
+}
+
+// what upload of file not expired yet is expected to reach, for its progress
+pub struct UploadTarget {
+    // declared at prepare time, or known after last chunk arrives
+    pub expected_chunks: Option<i64>,
+    pub expected_size: Option<i64>,
+    pub complete: bool,
+}
+
+// incomplete upload, as admin API lists it with its chunks
+#[derive(Serialize)]
+pub struct PendingUpload {
+    pub id: i64,
+    pub age_seconds: i64,
+    pub uploader_ip_hash: Option<String>,
+}
+
+// stored file, as admin API lists it with its chunks
+#[derive(Serialize)]
+pub struct FileSummary {
+    pub id: i64,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub is_text: bool,
+    pub available: bool,
+    pub download_count: i64,
+    pub max_downloads: Option<i64>,
+    pub legal_hold: bool,
+    pub uploader_ip_hash: Option<String>,
+}
+
+pub struct FileCounts {
+    pub files: i64,
+    pub available: i64,
+    // incomplete uploads which are not expired
+    pub incomplete: i64,
+    pub legal_holds: i64,
+}
+
+// rows of files and what refers to them. chunks go through `Storage`, which is the repository of
+// their own. like there, connection is given to every call so that callers can run them in one
+// transaction, and busy database is retried inside.
+pub trait FileRepo: Send + Sync {
+    // returns id of inserted file
+    fn insert(&self, conn: &Connection, file: &NewFile) -> rusqlite::Result<i64>;
+
+    fn get(&self, conn: &Connection, id: i64, now: i64) -> rusqlite::Result<Option<FileRecord>>;
+
+    fn id_of_slug(&self, conn: &Connection, slug: &str) -> rusqlite::Result<Option<i64>>;
+
+    // outer none if file does not exist, inner none if it was uploaded before slugs
+    fn slug(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<Option<String>>>;
+
+    fn upload_state(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<UploadState>>;
+
+    fn download_state(
+        &self,
+        conn: &Connection,
+        id: i64,
+        now: i64,
+    ) -> rusqlite::Result<Option<DownloadState>>;
+
+    fn is_available(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<bool>>;
+
+    fn set_available(&self, conn: &Connection, id: i64, available: bool) -> rusqlite::Result<()>;
+
+    fn set_last_seq(&self, conn: &Connection, id: i64, last_seq: i64) -> rusqlite::Result<()>;
+
+    // outer none if file does not exist, inner none if it was uploaded before deletion tokens
+    fn deletion_token_hash(
+        &self,
+        conn: &Connection,
+        id: i64,
+    ) -> rusqlite::Result<Option<Option<Vec<u8>>>>;
+
+    // count download unless file was downloaded as many times as allowed, in which case none is
+    // returned. otherwise returns whether this was the last download allowed.
+    fn count_download(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<bool>>;
+
+    // sealed response of earlier prepare with same idempotency key, looked up by hash of key
+    fn idempotent_response(
+        &self,
+        conn: &Connection,
+        key_hash: &[u8],
+    ) -> rusqlite::Result<Option<Vec<u8>>>;
+
+    fn remember_idempotency_key(
+        &self,
+        conn: &Connection,
+        key_hash: &[u8],
+        id: i64,
+        response: &[u8],
+        now: i64,
+    ) -> rusqlite::Result<()>;
+
+    // forget idempotency keys created at or before given time
+    fn forget_idempotency_keys(&self, conn: &Connection, before: i64) -> rusqlite::Result<()>;
+
+    fn issue_download_ticket(
+        &self,
+        conn: &Connection,
+        id: i64,
+        ticket_hash: &[u8],
+        now: i64,
+    ) -> rusqlite::Result<()>;
+
+    // use up ticket of file issued after given time. returns whether there was one
+    fn take_download_ticket(
+        &self,
+        conn: &Connection,
+        id: i64,
+        ticket_hash: &[u8],
+        issued_after: i64,
+    ) -> rusqlite::Result<bool>;
+
+    // forget download tickets issued at or before given time
+    fn forget_download_tickets(&self, conn: &Connection, before: i64) -> rusqlite::Result<()>;
+
+    // delete row of file and rows referring to it, but not its chunks. returns number of deleted
+    // file rows, which is 0 if file does not exist or is not `deletable`
+    fn delete(&self, conn: &Connection, id: i64, deletable: Deletable) -> rusqlite::Result<usize>;
+
+    // ids of files past expiry at given time, with their `expires_at`, earliest first. files on
+    // legal hold do not expire
+    fn expired(&self, conn: &Connection, at: i64) -> rusqlite::Result<Vec<(i64, i64)>>;
+
+    // ids of incomplete uploads prepared at or before `prepared_before`, which are not expired
+    fn abandoned(
+        &self,
+        conn: &Connection,
+        now: i64,
+        prepared_before: i64,
+    ) -> rusqlite::Result<Vec<i64>>;
+
+    // give files uploaded before `expires_at` was introduced expiry from their creation time.
+    // returns number of files given one
+    fn backfill_expiry(&self, conn: &Connection, expiry: i64) -> rusqlite::Result<usize>;
+
+    fn legal_hold(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<bool>>;
+
+    // returns false if file does not exist
+    fn set_legal_hold(&self, conn: &Connection, id: i64, hold: bool) -> rusqlite::Result<bool>;
+
+    // none if file does not exist or is expired
+    fn upload_target(
+        &self,
+        conn: &Connection,
+        id: i64,
+        now: i64,
+    ) -> rusqlite::Result<Option<UploadTarget>>;
+
+    // files uploaded with issued API key after given time
+    fn count_uploads_of_key(
+        &self,
+        conn: &Connection,
+        api_key_id: i64,
+        since: i64,
+    ) -> rusqlite::Result<i64>;
+
+    // incomplete uploads which are not expired, oldest first
+    fn pending_uploads(&self, conn: &Connection, now: i64) -> rusqlite::Result<Vec<PendingUpload>>;
+
+    // every file, newest first
+    fn list(
+        &self,
+        conn: &Connection,
+        limit: i64,
+        offset: i64,
+    ) -> rusqlite::Result<Vec<FileSummary>>;
+
+    fn counts(&self, conn: &Connection, now: i64) -> rusqlite::Result<FileCounts>;
+}
+
+// API key issued by admin API, with limits on what its holder may upload. keys given in config
+// have no such record and no limits.
+pub struct ApiKey {
+    pub id: i64,
+    pub max_size: Option<i64>,
+    pub max_files_per_day: Option<i64>,
+    pub min_expiry: Option<i64>,
+    pub max_expiry: Option<i64>,
+}
+
+// scopes and validity window of new API key. every limit is optional, and validity bounds are
+// unix timestamps
+#[derive(Deserialize)]
+pub struct NewApiKey {
+    pub label: Option<String>,
+    pub max_size: Option<i64>,
+    pub max_files_per_day: Option<i64>,
+    pub min_expiry: Option<i64>,
+    pub max_expiry: Option<i64>,
+    pub valid_from: Option<i64>,
+    pub valid_until: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct IssuedApiKey {
+    pub id: i64,
+    pub created_at: i64,
+    pub label: Option<String>,
+    pub max_size: Option<i64>,
+    pub max_files_per_day: Option<i64>,
+    pub min_expiry: Option<i64>,
+    pub max_expiry: Option<i64>,
+    pub valid_from: Option<i64>,
+    pub valid_until: Option<i64>,
+    pub revoked: bool,
+}
+
+#[derive(Serialize)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub created_at: i64,
+    pub action: String,
+    pub file_id: i64,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BandwidthUsage {
+    pub tenant: String,
+    pub month: String,
+    pub uploaded_bytes: i64,
+    pub downloaded_bytes: i64,
+}
+
+#[derive(Serialize)]
+pub struct StorageDay {
+    // `YYYY-MM-DD` in UTC
+    pub day: String,
+    // bytes of chunks stored as of last snapshot of the day. days when only deletions were
+    // recorded have none
+    pub stored_bytes: Option<i64>,
+    pub deleted_files: i64,
+    pub deleted_bytes: i64,
+    #[serde(skip)]
+    pub days_since_epoch: i64,
+}
+
+#[derive(Serialize)]
+pub struct ClientErrorCount {
+    pub kind: String,
+    pub app_version: String,
+    pub browser: String,
+    pub count: i64,
+    pub last_seen: i64,
+}
+
+// rows about this instance rather than single files: API keys, audit log of admin API, and
+// accounting of bandwidth, storage and client errors. connection is given to every call like in
+// `FileRepo`.
+pub trait InstanceRepo: Send + Sync {
+    // whether any issued key is still usable at given time
+    fn any_api_key_usable(&self, conn: &Connection, now: i64) -> rusqlite::Result<bool>;
+
+    // issued key of given hash, if it is usable at given time
+    fn api_key(
+        &self,
+        conn: &Connection,
+        key_hash: &[u8],
+        now: i64,
+    ) -> rusqlite::Result<Option<ApiKey>>;
+
+    fn api_keys(&self, conn: &Connection) -> rusqlite::Result<Vec<IssuedApiKey>>;
+
+    // returns id of inserted key
+    fn insert_api_key(
+        &self,
+        conn: &Connection,
+        key: &NewApiKey,
+        key_hash: &[u8],
+    ) -> rusqlite::Result<i64>;
+
+    // returns false if key does not exist
+    fn revoke_api_key(&self, conn: &Connection, id: i64) -> rusqlite::Result<bool>;
+
+    fn audit(
+        &self,
+        conn: &Connection,
+        action: &str,
+        file_id: i64,
+        reason: Option<&str>,
+    ) -> rusqlite::Result<()>;
+
+    fn audit_log(&self, conn: &Connection) -> rusqlite::Result<Vec<AuditLogEntry>>;
+
+    // bytes added to monthly totals of uploader of file. unknown file is not accounted
+    fn add_bandwidth(
+        &self,
+        conn: &Connection,
+        file_id: i64,
+        now: i64,
+        uploaded: i64,
+        downloaded: i64,
+    ) -> rusqlite::Result<()>;
+
+    // monthly totals of every uploader, or only of given month in `YYYY-MM` form
+    fn bandwidth(
+        &self,
+        conn: &Connection,
+        month: Option<&str>,
+    ) -> rusqlite::Result<Vec<BandwidthUsage>>;
+
+    // overwrites earlier snapshot of the same day
+    fn record_stored_bytes(
+        &self,
+        conn: &Connection,
+        now: i64,
+        stored_bytes: i64,
+    ) -> rusqlite::Result<()>;
+
+    // added to totals of the day
+    fn record_deletion(
+        &self,
+        conn: &Connection,
+        now: i64,
+        files: i64,
+        bytes: i64,
+    ) -> rusqlite::Result<()>;
+
+    // days after given time, oldest first
+    fn storage_history(&self, conn: &Connection, after: i64) -> rusqlite::Result<Vec<StorageDay>>;
+
+    // forget days before given time
+    fn forget_storage_history(&self, conn: &Connection, before: i64) -> rusqlite::Result<()>;
+
+    fn insert_client_error(
+        &self,
+        conn: &Connection,
+        kind: &str,
+        app_version: &str,
+        browser: &str,
+    ) -> rusqlite::Result<()>;
+
+    // forget reports made before given time, and oldest ones over `max_reports`
+    fn forget_client_errors(
+        &self,
+        conn: &Connection,
+        before: i64,
+        max_reports: i64,
+    ) -> rusqlite::Result<()>;
+
+    // reports counted per kind, app version and browser, most frequent first
+    fn client_error_counts(&self, conn: &Connection) -> rusqlite::Result<Vec<ClientErrorCount>>;
+
+    // random secret of this instance stored under given name. `fresh` is stored on first call,
+    // and later calls return it, so that secret stays same across restarts.
+    fn secret(&self, conn: &Connection, name: &str, fresh: &[u8]) -> rusqlite::Result<Vec<u8>>;
+}
@@ -0,0 +1,808 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{
+    ApiKey, AuditLogEntry, BandwidthUsage, ClientErrorCount, Deletable, DownloadState, FileCounts,
+    FileRecord, FileRepo, FileSummary, InstanceRepo, IssuedApiKey, NewApiKey, NewFile,
+    PendingUpload, StorageDay, UploadState, UploadTarget,
+};
+use crate::db;
+
+// rows in `files` table and tables referring to it
+pub struct SqliteFileRepo;
+
+impl FileRepo for SqliteFileRepo {
+    fn insert(&self, conn: &Connection, file: &NewFile) -> rusqlite::Result<i64> {
+        let query = "insert into files (filename, salt, nonce, filename_nonce, is_text, expires_at, format_version, available_from, expected_chunks, expected_size, uploader_ip_hash, deletion_token_hash, max_downloads, upload_token_hash, api_key_id, slug) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16) returning id";
+        let mut stmt = conn.prepare(query)?;
+        db::retry(|| {
+            stmt.query_row(
+                params![
+                    &file.filename,
+                    &file.salt,
+                    &file.nonce,
+                    &file.filename_nonce,
+                    file.is_text,
+                    file.expires_at,
+                    file.format_version,
+                    file.available_from,
+                    file.expected_chunks,
+                    file.expected_size,
+                    &file.uploader_ip_hash,
+                    &file.deletion_token_hash,
+                    file.max_downloads,
+                    &file.upload_token_hash,
+                    file.api_key_id,
+                    &file.slug,
+                ],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    fn get(&self, conn: &Connection, id: i64, now: i64) -> rusqlite::Result<Option<FileRecord>> {
+        let query = "select filename, salt, nonce, filename_nonce, is_text, expires_at, format_version, available_from, available, expires_at is not null and expires_at <= ?2 and legal_hold = false, max_downloads is not null and download_count >= max_downloads, max_downloads - download_count from files where id = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        stmt.query_row(params![&id, &now], |row| {
+            Ok(FileRecord {
+                filename: row.get(0)?,
+                salt: row.get(1)?,
+                nonce: row.get(2)?,
+                filename_nonce: row.get(3)?,
+                is_text: row.get(4)?,
+                expires_at: row.get(5)?,
+                format_version: row.get(6)?,
+                available_from: row.get(7)?,
+                available: row.get(8)?,
+                expired: row.get(9)?,
+                finished: row.get::<_, bool>(9)? || row.get::<_, bool>(10)?,
+                downloads_left: row.get(11)?,
+            })
+        })
+        .optional()
+    }
+
+    fn id_of_slug(&self, conn: &Connection, slug: &str) -> rusqlite::Result<Option<i64>> {
+        let query = "select id from files where slug = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        stmt.query_row(params![&slug], |row| row.get(0)).optional()
+    }
+
+    fn slug(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<Option<String>>> {
+        let query = "select slug from files where id = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        stmt.query_row(params![&id], |row| row.get(0)).optional()
+    }
+
+    fn upload_state(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<UploadState>> {
+        let query =
+            "select expected_chunks, expected_size, last_seq, upload_token_hash from files where id = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        db::retry(|| {
+            stmt.query_row(params![&id], |row| {
+                Ok(UploadState {
+                    expected_chunks: row.get(0)?,
+                    expected_size: row.get(1)?,
+                    last_seq: row.get(2)?,
+                    upload_token_hash: row.get(3)?,
+                })
+            })
+            .optional()
+        })
+    }
+
+    fn download_state(
+        &self,
+        conn: &Connection,
+        id: i64,
+        now: i64,
+    ) -> rusqlite::Result<Option<DownloadState>> {
+        let query = "select available, available_from, coalesce(download_count >= max_downloads, false), expires_at is not null and expires_at <= ?2 and legal_hold = false from files where id = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        stmt.query_row(params![&id, &now], |row| {
+            Ok(DownloadState {
+                available: row.get(0)?,
+                available_from: row.get(1)?,
+                downloads_exhausted: row.get(2)?,
+                expired: row.get(3)?,
+            })
+        })
+        .optional()
+    }
+
+    fn is_available(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<bool>> {
+        let query = "select available from files where id = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        stmt.query_row(params![&id], |row| row.get(0)).optional()
+    }
+
+    fn set_available(&self, conn: &Connection, id: i64, available: bool) -> rusqlite::Result<()> {
+        let query = "update files set available = ?2 where id = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        db::retry(|| stmt.execute(params![&id, &available]))?;
+        Ok(())
+    }
+
+    fn set_last_seq(&self, conn: &Connection, id: i64, last_seq: i64) -> rusqlite::Result<()> {
+        let query = "update files set last_seq = ?2 where id = ?1";
+        let mut stmt = conn.prepare_cached(query)?;
+        db::retry(|| stmt.execute(params![&id, &last_seq]))?;
+        Ok(())
+    }
+
+    fn deletion_token_hash(
+        &self,
+        conn: &Connection,
+        id: i64,
+    ) -> rusqlite::Result<Option<Option<Vec<u8>>>> {
+        let query = "select deletion_token_hash from files where id = ?1";
+        conn.query_row(query, params![&id], |row| row.get(0))
+            .optional()
+    }
+
+    fn count_download(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<bool>> {
+        let query = "update files set download_count = download_count + 1 where id = ?1 and (max_downloads is null or download_count < max_downloads) returning coalesce(download_count >= max_downloads, false)";
+        let mut stmt = conn.prepare_cached(query)?;
+        db::retry(|| stmt.query_row(params![&id], |row| row.get(0)).optional())
+    }
+
+    fn idempotent_response(
+        &self,
+        conn: &Connection,
+        key_hash: &[u8],
+    ) -> rusqlite::Result<Option<Vec<u8>>> {
+        let query = "select response from upload_idempotency_keys where key_hash = ?1";
+        db::retry(|| {
+            conn.query_row(query, params![&key_hash], |row| row.get(0))
+                .optional()
+        })
+    }
+
+    fn remember_idempotency_key(
+        &self,
+        conn: &Connection,
+        key_hash: &[u8],
+        id: i64,
+        response: &[u8],
+        now: i64,
+    ) -> rusqlite::Result<()> {
+        let query =
+            "insert into upload_idempotency_keys (key_hash, file_id, response, created_at) \
+             values (?1, ?2, ?3, ?4)";
+        db::retry(|| conn.execute(query, params![&key_hash, &id, &response, &now]))?;
+        Ok(())
+    }
+
+    fn forget_idempotency_keys(&self, conn: &Connection, before: i64) -> rusqlite::Result<()> {
+        let query = "delete from upload_idempotency_keys where created_at <= ?1";
+        db::retry(|| conn.execute(query, params![&before]))?;
+        Ok(())
+    }
+
+    fn issue_download_ticket(
+        &self,
+        conn: &Connection,
+        id: i64,
+        ticket_hash: &[u8],
+        now: i64,
+    ) -> rusqlite::Result<()> {
+        let query =
+            "insert into download_tickets (ticket_hash, file_id, created_at) values (?1, ?2, ?3)";
+        db::retry(|| conn.execute(query, params![&ticket_hash, &id, &now]))?;
+        Ok(())
+    }
+
+    fn take_download_ticket(
+        &self,
+        conn: &Connection,
+        id: i64,
+        ticket_hash: &[u8],
+        issued_after: i64,
+    ) -> rusqlite::Result<bool> {
+        let query = "delete from download_tickets \
+                     where ticket_hash = ?1 and file_id = ?2 and created_at > ?3";
+        let deleted = db::retry(|| conn.execute(query, params![&ticket_hash, &id, &issued_after]))?;
+        Ok(deleted > 0)
+    }
+
+    fn forget_download_tickets(&self, conn: &Connection, before: i64) -> rusqlite::Result<()> {
+        let query = "delete from download_tickets where created_at <= ?1";
+        db::retry(|| conn.execute(query, params![&before]))?;
+        Ok(())
+    }
+
+    fn delete(&self, conn: &Connection, id: i64, deletable: Deletable) -> rusqlite::Result<usize> {
+        let query = match deletable {
+            Deletable::NotHeld => "delete from files where id = ?1 and legal_hold = false",
+            Deletable::IncompleteNotHeld => {
+                "delete from files where id = ?1 and available = false and legal_hold = false"
+            }
+            Deletable::Incomplete => "delete from files where id = ?1 and available = false",
+        };
+        let deleted = db::retry(|| conn.execute(query, params![&id]))?;
+        if deleted == 0 {
+            return Ok(0);
+        }
+
+        let query = "delete from upload_idempotency_keys where file_id = ?1";
+        db::retry(|| conn.execute(query, params![&id]))?;
+        let query = "delete from download_tickets where file_id = ?1";
+        db::retry(|| conn.execute(query, params![&id]))?;
+        Ok(deleted)
+    }
+
+    fn expired(&self, conn: &Connection, at: i64) -> rusqlite::Result<Vec<(i64, i64)>> {
+        let query = "select id, expires_at from files where expires_at is not null and expires_at <= ?1 and legal_hold = false order by expires_at, id";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map(params![&at], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    fn abandoned(
+        &self,
+        conn: &Connection,
+        now: i64,
+        prepared_before: i64,
+    ) -> rusqlite::Result<Vec<i64>> {
+        let query = "select id from files where available = false and legal_hold = false and (expires_at is null or expires_at > ?1) and unixepoch(created_at) <= ?2";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map(params![&now, &prepared_before], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    fn backfill_expiry(&self, conn: &Connection, expiry: i64) -> rusqlite::Result<usize> {
+        let query =
+            "update files set expires_at = unixepoch(created_at) + ?1 where expires_at is null";
+        db::retry(|| conn.execute(query, params![&expiry]))
+    }
+
+    fn legal_hold(&self, conn: &Connection, id: i64) -> rusqlite::Result<Option<bool>> {
+        let query = "select legal_hold from files where id = ?1";
+        conn.query_row(query, params![&id], |row| row.get(0))
+            .optional()
+    }
+
+    fn set_legal_hold(&self, conn: &Connection, id: i64, hold: bool) -> rusqlite::Result<bool> {
+        let query = "update files set legal_hold = ?2 where id = ?1";
+        let updated = db::retry(|| conn.execute(query, params![&id, &hold]))?;
+        Ok(updated > 0)
+    }
+
+    fn upload_target(
+        &self,
+        conn: &Connection,
+        id: i64,
+        now: i64,
+    ) -> rusqlite::Result<Option<UploadTarget>> {
+        let query = "select coalesce(expected_chunks, last_seq), expected_size, available from files where id = ?1 and (expires_at is null or expires_at > ?2)";
+        let mut stmt = conn.prepare_cached(query)?;
+        stmt.query_row(params![&id, &now], |row| {
+            Ok(UploadTarget {
+                expected_chunks: row.get(0)?,
+                expected_size: row.get(1)?,
+                complete: row.get(2)?,
+            })
+        })
+        .optional()
+    }
+
+    fn count_uploads_of_key(
+        &self,
+        conn: &Connection,
+        api_key_id: i64,
+        since: i64,
+    ) -> rusqlite::Result<i64> {
+        let query =
+            "select count(*) from files where api_key_id = ?1 and unixepoch(created_at) > ?2";
+        db::retry(|| conn.query_row(query, params![&api_key_id, &since], |row| row.get(0)))
+    }
+
+    fn pending_uploads(&self, conn: &Connection, now: i64) -> rusqlite::Result<Vec<PendingUpload>> {
+        // expired files are unavailable too, but they have no chunks left and are not uploading
+        let query = "select id, ?1 - unixepoch(created_at), uploader_ip_hash from files where available = false and (expires_at is null or expires_at > ?1) order by id";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map(params![&now], |row| {
+            Ok(PendingUpload {
+                id: row.get(0)?,
+                age_seconds: row.get(1)?,
+                uploader_ip_hash: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn list(
+        &self,
+        conn: &Connection,
+        limit: i64,
+        offset: i64,
+    ) -> rusqlite::Result<Vec<FileSummary>> {
+        let query = "select id, unixepoch(created_at), expires_at, is_text, available, download_count, max_downloads, legal_hold, uploader_ip_hash from files order by id desc limit ?1 offset ?2";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map(params![&limit, &offset], |row| {
+            Ok(FileSummary {
+                id: row.get(0)?,
+                created_at: row.get(1)?,
+                expires_at: row.get(2)?,
+                is_text: row.get(3)?,
+                available: row.get(4)?,
+                download_count: row.get(5)?,
+                max_downloads: row.get(6)?,
+                legal_hold: row.get(7)?,
+                uploader_ip_hash: row.get(8)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn counts(&self, conn: &Connection, now: i64) -> rusqlite::Result<FileCounts> {
+        let query = "select count(*), coalesce(sum(available), 0), coalesce(sum(not available and (expires_at is null or expires_at > ?1)), 0), coalesce(sum(legal_hold), 0) from files";
+        conn.query_row(query, params![&now], |row| {
+            Ok(FileCounts {
+                files: row.get(0)?,
+                available: row.get(1)?,
+                incomplete: row.get(2)?,
+                legal_holds: row.get(3)?,
+            })
+        })
+    }
+}
+
+// rows in tables of this instance which do not belong to single file
+pub struct SqliteInstanceRepo;
+
+impl InstanceRepo for SqliteInstanceRepo {
+    fn any_api_key_usable(&self, conn: &Connection, now: i64) -> rusqlite::Result<bool> {
+        let query = "select exists (select 1 from api_keys where revoked = false and (valid_until is null or valid_until > ?1))";
+        conn.query_row(query, params![&now], |row| row.get(0))
+    }
+
+    fn api_key(
+        &self,
+        conn: &Connection,
+        key_hash: &[u8],
+        now: i64,
+    ) -> rusqlite::Result<Option<ApiKey>> {
+        let query = "select id, max_size, max_files_per_day, min_expiry, max_expiry from api_keys where key_hash = ?1 and revoked = false and (valid_from is null or valid_from <= ?2) and (valid_until is null or valid_until > ?2)";
+        conn.query_row(query, params![&key_hash, &now], |row| {
+            Ok(ApiKey {
+                id: row.get(0)?,
+                max_size: row.get(1)?,
+                max_files_per_day: row.get(2)?,
+                min_expiry: row.get(3)?,
+                max_expiry: row.get(4)?,
+            })
+        })
+        .optional()
+    }
+
+    fn api_keys(&self, conn: &Connection) -> rusqlite::Result<Vec<IssuedApiKey>> {
+        let query = "select id, unixepoch(created_at), label, max_size, max_files_per_day, min_expiry, max_expiry, valid_from, valid_until, revoked from api_keys order by id";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map([], |row| {
+            Ok(IssuedApiKey {
+                id: row.get(0)?,
+                created_at: row.get(1)?,
+                label: row.get(2)?,
+                max_size: row.get(3)?,
+                max_files_per_day: row.get(4)?,
+                min_expiry: row.get(5)?,
+                max_expiry: row.get(6)?,
+                valid_from: row.get(7)?,
+                valid_until: row.get(8)?,
+                revoked: row.get(9)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn insert_api_key(
+        &self,
+        conn: &Connection,
+        key: &NewApiKey,
+        key_hash: &[u8],
+    ) -> rusqlite::Result<i64> {
+        let query = "insert into api_keys (label, key_hash, max_size, max_files_per_day, min_expiry, max_expiry, valid_from, valid_until) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8) returning id";
+        db::retry(|| {
+            conn.query_row(
+                query,
+                params![
+                    &key.label,
+                    &key_hash,
+                    &key.max_size,
+                    &key.max_files_per_day,
+                    &key.min_expiry,
+                    &key.max_expiry,
+                    &key.valid_from,
+                    &key.valid_until,
+                ],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    fn revoke_api_key(&self, conn: &Connection, id: i64) -> rusqlite::Result<bool> {
+        let query = "update api_keys set revoked = true where id = ?1";
+        let updated = db::retry(|| conn.execute(query, params![&id]))?;
+        Ok(updated > 0)
+    }
+
+    fn audit(
+        &self,
+        conn: &Connection,
+        action: &str,
+        file_id: i64,
+        reason: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let query = "insert into admin_audit_log (action, file_id, reason) values (?1, ?2, ?3)";
+        db::retry(|| conn.execute(query, params![&action, &file_id, &reason]))?;
+        Ok(())
+    }
+
+    fn audit_log(&self, conn: &Connection) -> rusqlite::Result<Vec<AuditLogEntry>> {
+        let query = "select id, unixepoch(created_at), action, file_id, reason from admin_audit_log order by id";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map([], |row| {
+            Ok(AuditLogEntry {
+                id: row.get(0)?,
+                created_at: row.get(1)?,
+                action: row.get(2)?,
+                file_id: row.get(3)?,
+                reason: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn add_bandwidth(
+        &self,
+        conn: &Connection,
+        file_id: i64,
+        now: i64,
+        uploaded: i64,
+        downloaded: i64,
+    ) -> rusqlite::Result<()> {
+        let query = "insert into bandwidth_usage (tenant, month, uploaded_bytes, downloaded_bytes) select coalesce(uploader_ip_hash, 'unknown'), strftime('%Y-%m', ?2, 'unixepoch'), ?3, ?4 from files where id = ?1 on conflict (tenant, month) do update set uploaded_bytes = uploaded_bytes + excluded.uploaded_bytes, downloaded_bytes = downloaded_bytes + excluded.downloaded_bytes";
+        let mut stmt = conn.prepare_cached(query)?;
+        db::retry(|| stmt.execute(params![&file_id, &now, &uploaded, &downloaded]))?;
+        Ok(())
+    }
+
+    fn bandwidth(
+        &self,
+        conn: &Connection,
+        month: Option<&str>,
+    ) -> rusqlite::Result<Vec<BandwidthUsage>> {
+        let query = "select tenant, month, uploaded_bytes, downloaded_bytes from bandwidth_usage where ?1 is null or month = ?1 order by month, tenant";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map(params![&month], |row| {
+            Ok(BandwidthUsage {
+                tenant: row.get(0)?,
+                month: row.get(1)?,
+                uploaded_bytes: row.get(2)?,
+                downloaded_bytes: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn record_stored_bytes(
+        &self,
+        conn: &Connection,
+        now: i64,
+        stored_bytes: i64,
+    ) -> rusqlite::Result<()> {
+        let query = "insert into storage_history (day, stored_bytes) values (strftime('%Y-%m-%d', ?1, 'unixepoch'), ?2) on conflict (day) do update set stored_bytes = excluded.stored_bytes";
+        db::retry(|| conn.execute(query, params![&now, &stored_bytes]))?;
+        Ok(())
+    }
+
+    fn record_deletion(
+        &self,
+        conn: &Connection,
+        now: i64,
+        files: i64,
+        bytes: i64,
+    ) -> rusqlite::Result<()> {
+        let query = "insert into storage_history (day, deleted_files, deleted_bytes) values (strftime('%Y-%m-%d', ?1, 'unixepoch'), ?2, ?3) on conflict (day) do update set deleted_files = deleted_files + excluded.deleted_files, deleted_bytes = deleted_bytes + excluded.deleted_bytes";
+        db::retry(|| conn.execute(query, params![&now, &files, &bytes]))?;
+        Ok(())
+    }
+
+    fn storage_history(&self, conn: &Connection, after: i64) -> rusqlite::Result<Vec<StorageDay>> {
+        let query = "select day, stored_bytes, deleted_files, deleted_bytes, unixepoch(day) / 86400 from storage_history where day > strftime('%Y-%m-%d', ?1, 'unixepoch') order by day";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map(params![&after], |row| {
+            Ok(StorageDay {
+                day: row.get(0)?,
+                stored_bytes: row.get(1)?,
+                deleted_files: row.get(2)?,
+                deleted_bytes: row.get(3)?,
+                days_since_epoch: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn forget_storage_history(&self, conn: &Connection, before: i64) -> rusqlite::Result<()> {
+        let query = "delete from storage_history where day < strftime('%Y-%m-%d', ?1, 'unixepoch')";
+        db::retry(|| conn.execute(query, params![&before]))?;
+        Ok(())
+    }
+
+    fn insert_client_error(
+        &self,
+        conn: &Connection,
+        kind: &str,
+        app_version: &str,
+        browser: &str,
+    ) -> rusqlite::Result<()> {
+        let query = "insert into client_errors (kind, app_version, browser) values (?1, ?2, ?3)";
+        db::retry(|| conn.execute(query, params![&kind, &app_version, &browser]))?;
+        Ok(())
+    }
+
+    fn forget_client_errors(
+        &self,
+        conn: &Connection,
+        before: i64,
+        max_reports: i64,
+    ) -> rusqlite::Result<()> {
+        let query = "delete from client_errors where created_at < datetime(?1, 'unixepoch')";
+        db::retry(|| conn.execute(query, params![&before]))?;
+        let query = "delete from client_errors where id <= (select id from client_errors order by id desc limit 1 offset ?1)";
+        db::retry(|| conn.execute(query, params![&max_reports]))?;
+        Ok(())
+    }
+
+    fn client_error_counts(&self, conn: &Connection) -> rusqlite::Result<Vec<ClientErrorCount>> {
+        let query = "select kind, app_version, browser, count(*), max(unixepoch(created_at)) from client_errors group by kind, app_version, browser order by count(*) desc, kind";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ClientErrorCount {
+                kind: row.get(0)?,
+                app_version: row.get(1)?,
+                browser: row.get(2)?,
+                count: row.get(3)?,
+                last_seen: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn secret(&self, conn: &Connection, name: &str, fresh: &[u8]) -> rusqlite::Result<Vec<u8>> {
+        let query = "insert into instance_secrets (name, secret) values (?1, ?2) on conflict (name) do nothing";
+        db::retry(|| conn.execute(query, params![&name, &fresh]))?;
+        let query = "select secret from instance_secrets where name = ?1";
+        conn.query_row(query, params![&name], |row| row.get(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn test_conn() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../../schema.sql"))
+            .unwrap();
+        migrations::run(&mut conn).unwrap();
+        conn
+    }
+
+    fn new_file(max_downloads: Option<i64>, expires_at: Option<i64>) -> NewFile {
+        NewFile {
+            slug: "slug".to_owned(),
+            filename: b"filename".to_vec(),
+            salt: vec![0; 32],
+            nonce: vec![0; 19],
+            filename_nonce: vec![0; 24],
+            is_text: false,
+            expires_at,
+            format_version: 1,
+            available_from: None,
+            expected_chunks: Some(2),
+            expected_size: Some(100),
+            uploader_ip_hash: "hash".into(),
+            deletion_token_hash: b"deletion".to_vec(),
+            max_downloads,
+            upload_token_hash: b"upload".to_vec(),
+            api_key_id: None,
+        }
+    }
+
+    #[test]
+    fn inserted_file_is_published_and_finished() {
+        let conn = test_conn();
+        let repo = SqliteFileRepo;
+        let id = repo.insert(&conn, &new_file(None, Some(100))).unwrap();
+
+        let file = repo.get(&conn, id, 50).unwrap().unwrap();
+        assert_eq!(file.filename, b"filename");
+        assert!(!file.available);
+        assert!(!file.finished);
+        assert!(repo.get(&conn, id, 100).unwrap().unwrap().finished);
+        assert_eq!(file.downloads_left, None);
+        assert!(repo.get(&conn, id + 1, 50).unwrap().is_none());
+        assert_eq!(repo.id_of_slug(&conn, "slug").unwrap(), Some(id));
+        assert_eq!(repo.id_of_slug(&conn, "other").unwrap(), None);
+
+        assert!(
+            !repo
+                .download_state(&conn, id, 50)
+                .unwrap()
+                .unwrap()
+                .available
+        );
+
+        let state = repo.upload_state(&conn, id).unwrap().unwrap();
+        assert_eq!(state.expected_chunks, Some(2));
+        assert_eq!(state.last_seq, None);
+        assert_eq!(state.upload_token_hash.as_deref(), Some(&b"upload"[..]));
+
+        repo.set_last_seq(&conn, id, 2).unwrap();
+        repo.set_available(&conn, id, true).unwrap();
+        assert_eq!(
+            repo.upload_state(&conn, id).unwrap().unwrap().last_seq,
+            Some(2)
+        );
+        assert_eq!(repo.is_available(&conn, id).unwrap(), Some(true));
+
+        // available files are expired past expiry, before expiry worker hides them
+        assert!(!repo.download_state(&conn, id, 50).unwrap().unwrap().expired);
+        assert!(
+            repo.download_state(&conn, id, 100)
+                .unwrap()
+                .unwrap()
+                .expired
+        );
+        assert!(repo.get(&conn, id, 100).unwrap().unwrap().expired);
+    }
+
+    #[test]
+    fn downloads_are_counted_up_to_limit() {
+        let conn = test_conn();
+        let repo = SqliteFileRepo;
+        let id = repo.insert(&conn, &new_file(Some(2), None)).unwrap();
+
+        assert_eq!(
+            repo.get(&conn, id, 0).unwrap().unwrap().downloads_left,
+            Some(2)
+        );
+        assert_eq!(repo.count_download(&conn, id).unwrap(), Some(false));
+        assert_eq!(repo.count_download(&conn, id).unwrap(), Some(true));
+        assert_eq!(repo.count_download(&conn, id).unwrap(), None);
+        let state = repo.download_state(&conn, id, 0).unwrap().unwrap();
+        assert!(state.downloads_exhausted);
+    }
+
+    #[test]
+    fn idempotency_keys_are_forgotten_after_window() {
+        let conn = test_conn();
+        let repo = SqliteFileRepo;
+        let id = repo.insert(&conn, &new_file(None, None)).unwrap();
+
+        repo.remember_idempotency_key(&conn, b"hash", id, b"response", 10)
+            .unwrap();
+        let response = repo.idempotent_response(&conn, b"hash").unwrap();
+        assert_eq!(response.as_deref(), Some(&b"response"[..]));
+
+        repo.forget_idempotency_keys(&conn, 9).unwrap();
+        assert!(repo.idempotent_response(&conn, b"hash").unwrap().is_some());
+        repo.forget_idempotency_keys(&conn, 10).unwrap();
+        assert_eq!(repo.idempotent_response(&conn, b"hash").unwrap(), None);
+    }
+
+    #[test]
+    fn download_ticket_is_taken_once_while_fresh() {
+        let conn = test_conn();
+        let repo = SqliteFileRepo;
+        let id = repo.insert(&conn, &new_file(None, None)).unwrap();
+
+        repo.issue_download_ticket(&conn, id, b"old", 10).unwrap();
+        repo.issue_download_ticket(&conn, id, b"new", 20).unwrap();
+        assert!(!repo
+            .take_download_ticket(&conn, id + 1, b"new", 15)
+            .unwrap());
+        assert!(!repo.take_download_ticket(&conn, id, b"old", 15).unwrap());
+        assert!(repo.take_download_ticket(&conn, id, b"new", 15).unwrap());
+        assert!(!repo.take_download_ticket(&conn, id, b"new", 15).unwrap());
+
+        repo.forget_download_tickets(&conn, 10).unwrap();
+        assert!(!repo.take_download_ticket(&conn, id, b"old", 0).unwrap());
+    }
+
+    #[test]
+    fn files_are_deleted_only_if_deletable() {
+        let conn = test_conn();
+        let repo = SqliteFileRepo;
+        let id = repo.insert(&conn, &new_file(None, None)).unwrap();
+        repo.remember_idempotency_key(&conn, b"hash", id, b"response", 10)
+            .unwrap();
+
+        assert!(repo.set_legal_hold(&conn, id, true).unwrap());
+        assert!(!repo.set_legal_hold(&conn, id + 1, true).unwrap());
+        assert_eq!(repo.legal_hold(&conn, id).unwrap(), Some(true));
+        assert_eq!(repo.delete(&conn, id, Deletable::NotHeld).unwrap(), 0);
+        assert_eq!(
+            repo.delete(&conn, id, Deletable::IncompleteNotHeld)
+                .unwrap(),
+            0
+        );
+
+        repo.set_legal_hold(&conn, id, false).unwrap();
+        repo.set_available(&conn, id, true).unwrap();
+        assert_eq!(repo.delete(&conn, id, Deletable::Incomplete).unwrap(), 0);
+        assert_eq!(repo.delete(&conn, id, Deletable::NotHeld).unwrap(), 1);
+        assert_eq!(repo.legal_hold(&conn, id).unwrap(), None);
+        assert_eq!(repo.idempotent_response(&conn, b"hash").unwrap(), None);
+    }
+
+    #[test]
+    fn expired_and_abandoned_files_are_found() {
+        let conn = test_conn();
+        let repo = SqliteFileRepo;
+        let file = |slug: &str, expires_at| NewFile {
+            slug: slug.to_owned(),
+            ..new_file(None, expires_at)
+        };
+        let later = repo.insert(&conn, &file("later", Some(200))).unwrap();
+        let sooner = repo.insert(&conn, &file("sooner", Some(100))).unwrap();
+        let never = repo.insert(&conn, &file("never", None)).unwrap();
+
+        assert_eq!(repo.expired(&conn, 150).unwrap(), vec![(sooner, 100)]);
+        assert_eq!(
+            repo.expired(&conn, 200).unwrap(),
+            vec![(sooner, 100), (later, 200)]
+        );
+        repo.set_legal_hold(&conn, sooner, true).unwrap();
+        assert_eq!(repo.expired(&conn, 200).unwrap(), vec![(later, 200)]);
+
+        // expired uploads are left for expiry worker, and those on legal hold are kept
+        let mut abandoned = repo.abandoned(&conn, 150, i64::MAX).unwrap();
+        abandoned.sort_unstable();
+        assert_eq!(abandoned, vec![later, never]);
+        assert!(repo.abandoned(&conn, 150, 0).unwrap().is_empty());
+
+        assert_eq!(repo.backfill_expiry(&conn, 60).unwrap(), 1);
+        assert!(repo
+            .get(&conn, never, 0)
+            .unwrap()
+            .unwrap()
+            .expires_at
+            .is_some());
+        let counts = repo.counts(&conn, 150).unwrap();
+        assert_eq!((counts.files, counts.legal_holds), (3, 1));
+    }
+
+    #[test]
+    fn api_keys_are_usable_within_validity_until_revoked() {
+        let conn = test_conn();
+        let repo = SqliteInstanceRepo;
+        assert!(!repo.any_api_key_usable(&conn, 0).unwrap());
+
+        let key = NewApiKey {
+            label: Some("ci".to_owned()),
+            max_size: Some(10),
+            max_files_per_day: None,
+            min_expiry: None,
+            max_expiry: None,
+            valid_from: Some(100),
+            valid_until: Some(200),
+        };
+        let id = repo.insert_api_key(&conn, &key, b"hash").unwrap();
+        assert!(repo.any_api_key_usable(&conn, 150).unwrap());
+        assert!(!repo.any_api_key_usable(&conn, 200).unwrap());
+        assert!(repo.api_key(&conn, b"hash", 50).unwrap().is_none());
+        assert!(repo.api_key(&conn, b"other", 150).unwrap().is_none());
+        let found = repo.api_key(&conn, b"hash", 150).unwrap().unwrap();
+        assert_eq!((found.id, found.max_size), (id, Some(10)));
+
+        assert!(repo.revoke_api_key(&conn, id).unwrap());
+        assert!(!repo.revoke_api_key(&conn, id + 1).unwrap());
+        assert!(repo.api_key(&conn, b"hash", 150).unwrap().is_none());
+        let keys = repo.api_keys(&conn).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].revoked);
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum::{
+    body::HttpBody,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::{Log, Metadata, Record};
+use tokio::task::JoinHandle;
+
+use crate::error::ApiError;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// longest request id taken from proxy in front of server
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+// number of 5xx responses since startup
+static SERVER_ERROR_COUNT: AtomicU64 = AtomicU64::new(0);
+
+pub fn server_error_count() -> u64 {
+    SERVER_ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+tokio::task_local! {
+    // id of the request currently handled by this task
+    pub static REQUEST_ID: String;
+}
+
+fn generate_request_id() -> String {
+    let mut buf = [0u8; 6];
+    if let Err(err) = getrandom::getrandom(&mut buf) {
+        log::error!("cannot get random request id: {:?}", err);
+    }
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// id given by proxy in front of server, so that its logs and ours share the same id. only plain
+// characters are taken, as it is written to logs as is
+fn given_request_id<B>(req: &Request<B>) -> Option<String> {
+    let id = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let plain = id
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+    if id.is_empty() || id.len() > MAX_REQUEST_ID_LEN || !plain {
+        return None;
+    }
+    Some(id.to_owned())
+}
+
+// tags every request with id given in `x-request-id` header, or a short random one. it is
+// returned in the same header and in the body of error responses, and every log line written
+// while handling request carries it, so that user reports can be matched against server logs.
+pub async fn request_id<B>(req: Request<B>, next: Next<B>) -> Response {
+    let id = given_request_id(&req).unwrap_or_else(generate_request_id);
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+
+    let mut resp = REQUEST_ID.scope(id.clone(), next.run(req)).await;
+
+    let status = resp.status();
+    if status.is_server_error() {
+        SERVER_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+    if status.is_client_error() || status.is_server_error() {
+        log::error!(
+            "request failed: ref={}, {} {} -> {}",
+            id,
+            method,
+            uri,
+            status
+        );
+
+        // failures without body, e.g. unmatched route or rejected extractor, get the body every
+        // `ApiError` has. built in scope of request, so that it carries the request id
+        if resp.body().size_hint().exact() == Some(0) {
+            let error = ApiError::from(status);
+            resp = REQUEST_ID
+                .scope(id.clone(), async move { error.into_response() })
+                .await;
+        }
+    }
+
+    if let Ok(v) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, v);
+    }
+
+    resp
+}
+
+// spawn task which outlives handler of request, e.g. streaming response body, carrying request id
+// over for logs as `db::blocking` does
+pub fn spawn<F>(task: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match REQUEST_ID.try_with(|id| id.clone()) {
+        Ok(id) => tokio::spawn(REQUEST_ID.scope(id, task)),
+        Err(_) => tokio::spawn(task),
+    }
+}
+
+// logger prefixing lines with id of request being handled, if any
+pub struct Logger<L>(pub L);
+
+impl<L: Log> Log for Logger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let logged = REQUEST_ID.try_with(|id| {
+            self.0.log(
+                &Record::builder()
+                    .args(format_args!("[ref={}] {}", id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            )
+        });
+        if logged.is_err() {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn given(id: &str) -> Option<String> {
+        let req = Request::builder()
+            .header(REQUEST_ID_HEADER, id)
+            .body(())
+            .unwrap();
+        given_request_id(&req)
+    }
+
+    #[test]
+    fn only_plain_request_ids_are_taken_from_proxy() {
+        assert_eq!(given("abc-123_x.y").as_deref(), Some("abc-123_x.y"));
+        assert_eq!(given(""), None);
+        assert_eq!(given("a b"), None);
+        assert_eq!(given(&"a".repeat(MAX_REQUEST_ID_LEN + 1)), None);
+        assert_eq!(given_request_id(&Request::new(())), None);
+    }
+}
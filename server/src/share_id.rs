@@ -0,0 +1,215 @@
+use axum::http::StatusCode;
+use hmac::{Hmac, Mac, NewMac};
+use rusqlite::{ffi, Connection};
+use sha2::Sha256;
+
+use crate::{config::Config, repo::NewFile, state::State, utils};
+
+// bytes of hmac kept in share url. this only needs to make guessing other ids impractical over
+// network, not to resist offline attack
+const TAG_LEN: usize = 4;
+// slugs drawn for one file before giving up, in case short ones keep colliding
+const SLUG_ATTEMPTS: usize = 5;
+
+// random slug of configured length, drawn evenly from configured alphabet
+pub fn generate(config: &Config) -> Result<String, StatusCode> {
+    let alphabet = config.slug_alphabet.as_bytes();
+    // bytes at or above this would favor first characters of alphabet, so they are drawn again
+    let limit = 256 - 256 % alphabet.len();
+    let mut slug = String::with_capacity(config.slug_length);
+    let mut buf = [0u8; 32];
+    while slug.len() < config.slug_length {
+        if let Err(err) = getrandom::getrandom(&mut buf) {
+            log::error!("cannot get random slug: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        for b in buf.iter().filter(|b| (**b as usize) < limit) {
+            if slug.len() == config.slug_length {
+                break;
+            }
+            slug.push(alphabet[*b as usize % alphabet.len()] as char);
+        }
+    }
+    Ok(slug)
+}
+
+// insert file under fresh slug, which is its share id. slug taken already is drawn again
+pub fn insert(state: &State, conn: &Connection, file: &mut NewFile) -> Result<i64, StatusCode> {
+    for _ in 0..SLUG_ATTEMPTS {
+        file.slug = generate(&state.config)?;
+        match state.files.insert(conn, file) {
+            Ok(id) => return Ok(id),
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE =>
+            {
+                log::info!("slug is taken, drawing another one");
+            }
+            Err(err) => {
+                log::error!("failed to insert file: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    log::error!("no free slug after {} attempts", SLUG_ATTEMPTS);
+    Err(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// share id of file, which is its slug, or id formatted as before slugs if it has none
+pub fn of(state: &State, conn: &Connection, id: i64) -> Result<String, StatusCode> {
+    match state.files.slug(conn, id) {
+        Ok(Some(Some(slug))) => Ok(slug),
+        Ok(_) => Ok(format(&state.config, id)),
+        Err(err) => {
+            log::error!("failed to query slug: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// id of file from its share id. ids in format before slugs still find files uploaded back then,
+// but never files which have slug, so that counting up finds nothing new
+pub fn resolve(state: &State, conn: &Connection, s: &str) -> Result<i64, StatusCode> {
+    match state.files.id_of_slug(conn, s) {
+        Ok(Some(id)) => return Ok(id),
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("failed to query slug: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let id = parse(&state.config, s).map_err(|_| StatusCode::NOT_FOUND)?;
+    match state.files.slug(conn, id) {
+        Ok(Some(None)) => Ok(id),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            log::error!("failed to query slug: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// whether path could be share id, of either format. characters of slugs are checked by config
+pub fn is_well_formed(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn tag(key: &str, id: i64) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("hmac accepts key of any length");
+    mac.update(&id.to_be_bytes());
+    mac.finalize().into_bytes()[..TAG_LEN].to_vec()
+}
+
+// id as it appeared in share url before slugs. when share url key is configured, it is followed
+// by truncated hmac of id, e.g. `123-ab12cd34`, so that existing files cannot be found by
+// counting up
+fn format(config: &Config, id: i64) -> String {
+    match &config.share_url_key {
+        Some(key) => {
+            let tag: String = tag(key, id).iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{}-{}", id, tag)
+        }
+        None => id.to_string(),
+    }
+}
+
+// parse id from share url in format before slugs. malformed ids are bad request, and ids with
+// wrong or missing tag look like nonexistent files
+fn parse(config: &Config, s: &str) -> Result<i64, StatusCode> {
+    let (id, tag_hex) = match s.split_once('-') {
+        Some((id, tag)) => (id, Some(tag)),
+        None => (s, None),
+    };
+    let id = match id.parse::<i64>() {
+        Ok(id) if id > 0 => id,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let key = match &config.share_url_key {
+        Some(key) => key,
+        // signing is off, so tag is meaningless
+        None => return Ok(id),
+    };
+
+    let tag_hex = match tag_hex {
+        Some(tag_hex) => tag_hex,
+        None if config.accept_unsigned_ids => return Ok(id),
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+    let given = match decode_hex(tag_hex) {
+        Some(given) => given,
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    if !utils::constant_time_eq(&given, &tag(key, id)) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(id)
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use clap::Parser;
+
+    use crate::config::Config;
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(["hako"].iter().chain(args.iter()))
+    }
+
+    #[test]
+    fn signed_id_round_trips() {
+        let config = config(&["--share-url-key", "secret"]);
+        let share_id = super::format(&config, 123);
+        assert!(share_id.starts_with("123-"));
+        assert_eq!(super::parse(&config, &share_id), Ok(123));
+    }
+
+    #[test]
+    fn other_ids_are_not_found() {
+        let config = config(&["--share-url-key", "secret"]);
+        let tag = super::format(&config, 123)
+            .split_once('-')
+            .unwrap()
+            .1
+            .to_owned();
+        assert_eq!(
+            super::parse(&config, &format!("124-{}", tag)),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(super::parse(&config, "123"), Err(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn slug_is_drawn_from_alphabet() {
+        let config = config(&["--slug-length", "40", "--slug-alphabet", "ab"]);
+        let slug = super::generate(&config).unwrap();
+        assert_eq!(slug.len(), 40);
+        assert!(slug.bytes().all(|b| b == b'a' || b == b'b'));
+        assert_ne!(slug, super::generate(&config).unwrap());
+    }
+
+    #[test]
+    fn unsigned_ids_are_accepted_when_allowed() {
+        let config = config(&["--share-url-key", "secret", "--accept-unsigned-ids"]);
+        assert_eq!(super::parse(&config, "123"), Ok(123));
+
+        let config = config(&[]);
+        assert_eq!(super::parse(&config, "123"), Ok(123));
+        assert_eq!(super::format(&config, 123), "123");
+    }
+}
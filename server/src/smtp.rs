@@ -0,0 +1,139 @@
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_native_tls::{native_tls, TlsConnector};
+
+use crate::webhook::Error;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+// just enough of SMTP submission to deliver plain text notifications. STARTTLS is mandatory, as
+// both credentials and notification text would cross the network in clear otherwise.
+pub struct Relay {
+    host: String,
+    port: u16,
+    credentials: Option<Credentials>,
+    tls: TlsConnector,
+}
+
+impl Relay {
+    pub fn new(host: &str, port: u16, credentials: Option<Credentials>) -> Result<Self, Error> {
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            credentials,
+            tls: TlsConnector::from(native_tls::TlsConnector::new()?),
+        })
+    }
+
+    pub async fn send(&self, from: &str, to: &str, subject: &str, text: &str) -> Result<(), Error> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let mut conn = BufReader::new(stream);
+        expect(&mut conn, 220).await?;
+        command(&mut conn, "EHLO hako", 250).await?;
+        command(&mut conn, "STARTTLS", 220).await?;
+
+        // server says nothing more until handshake, so nothing is left in buffer
+        let stream = self.tls.connect(&self.host, conn.into_inner()).await?;
+        let mut conn = BufReader::new(stream);
+        command(&mut conn, "EHLO hako", 250).await?;
+        if let Some(credentials) = &self.credentials {
+            let token = base64::encode(format!(
+                "\0{}\0{}",
+                credentials.username, credentials.password
+            ));
+            command(&mut conn, &format!("AUTH PLAIN {}", token), 235).await?;
+        }
+        command(&mut conn, &format!("MAIL FROM:<{}>", from), 250).await?;
+        command(&mut conn, &format!("RCPT TO:<{}>", to), 250).await?;
+        command(&mut conn, "DATA", 354).await?;
+        conn.write_all(message(from, to, subject, text).as_bytes())
+            .await?;
+        command(&mut conn, ".", 250).await?;
+
+        // mail is accepted already, so failing to say goodbye does not matter
+        let _ = command(&mut conn, "QUIT", 221).await;
+        Ok(())
+    }
+}
+
+// bare address, which smtp commands and headers put inside angle brackets
+pub fn address(s: &str) -> Result<String, Error> {
+    let s = s.trim();
+    let malformed = s.is_empty()
+        || !s.contains('@')
+        || s.chars().any(|c| c.is_whitespace() || c == '<' || c == '>');
+    if malformed {
+        return Err(format!("malformed email address {:?}", s).into());
+    }
+    Ok(s.to_string())
+}
+
+// body is base64 encoded, so that it needs neither dot stuffing nor 8BITMIME for non-ascii text
+fn message(from: &str, to: &str, subject: &str, text: &str) -> String {
+    let mut out = format!(
+        "From: <{}>\r\nTo: <{}>\r\nSubject: {}\r\nMIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n",
+        from, to, subject
+    );
+    let encoded = base64::encode(text);
+    for line in encoded.as_bytes().chunks(76) {
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+async fn command<S>(conn: &mut S, line: &str, code: u16) -> Result<(), Error>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    conn.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    conn.flush().await?;
+    expect(conn, code).await
+}
+
+// read one reply, which may continue over lines like `250-...`, and check its code
+async fn expect<S: AsyncBufRead + Unpin>(conn: &mut S, code: u16) -> Result<(), Error> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if conn.read_line(&mut line).await? == 0 {
+            return Err("smtp server closed connection".into());
+        }
+        if line.as_bytes().get(3) != Some(&b'-') {
+            break;
+        }
+    }
+
+    match line.get(..3).and_then(|c| c.parse::<u16>().ok()) {
+        Some(got) if got == code => Ok(()),
+        _ => Err(format!("smtp server answered {:?}", line.trim_end()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn multiline_reply_is_read_to_last_line() {
+        let mut reply: &[u8] = b"250-smtp.example.org\r\n250-STARTTLS\r\n250 SIZE 1000\r\n";
+        assert!(expect(&mut reply, 250).await.is_ok());
+        assert!(reply.is_empty());
+
+        let mut reply: &[u8] = b"535 5.7.8 authentication failed\r\n";
+        assert!(expect(&mut reply, 235).await.is_err());
+    }
+
+    #[test]
+    fn address_must_be_bare() {
+        assert_eq!(address(" ops@example.org ").unwrap(), "ops@example.org");
+        assert!(address("Ops <ops@example.org>").is_err());
+        assert!(address("ops").is_err());
+    }
+}
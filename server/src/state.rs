@@ -1,9 +1,41 @@
-use rusqlite::Connection;
-use tokio::sync::Mutex;
+use std::{collections::HashMap, net::IpAddr, sync::atomic::AtomicI64};
 
-use crate::config::Config;
+use tokio::sync::watch;
+
+use crate::{
+    config::Config,
+    db::{ReadPool, Writer},
+    events::Events,
+    progress::Progress,
+    rate_limit::RateLimits,
+    repo::{FileRepo, InstanceRepo},
+    storage::Storage,
+    utils::Clock,
+};
 
 pub struct State {
-    pub conn: Mutex<Connection>,
+    pub conn: Writer,
+    // read-only connections for downloads and metadata, empty unless database is in WAL mode
+    pub readers: ReadPool,
+    // chunks of files. handlers and workers go through it instead of querying them directly
+    pub storage: Box<dyn Storage>,
+    // rows of files, so that handlers do not query them directly either
+    pub files: Box<dyn FileRepo>,
+    // API keys, admin audit log and accounting, which do not belong to single file
+    pub instance: Box<dyn InstanceRepo>,
     pub config: Config,
+    pub clock: Clock,
+    // keys hash of uploader addresses, so that they cannot be found by hashing every address
+    pub ip_hash_key: Vec<u8>,
+    // text uploads of each client ip today, as pair of day number and count
+    pub text_uploads: std::sync::Mutex<HashMap<IpAddr, (i64, u64)>>,
+    // unix timestamp of last successful run of expiry worker, or 0 if it has not run yet
+    pub expiry_last_run: AtomicI64,
+    pub rate_limits: RateLimits,
+    // broadcasts progress of uploads to clients watching them
+    pub progress: Progress,
+    // lifecycle events of files, for sinks configured and for clients watching them
+    pub events: Events,
+    // turns true once server is shutting down, so that long-lived responses end
+    pub shutdown: watch::Receiver<bool>,
 }
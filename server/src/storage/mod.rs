@@ -0,0 +1,90 @@
+use std::{fmt, ops::RangeInclusive, sync::Arc};
+
+use futures::stream::BoxStream;
+use rusqlite::Connection;
+
+use crate::state::State;
+
+mod sqlite;
+
+pub use sqlite::SqliteStorage;
+
+// chunks stored for file, from some seq on
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunks {
+    pub count: i64,
+    pub first_seq: Option<i64>,
+    pub last_seq: Option<i64>,
+    pub bytes: i64,
+}
+
+// chunks and bytes stored for every file
+#[derive(Debug, Default, PartialEq)]
+pub struct Usage {
+    pub chunks: i64,
+    pub bytes: i64,
+}
+
+// failure of storage, whichever backend it is
+#[derive(Debug)]
+pub enum Error {
+    // chunk which was expected to be there is not, e.g. file was deleted while it was read
+    MissingChunk { file_id: i64, seq: i64 },
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingChunk { file_id, seq } => {
+                write!(f, "chunk is missing: id={}, seq={}", file_id, seq)
+            }
+            Error::Backend(err) => write!(f, "storage backend failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// transaction which metadata of files is written in. database backed storage writes chunks in
+// it too, so that they are committed or rolled back along with their file. other backends may
+// ignore it. only backends in this module see what is inside.
+#[derive(Clone, Copy)]
+pub struct Tx<'a>(&'a Connection);
+
+impl<'a> Tx<'a> {
+    pub fn of(conn: &'a Connection) -> Self {
+        Tx(conn)
+    }
+}
+
+// where chunks of file ciphertext are kept. metadata of files stays in database
+pub trait Storage: Send + Sync {
+    // replaces chunk of same seq if already stored, so that retried upload does not fail
+    fn put_chunk(&self, tx: Tx, file_id: i64, seq: i64, content: &[u8]) -> Result<()>;
+
+    fn get_chunk(&self, tx: Tx, file_id: i64, seq: i64) -> Result<Option<Vec<u8>>>;
+
+    // chunks of file in given range, read one at a time so that memory use does not grow with
+    // file size. ends after first error, and missing chunk is an error, as range is expected to
+    // be checked up front.
+    fn get_chunk_stream(
+        &self,
+        state: Arc<State>,
+        file_id: i64,
+        seqs: RangeInclusive<i64>,
+    ) -> BoxStream<'static, Result<Vec<u8>>>;
+
+    // chunks of file whose seq is `from_seq` or later
+    fn chunks(&self, tx: Tx, file_id: i64, from_seq: i64) -> Result<Chunks>;
+
+    // seqs up to `last_seq` which have no chunk stored, at most `limit` of them in order
+    fn missing_seqs(&self, tx: Tx, file_id: i64, last_seq: i64, limit: i64) -> Result<Vec<i64>>;
+
+    // delete every chunk of file. returns whether there was any
+    fn delete_file(&self, tx: Tx, file_id: i64) -> Result<bool>;
+
+    fn usage(&self, tx: Tx) -> Result<Usage>;
+}
@@ -0,0 +1,148 @@
+use std::{ops::RangeInclusive, sync::Arc};
+
+use futures::stream::{self, BoxStream, StreamExt};
+use rusqlite::{params, OptionalExtension};
+
+use super::{Chunks, Error, Result, Storage, Tx, Usage};
+use crate::{db, state::State};
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Backend(Box::new(err))
+    }
+}
+
+// chunks in `file_contents` table of the same database as metadata
+pub struct SqliteStorage;
+
+impl Storage for SqliteStorage {
+    fn put_chunk(&self, tx: Tx, file_id: i64, seq: i64, content: &[u8]) -> Result<()> {
+        let query = "insert into file_contents (file_id, seq, content) values (?1, ?2, ?3) on conflict (file_id, seq) do update set content = excluded.content";
+        let mut stmt = tx.0.prepare_cached(query)?;
+        db::retry(|| stmt.execute(params![&file_id, &seq, &content]))?;
+        Ok(())
+    }
+
+    fn get_chunk(&self, tx: Tx, file_id: i64, seq: i64) -> Result<Option<Vec<u8>>> {
+        let query = "select content from file_contents where file_id = ?1 and seq = ?2";
+        let mut stmt = tx.0.prepare_cached(query)?;
+        let content = stmt
+            .query_row(params![&file_id, &seq], |row| row.get(0))
+            .optional()?;
+        Ok(content)
+    }
+
+    // connection is taken only while reading each chunk, off runtime threads
+    fn get_chunk_stream(
+        &self,
+        state: Arc<State>,
+        file_id: i64,
+        seqs: RangeInclusive<i64>,
+    ) -> BoxStream<'static, Result<Vec<u8>>> {
+        stream::unfold(Some(seqs), move |seqs| {
+            let state = state.clone();
+            async move {
+                let mut seqs = seqs?;
+                let seq = seqs.next()?;
+                let content = db::read(state, move |state, conn| {
+                    state.storage.get_chunk(Tx::of(conn), file_id, seq)
+                })
+                .await;
+                match content {
+                    Ok(Some(content)) => Some((Ok(content), Some(seqs))),
+                    Ok(None) => Some((Err(Error::MissingChunk { file_id, seq }), None)),
+                    Err(err) => Some((Err(err), None)),
+                }
+            }
+        })
+        .boxed()
+    }
+
+    fn chunks(&self, tx: Tx, file_id: i64, from_seq: i64) -> Result<Chunks> {
+        let query = "select count(*), min(seq), max(seq), coalesce(sum(length(content)), 0) from file_contents where file_id = ?1 and seq >= ?2";
+        let mut stmt = tx.0.prepare_cached(query)?;
+        let chunks = stmt.query_row(params![&file_id, &from_seq], |row| {
+            Ok(Chunks {
+                count: row.get(0)?,
+                first_seq: row.get(1)?,
+                last_seq: row.get(2)?,
+                bytes: row.get(3)?,
+            })
+        })?;
+        Ok(chunks)
+    }
+
+    fn missing_seqs(&self, tx: Tx, file_id: i64, last_seq: i64, limit: i64) -> Result<Vec<i64>> {
+        let query = "with recursive seqs (seq) as (select 1 union all select seq + 1 from seqs where seq < ?2) select seq from seqs where not exists (select 1 from file_contents where file_id = ?1 and file_contents.seq = seqs.seq) limit ?3";
+        let mut stmt = tx.0.prepare(query)?;
+        let rows = stmt.query_map(params![&file_id, &last_seq, &limit], |row| row.get(0))?;
+        let seqs = rows.collect::<rusqlite::Result<_>>()?;
+        Ok(seqs)
+    }
+
+    fn delete_file(&self, tx: Tx, file_id: i64) -> Result<bool> {
+        let query = "delete from file_contents where file_id = ?1";
+        let deleted = db::retry(|| tx.0.execute(query, params![&file_id]))?;
+        Ok(deleted > 0)
+    }
+
+    fn usage(&self, tx: Tx) -> Result<Usage> {
+        let query = "select count(*), coalesce(sum(length(content)), 0) from file_contents";
+        let usage = tx.0.query_row(query, [], |row| {
+            Ok(Usage {
+                chunks: row.get(0)?,
+                bytes: row.get(1)?,
+            })
+        })?;
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    use crate::migrations;
+
+    #[test]
+    fn chunks_are_counted_from_seq() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("../../schema.sql"))
+            .unwrap();
+        migrations::run(&mut conn).unwrap();
+        conn.execute(
+            "insert into files (id, filename, salt, nonce, filename_nonce) values (1, x'', x'', x'', x'')",
+            [],
+        )
+        .unwrap();
+
+        let storage = SqliteStorage;
+        let tx = Tx::of(&conn);
+        for seq in 1..=3 {
+            storage.put_chunk(tx, 1, seq, b"chunk").unwrap();
+        }
+        storage.put_chunk(tx, 1, 2, b"again").unwrap();
+        assert_eq!(
+            storage.get_chunk(tx, 1, 2).unwrap(),
+            Some(b"again".to_vec())
+        );
+
+        let chunks = storage.chunks(tx, 1, 2).unwrap();
+        assert_eq!(
+            chunks,
+            Chunks {
+                count: 2,
+                first_seq: Some(2),
+                last_seq: Some(3),
+                bytes: 10,
+            }
+        );
+        assert_eq!(storage.get_chunk(tx, 1, 4).unwrap(), None);
+        assert_eq!(storage.missing_seqs(tx, 1, 6, 2).unwrap(), vec![4, 5]);
+        assert!(storage.missing_seqs(tx, 1, 3, 2).unwrap().is_empty());
+
+        assert!(storage.delete_file(tx, 1).unwrap());
+        assert_eq!(storage.usage(tx).unwrap(), Usage::default());
+    }
+}
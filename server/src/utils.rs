@@ -1,15 +1,46 @@
-pub mod base64 {
-    use serde::Serialize;
-    use serde::Serializer;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-    pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
-        let base64 = base64::encode(v);
-        String::serialize(&base64, s)
+// wall clock which is read once at startup and then advanced by the monotonic clock, so that
+// timestamps issued by this process never go backwards even if the system clock is adjusted.
+// all expiry timestamps are computed here instead of inside SQLite, which keeps them consistent
+// when the database file is moved across machines.
+pub struct Clock {
+    base: i64,
+    started: Instant,
+}
+
+impl Clock {
+    pub fn start() -> Self {
+        let base = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(err) => {
+                log::error!("system clock is before unix epoch: {:?}", err);
+                0
+            }
+        };
+
+        Self {
+            base,
+            started: Instant::now(),
+        }
+    }
+
+    // current unix timestamp in seconds
+    pub fn now(&self) -> i64 {
+        self.base + self.started.elapsed().as_secs() as i64
+    }
+
+    // seconds since startup
+    pub fn uptime(&self) -> i64 {
+        self.started.elapsed().as_secs() as i64
     }
+}
 
-    // pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
-    //     let base64 = String::deserialize(d)?;
-    //     base64::decode(base64.as_bytes())
-    //         .map_err(|e| serde::de::Error::custom(e))
-    // }
+// compare secrets without leaking position of first difference through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let diff = a.len() ^ b.len()
+        | a.iter()
+            .zip(b.iter())
+            .fold(0, |acc, (x, y)| acc | (x ^ y) as usize);
+    diff == 0
 }
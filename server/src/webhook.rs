@@ -0,0 +1,45 @@
+use axum::http::{header::CONTENT_TYPE, request, Request};
+use hyper::{client::HttpConnector, Body, Client};
+use hyper_tls::HttpsConnector;
+use serde::Serialize;
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>, Body>;
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// trusts system certificate store through native-tls, as reqwest of webapp does
+pub fn client() -> HttpClient {
+    Client::builder().build(HttpsConnector::new())
+}
+
+// message body understood by both Slack incoming webhooks and Matrix webhook bridges
+#[derive(Serialize)]
+pub struct TextMessage<'a> {
+    pub text: &'a str,
+}
+
+pub async fn post_json<T: Serialize>(
+    client: &HttpClient,
+    url: &str,
+    body: &T,
+) -> Result<(), Error> {
+    send_json(client, Request::post(url), body).await
+}
+
+// send JSON body with given request, which may carry its own method and headers
+pub async fn send_json<T: Serialize>(
+    client: &HttpClient,
+    req: request::Builder,
+    body: &T,
+) -> Result<(), Error> {
+    let body = serde_json::to_vec(body)?;
+    let req = req
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))?;
+
+    let resp = client.request(req).await?;
+    if !resp.status().is_success() {
+        return Err(format!("webhook responded with {}", resp.status()).into());
+    }
+
+    Ok(())
+}
@@ -1,96 +1,422 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
-use rusqlite::params;
+use rusqlite::{Connection, Transaction};
+use serde::Serialize;
+use tokio::{sync::watch, time::Interval};
 
-use crate::{config::Config, state::State};
+use crate::{
+    config::Config, db, events::Kind, growth, notifier, repo::Deletable, request_id, state::State,
+    storage::Tx,
+};
 
-pub async fn delete_expired(state: Arc<State>, config: Config) {
-    if config.expiry.unwrap_or(0) == 0 {
-        log::info!("expiry not specified. expiry worker will not run");
+#[derive(Serialize)]
+pub struct ExpiredFile {
+    id: i64,
+    expires_at: i64,
+    chunks: i64,
+    bytes: i64,
+}
+
+// files which expiry worker deletes when it runs at given time. files already deleted have no
+// chunks left, and files on legal hold are kept.
+pub fn expired_files(state: &State, conn: &Connection, at: i64) -> db::Result<Vec<ExpiredFile>> {
+    let mut files = Vec::new();
+    for (id, expires_at) in state.files.expired(conn, at)? {
+        let chunks = state.storage.chunks(Tx::of(conn), id, 1)?;
+        if chunks.count > 0 {
+            files.push(ExpiredFile {
+                id,
+                expires_at,
+                chunks: chunks.count,
+                bytes: chunks.bytes,
+            });
+        }
+    }
+    Ok(files)
+}
+
+// waits for next tick of interval. returns false instead once server is shutting down, so that
+// worker stops between runs rather than in the middle of one
+async fn tick(interval: &mut Interval, shutdown: &mut watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        _ = interval.tick() => true,
+        _ = shutdown.changed() => false,
+    }
+}
+
+pub async fn delete_expired(
+    state: Arc<State>,
+    config: Config,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    // files uploaded before `expires_at` was introduced: derive it from their creation time
+    if let Some(expiry) = config.expiry.filter(|expiry| *expiry > 0) {
+        let expiry = expiry as i64;
+        let conn = state.conn.lock().await;
+        match state.files.backfill_expiry(&conn, expiry) {
+            Ok(updated) if updated > 0 => {
+                log::info!("set expiry of {} files uploaded without one", updated);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                log::error!("failed to set expiry of old files: {:?}", err);
+            }
+        }
+    }
+
+    // runs even without configured expiry, as api keys may give uploads their own `expires_at`
+    let mut interval = tokio::time::interval(Duration::from_secs(config.delete_interval));
+
+    if config.expiry_dry_run {
+        log::info!("starting expiry worker in dry run mode...");
+    } else {
+        log::info!("starting expiry worker...");
+    }
+    while tick(&mut interval, &mut shutdown).await {
+        let dry_run = config.expiry_dry_run;
+        let hard_delete = config.expiry_hard_delete;
+        db::blocking(state.clone(), move |state, conn| {
+            expire(state, conn, dry_run, hard_delete)
+        })
+        .await;
+    }
+    log::info!("stopped expiry worker");
+}
+
+// delete chunks of every expired file, or only log them in dry run mode. rows of files go as well
+// in hard delete mode. runs on blocking thread pool, as there may be many chunks to delete.
+fn expire(state: &State, conn: &mut Connection, dry_run: bool, hard_delete: bool) {
+    if dry_run {
+        match expired_files(state, conn, state.clock.now()) {
+            Ok(files) => {
+                for file in files {
+                    log::info!(
+                        "dry run: would delete expired file: id {}, expires_at {}, {} bytes",
+                        file.id,
+                        file.expires_at,
+                        file.bytes
+                    );
+                }
+            }
+            Err(err) => {
+                log::error!("failed to query expired files: {:?}", err);
+                return;
+            }
+        }
+        state
+            .expiry_last_run
+            .store(state.clock.now(), Ordering::Relaxed);
+        return;
+    }
+
+    // make transaction object
+    let tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(err) => {
+            log::error!("could not build transaction object: {:?}", err);
+            return;
+        }
+    };
+
+    let deleted = if hard_delete {
+        hard_delete_expired(state, &tx)
+    } else {
+        soft_delete_expired(state, &tx)
+    };
+    let deleted = match deleted {
+        Some(deleted) => deleted,
+        None => return,
+    };
+
+    // commit
+    if let Err(err) = db::commit(tx) {
+        log::error!("failed to commit: {:?}", err);
         return;
     }
-    let expiry = config.expiry.unwrap() as f64;
+    for id in deleted {
+        state.events.emit(Kind::Expired, id, state.clock.now());
+    }
+    state
+        .expiry_last_run
+        .store(state.clock.now(), Ordering::Relaxed);
+}
+
+// delete every expired file with its row. rows left by soft deletion before hard delete mode was
+// turned on have no chunks, and are deleted as well. returns ids of deleted files, or none if
+// expired files could not be queried.
+fn hard_delete_expired(state: &State, tx: &Transaction) -> Option<Vec<i64>> {
+    let expired = match state.files.expired(tx, state.clock.now()) {
+        Ok(expired) => expired,
+        Err(err) => {
+            log::error!("failed to query expired files: {:?}", err);
+            return None;
+        }
+    };
+
+    let (mut deleted, mut deleted_bytes) = (Vec::new(), 0);
+    for (id, _) in expired {
+        let result = (|| -> db::Result<_> {
+            let chunks = state.storage.chunks(Tx::of(tx), id, 1)?;
+            let deleted = db::delete_file(state, tx, id, Deletable::NotHeld)?;
+            Ok((deleted, chunks.bytes))
+        })();
+        match result {
+            Ok((0, _)) => {}
+            Ok((_, bytes)) => {
+                log::info!("deleted expired file: id {}", id);
+                deleted.push(id);
+                deleted_bytes += bytes;
+            }
+            Err(err) => log::error!("failed to delete expired file: id {}, {:?}", id, err),
+        }
+    }
+    let deleted_files = deleted.len() as i64;
+    growth::record_deletion(
+        &*state.instance,
+        tx,
+        state.clock.now(),
+        deleted_files,
+        deleted_bytes,
+    );
+    Some(deleted)
+}
+
+fn soft_delete_expired(state: &State, tx: &Transaction) -> Option<Vec<i64>> {
+    let files = match expired_files(state, tx, state.clock.now()) {
+        Ok(files) => files,
+        Err(err) => {
+            log::error!("failed to query expired files: {:?}", err);
+            return None;
+        }
+    };
+
+    // drop chunks and mark each file unavailable. file row is kept, so that its share id
+    // reads as expired instead of unknown
+    let (mut deleted, mut deleted_bytes) = (Vec::new(), 0);
+    for file in files {
+        let result = (|| -> db::Result<_> {
+            state.storage.delete_file(Tx::of(tx), file.id)?;
+            state.files.set_available(tx, file.id, false)?;
+            Ok(())
+        })();
+        if let Err(err) = result {
+            log::error!("failed to delete expired file: id {}, {:?}", file.id, err);
+            continue;
+        }
+        log::info!("deleted expired file: id {}", file.id);
+        deleted.push(file.id);
+        deleted_bytes += file.bytes;
+    }
+    let deleted_files = deleted.len() as i64;
+    growth::record_deletion(
+        &*state.instance,
+        tx,
+        state.clock.now(),
+        deleted_files,
+        deleted_bytes,
+    );
+    Some(deleted)
+}
+
+pub async fn purge_abandoned(
+    state: Arc<State>,
+    config: Config,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let age = match config.abandoned_upload_age {
+        Some(age) => age as i64,
+        None => {
+            log::info!("abandoned upload age not specified. purge worker will not run");
+            return;
+        }
+    };
 
     let mut interval = tokio::time::interval(Duration::from_secs(config.delete_interval));
 
-    log::info!("starting expiry worker...");
-    loop {
-        interval.tick().await;
+    log::info!("starting abandoned upload purge worker...");
+    while tick(&mut interval, &mut shutdown).await {
+        db::blocking(state.clone(), move |state, conn| purge(state, conn, age)).await;
+    }
+    log::info!("stopped abandoned upload purge worker");
+}
 
-        let conn = &mut state.conn.lock().await;
+// delete incomplete uploads prepared more than `age` seconds ago. expired ones are left for expiry
+// worker, which keeps their rows unless in hard delete mode
+fn purge(state: &State, conn: &mut Connection, age: i64) {
+    let now = state.clock.now();
+    let ids = match state.files.abandoned(conn, now, now - age) {
+        Ok(ids) => ids,
+        Err(err) => {
+            log::error!("failed to query abandoned uploads: {:?}", err);
+            return;
+        }
+    };
 
-        // make transaction object
-        let tx = match conn.transaction() {
-            Ok(tx) => tx,
-            Err(err) => {
-                log::error!("could not build transaction object: {:?}", err);
-                continue;
+    for id in ids {
+        let result = (|| -> db::Result<_> {
+            let tx = conn.transaction()?;
+            let chunks = state.storage.chunks(Tx::of(&tx), id, 1)?;
+            let deleted = db::delete_file(state, &tx, id, Deletable::IncompleteNotHeld)?;
+            if deleted > 0 {
+                growth::record_deletion(&*state.instance, &tx, now, 1, chunks.bytes);
             }
-        };
-
-        {
-            // prepare statement
-            let query =
-                "delete from file_contents where file_id in (select id from files where (unixepoch(current_timestamp) > unixepoch(created_at) + ?1)) returning file_id";
-            let mut stmt = match tx.prepare(query) {
-                Ok(stmt) => stmt,
-                Err(err) => {
-                    log::error!("could not prepare statement: {:?}", err);
-                    continue;
-                }
-            };
+            db::commit(tx)?;
+            Ok(deleted)
+        })();
+        match result {
+            Ok(0) => {}
+            Ok(_) => {
+                state.events.emit(Kind::Deleted, id, now);
+                log::info!("deleted abandoned upload: id {}", id);
+            }
+            Err(err) => log::error!("failed to delete abandoned upload: id {}, {:?}", id, err),
+        }
+    }
+}
+
+pub async fn reclaim_space(state: Arc<State>, config: Config, mut shutdown: watch::Receiver<bool>) {
+    let vacuum_interval = match config.vacuum_interval {
+        Some(vacuum_interval) => vacuum_interval,
+        None => {
+            log::info!("vacuum interval not specified. vacuum worker will not run");
+            return;
+        }
+    };
+    let full = config.full_vacuum;
 
-            // insert row
-            let result = stmt.query(params![&expiry]);
+    let mut interval = tokio::time::interval(Duration::from_secs(vacuum_interval));
 
-            let mut rows = match result {
-                Ok(rows) => rows,
-                Err(err) => {
-                    log::error!("failed to delete expired files: {:?}", err);
-                    continue;
-                }
-            };
-
-            // get deleted file ids
-            let mut file_ids = Vec::new();
-            while let Some(row) = rows.next().map_or(None, |row| row) {
-                let id: Option<i64> = row.get(0).ok();
-                if let Some(id) = id {
-                    file_ids.push(id);
+    log::info!("starting vacuum worker...");
+    while tick(&mut interval, &mut shutdown).await {
+        let result = db::blocking(state.clone(), move |_, conn| db::vacuum(conn, full)).await;
+        match result {
+            Ok(0) => {}
+            Ok(reclaimed) => log::info!("reclaimed {} bytes of database file", reclaimed),
+            Err(err) => log::error!("failed to vacuum database: {:?}", err),
+        }
+    }
+    log::info!("stopped vacuum worker");
+}
+
+// snapshot of stored bytes for growth forecast, taken hourly as last one of each day is kept
+pub async fn record_growth(state: Arc<State>, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+
+    log::info!("starting storage growth worker...");
+    while tick(&mut interval, &mut shutdown).await {
+        let result = db::blocking(state.clone(), |state, conn| -> db::Result<_> {
+            let usage = state.storage.usage(Tx::of(conn))?;
+            growth::record_usage(&*state.instance, conn, state.clock.now(), usage.bytes)?;
+            Ok(())
+        })
+        .await;
+        if let Err(err) = result {
+            log::error!("failed to record stored bytes: {:?}", err);
+        }
+    }
+    log::info!("stopped storage growth worker");
+}
+
+// size of database file including its write-ahead log
+pub fn db_file_size(filename: &str) -> std::io::Result<u64> {
+    let size = std::fs::metadata(filename)?.len();
+    let wal_size = std::fs::metadata(format!("{}-wal", filename)).map_or(0, |m| m.len());
+    Ok(size + wal_size)
+}
+
+// records whether threshold was exceeded on last check, so that operator gets notified only when
+// it is crossed instead of on every check.
+fn check_threshold(breached: &mut bool, value: u64, threshold: Option<u64>) -> bool {
+    let over = threshold.map_or(false, |t| value > t);
+    let newly_breached = over && !*breached;
+    *breached = over;
+    newly_breached
+}
+
+pub async fn monitor_storage(state: Arc<State>, config: Config) {
+    let notifiers = notifier::from_config(&config);
+    if notifiers.is_empty() {
+        log::info!("no notifier configured. monitoring worker will not run");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.alert_interval));
+    let mut last_error_count = request_id::server_error_count();
+    let mut db_size_breached = false;
+    let mut stored_bytes_breached = false;
+    let mut server_errors_breached = false;
+
+    log::info!("starting monitoring worker...");
+    loop {
+        interval.tick().await;
+
+        let mut alerts = Vec::new();
+
+        match db_file_size(&config.sqlite_db_filename) {
+            Ok(size) => {
+                if check_threshold(&mut db_size_breached, size, config.alert_db_size) {
+                    alerts.push(format!(
+                        "database size is {} bytes, over threshold of {} bytes",
+                        size,
+                        config.alert_db_size.unwrap_or_default()
+                    ));
                 }
             }
+            Err(err) => {
+                log::error!("failed to get database file size: {:?}", err);
+            }
+        }
 
-            // remove all duplicate file ids
-            file_ids.sort();
-            file_ids.dedup();
-
-            // update available field for each file row
-            for file_id in file_ids {
-                let query = "update files set available = false where id = ?1";
-                let mut stmt = {
-                    match tx.prepare(query) {
-                        Ok(stmt) => stmt,
-                        Err(err) => {
-                            log::error!("could not prepare statement: {:?}", err);
-                            continue;
-                        }
+        if config.alert_stored_bytes.is_some() {
+            // scan of every chunk, so it should not hold writer while uploads wait
+            let stored = db::read(state.clone(), |state, conn| {
+                state.storage.usage(Tx::of(conn)).map(|usage| usage.bytes)
+            })
+            .await;
+            match stored {
+                Ok(stored) => {
+                    let stored = stored as u64;
+                    if check_threshold(
+                        &mut stored_bytes_breached,
+                        stored,
+                        config.alert_stored_bytes,
+                    ) {
+                        alerts.push(format!(
+                            "{} bytes of files are stored, over threshold of {} bytes",
+                            stored,
+                            config.alert_stored_bytes.unwrap_or_default()
+                        ));
                     }
-                };
-
-                // update row
-                let result = stmt.execute(params![&file_id]);
-                if let Err(err) = result {
-                    log::error!("failed to query: {:?}", err);
-                    continue;
                 }
-                log::info!("deleted expired file: id {}", file_id);
+                Err(err) => {
+                    log::error!("failed to query stored bytes: {:?}", err);
+                }
             }
         }
 
-        // commit
-        if let Err(err) = tx.commit() {
-            log::error!("failed to commit: {:?}", err);
-            continue;
+        let error_count = request_id::server_error_count();
+        let errors = error_count - last_error_count;
+        last_error_count = error_count;
+        if check_threshold(
+            &mut server_errors_breached,
+            errors,
+            config.alert_server_errors,
+        ) {
+            alerts.push(format!(
+                "{} server errors in last {} seconds, over threshold of {}",
+                errors,
+                config.alert_interval,
+                config.alert_server_errors.unwrap_or_default()
+            ));
+        }
+
+        for alert in alerts {
+            log::warn!("{}", alert);
+            let text = format!("[hako] {}", alert);
+            notifier::notify_all(&notifiers, &text).await;
         }
     }
 }
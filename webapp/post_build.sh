@@ -2,4 +2,25 @@
 
 if [ "$TRUNK_PROFILE" = "release" ]; then
     mv ./asset.css.bak ./asset.css
+
+    # fail release build when bundle grows past budget, as landing page cannot be interactive
+    # until whole wasm is fetched. override with WASM_SIZE_BUDGET (in bytes) when raising it.
+    budget="${WASM_SIZE_BUDGET:-2097152}"
+    for wasm in "$TRUNK_STAGING_DIR"/*.wasm; do
+        size=$(wc -c < "$wasm")
+        echo "$(basename "$wasm"): $size bytes (budget $budget bytes)"
+        if [ "$size" -gt "$budget" ]; then
+            echo "wasm bundle is over size budget" >&2
+            exit 1
+        fi
+    done
+
+    # precompress assets, which server sends to clients accepting them
+    for asset in "$TRUNK_STAGING_DIR"/*.wasm "$TRUNK_STAGING_DIR"/*.js "$TRUNK_STAGING_DIR"/*.css; do
+        [ -f "$asset" ] || continue
+        gzip -9 -k -f "$asset"
+        if command -v brotli > /dev/null; then
+            brotli -q 11 -k -f "$asset"
+        fi
+    done
 fi
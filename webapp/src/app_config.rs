@@ -0,0 +1,49 @@
+use serde::Deserialize;
+
+use crate::utils::join_uri;
+
+// limits advertised by server
+#[derive(Deserialize, Clone, Debug)]
+pub struct AppConfig {
+    pub max_file_size: u64,
+    // chunks one file may be uploaded in
+    #[serde(default)]
+    pub chunk_count_limit: Option<u64>,
+    // lowercase, without leading dot
+    #[serde(default)]
+    pub forbidden_extensions: Vec<String>,
+    // uploads are allowed only with API key given by server operator
+    #[serde(default)]
+    pub api_key_required: bool,
+    // users may report their errors to server operator
+    #[serde(default)]
+    pub client_error_reports: bool,
+}
+
+pub async fn get_app_config(base_uri: &str) -> Option<AppConfig> {
+    let client = reqwest::Client::new();
+    let resp = match client
+        .get(join_uri(base_uri, "/api/app_config"))
+        .send()
+        .await
+    {
+        Ok(resp) => resp,
+        Err(e) => {
+            log::error!("failed to request app_config: {:?}", e);
+            return None;
+        }
+    };
+    if resp.status() != 200 {
+        log::error!("app_config status != 200, but {}", resp.status());
+        return None;
+    }
+
+    let body = resp.bytes().await.ok()?;
+    match serde_json::from_slice::<AppConfig>(&body) {
+        Ok(c) => Some(c),
+        Err(e) => {
+            log::error!("failed to deserialize app_config: {:?}", e);
+            None
+        }
+    }
+}
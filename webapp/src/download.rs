@@ -4,36 +4,65 @@ use std::string::FromUtf8Error;
 use aead::generic_array::GenericArray;
 use chacha20poly1305::aead::{Aead, NewAead};
 use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
-use futures_util::{FutureExt, TryStreamExt};
+use std::pin::Pin;
+
+use futures_util::{future, stream, Stream, StreamExt, TryStreamExt};
+use hako_core::Metadata;
 use hkdf::Hkdf;
 use js_sys::{Array, Uint8Array};
 use serde::Deserialize;
 use sha2::Sha256;
-use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
 use wasm_bindgen_futures::{spawn_local, JsFuture};
 use yew::{classes, html, Component, ComponentLink, Html, NodeRef, Properties};
 use yew::{web_sys::*, Classes};
 
-use crate::utils::{join_uri, BLOCK_OVERHEAD, BLOCK_SIZE};
+use crate::app_config::{get_app_config, AppConfig};
+use crate::telemetry;
+use crate::utils::{
+    base_uri, error_body, format_duration, format_size, join_uri, progress_milestone, request_ref,
+    set_title, ErrorBody, BLOCK_OVERHEAD, BLOCK_SIZE, FORMAT_VERSION, REQUEST_ID_HEADER,
+};
+
+// files this large get their decryption time estimated before download, and users are warned if it
+// takes longer than `SLOW_DECRYPTION_SECS` on their device
+const ESTIMATED_SIZE: i64 = 256 * 1024 * 1024;
+const SLOW_DECRYPTION_SECS: f64 = 60.0;
 
 pub enum DownloadMsg {
-    Metadata(Result<FileMetadata, MetadataError>),
+    // metadata with first ciphertext chunk of file
+    Metadata(Result<(Metadata, Vec<u8>), MetadataError>),
     PassphraseInput,
     StartDownload,
-    StartFileDownload(FileMetadata, String),
-    StartTextDownload(FileMetadata, String),
+    StartFileDownload(Metadata, String),
+    StartTextDownload(Metadata, String),
     Filename(Vec<u8>),
     Progress(ProgressInfo),
     DownloadError(DownloadError),
     FileDownloadComplete(Vec<u8>),
     TextDownloadComplete(Vec<u8>),
+    WaitForUpload,
+    UploadProgress(UploadProgress),
+    UploadComplete,
+    AppConfig(AppConfig),
+    ReportError,
+    CopyText,
 }
 
 #[derive(Debug)]
 pub enum MetadataError {
     FileNotFound,
     NotAvailable,
+    // what server told about error, if anything, and reference of request
+    ServerError(Option<String>, String),
     Deserialize,
+    UnsupportedFormat(u8),
+    // file is published later, at given unix timestamp
+    NotYetAvailable(i64),
+    // sender has not finished uploading file yet
+    UploadInProgress,
+    // file expired or was downloaded as many times as allowed
+    Gone,
 }
 
 #[derive(Debug)]
@@ -43,9 +72,26 @@ pub enum DownloadError {
     Aead(aead::Error),
     MetadataError(MetadataError),
     Utf8Error(FromUtf8Error),
+    Remote(String),
     Other,
 }
 
+impl DownloadError {
+    // kind of error in error report, without details
+    fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::KeyGeneration(_) => "download_key_generation",
+            DownloadError::JsValue(_) => "download_file_read",
+            DownloadError::Aead(_) => "download_decryption",
+            DownloadError::MetadataError(MetadataError::ServerError(..)) => "download_server",
+            DownloadError::MetadataError(_) => "download_unavailable",
+            DownloadError::Utf8Error(_) => "download_utf8",
+            DownloadError::Remote(_) => "download_server",
+            DownloadError::Other => "download_other",
+        }
+    }
+}
+
 pub enum ProgressInfo {
     DownloadBytes(usize),
 }
@@ -55,47 +101,143 @@ pub struct DownloadComponent {
     base_uri: String,
     passphrase_ref: NodeRef,
     a_ref: NodeRef,
+    textarea_ref: NodeRef,
     passphrase_available: bool,
-    file_id: i64,
-    metadata: Option<Result<FileMetadata, MetadataError>>,
+    file_id: String,
+    metadata: Option<Result<Metadata, MetadataError>>,
+    first_chunk: Vec<u8>,
     decrypted_filename: Option<String>,
     decrypted_text: Option<String>,
     downloaded_size: Option<usize>,
     download_error: Option<DownloadError>,
+    upload_watcher: Option<UploadWatcher>,
+    upload_progress: Option<UploadProgress>,
+    // passphrase given in fragment of share url, which is never sent to server
+    fragment_key: Option<String>,
+    // users may report their errors to server operator
+    client_error_reports: bool,
+    // current error was reported to server operator
+    error_reported: bool,
+    // decrypted text was copied to clipboard
+    text_copied: bool,
+    // seconds this device would take to decrypt large file
+    decryption_estimate: Option<f64>,
 }
 
 #[derive(Properties, Clone, PartialEq)]
 pub struct DownloadProps {
-    pub id: i64,
+    // share id of file
+    pub id: String,
 }
 
+// progress of upload which is still going on, sent by server as events
 #[derive(Deserialize, Clone, Debug)]
-pub struct FileMetadata {
-    #[serde(with = "crate::utils::base64")]
-    filename: Vec<u8>,
-    #[serde(with = "crate::utils::base64")]
-    salt: Vec<u8>,
-    #[serde(with = "crate::utils::base64")]
-    nonce: Vec<u8>,
-    #[serde(with = "crate::utils::base64")]
-    filename_nonce: Vec<u8>,
-    is_text: bool,
-    size: i64,
+pub struct UploadProgress {
+    received_bytes: i64,
+    expected_size: Option<i64>,
+}
+
+// watches upload progress of file through server-sent events, until dropped
+struct UploadWatcher {
+    source: EventSource,
+    _onprogress: Closure<dyn FnMut(MessageEvent)>,
+    _oncomplete: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl Drop for UploadWatcher {
+    fn drop(&mut self) {
+        self.source.close();
+    }
+}
+
+fn watch_upload(
+    base_uri: &str,
+    id: &str,
+    link: ComponentLink<DownloadComponent>,
+) -> Result<UploadWatcher, JsValue> {
+    let url = join_uri(base_uri, &format!("/api/events?id={}", id));
+    let source = EventSource::new(&url)?;
+
+    let clink = link.clone();
+    let onprogress = Closure::wrap(Box::new(move |e: MessageEvent| {
+        let progress = e
+            .data()
+            .as_string()
+            .and_then(|data| serde_json::from_str::<UploadProgress>(&data).ok());
+        if let Some(progress) = progress {
+            clink.send_message(DownloadMsg::UploadProgress(progress));
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    let oncomplete = Closure::wrap(Box::new(move |_: MessageEvent| {
+        link.send_message(DownloadMsg::UploadComplete);
+    }) as Box<dyn FnMut(MessageEvent)>);
+    source.add_event_listener_with_callback("progress", onprogress.as_ref().unchecked_ref())?;
+    source.add_event_listener_with_callback("complete", oncomplete.as_ref().unchecked_ref())?;
+    // opening file again tells that it is gone, just as it tells that it is complete
+    source.add_event_listener_with_callback("gone", oncomplete.as_ref().unchecked_ref())?;
+
+    Ok(UploadWatcher {
+        source,
+        _onprogress: onprogress,
+        _oncomplete: oncomplete,
+    })
 }
 
-async fn get_file_metadata(base_uri: &str, id: i64) -> Result<FileMetadata, MetadataError> {
+// `open` does not serve files which are still being uploaded, and only metadata tells them apart
+// from missing ones, by answering with 202
+async fn upload_in_progress(base_uri: &str, id: &str) -> bool {
     let client = reqwest::Client::new();
-    let resp = client
+    match client
         .get(join_uri(base_uri, "/api/metadata"))
         .query(&[("id", id)])
         .send()
+        .await
+    {
+        Ok(resp) => resp.status() == 202,
+        Err(_) => false,
+    }
+}
+
+// detail of error response which server returns for embargoed file
+#[derive(Deserialize)]
+struct NotYetAvailable {
+    available_from: i64,
+}
+
+// fetch metadata together with first chunk, which is the whole ciphertext for texts and small
+// files. passphrase can be checked against it right away without another round trip.
+async fn open_file(base_uri: &str, id: &str) -> Result<(Metadata, Vec<u8>), MetadataError> {
+    let client = reqwest::Client::new();
+    let resp = client
+        .get(join_uri(base_uri, "/api/open"))
+        .query(&[("id", id)])
+        .send()
         .await;
     let resp = match resp {
         Ok(resp) => {
             if resp.status() == 404 {
+                if upload_in_progress(base_uri, id).await {
+                    return Err(MetadataError::UploadInProgress);
+                }
                 return Err(MetadataError::FileNotFound);
+            } else if resp.status() == 410 {
+                return Err(MetadataError::Gone);
             } else if resp.status() != 200 {
-                return Err(MetadataError::NotAvailable);
+                let request_ref = request_ref(&resp);
+                let body = match error_body(resp).await {
+                    Some(body) => body,
+                    None => return Err(MetadataError::ServerError(None, request_ref)),
+                };
+                if body.code == "not_yet_available" {
+                    return match body.detail::<NotYetAvailable>() {
+                        Some(e) => Err(MetadataError::NotYetAvailable(e.available_from)),
+                        None => Err(MetadataError::Deserialize),
+                    };
+                }
+                return Err(MetadataError::ServerError(
+                    Some(body.describe()),
+                    request_ref,
+                ));
             }
             resp
         }
@@ -110,28 +252,83 @@ async fn get_file_metadata(base_uri: &str, id: i64) -> Result<FileMetadata, Meta
         }
     };
 
-    match serde_json::from_slice::<FileMetadata>(&body) {
-        Ok(f) => Ok(f),
-        Err(_) => Err(MetadataError::Deserialize),
+    // body is 4-byte big-endian length of metadata, metadata JSON and then first chunk
+    if body.len() < 4 {
+        return Err(MetadataError::Deserialize);
+    }
+    let (len, body) = body.split_at(4);
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    if body.len() < len {
+        return Err(MetadataError::Deserialize);
+    }
+    let (metadata, first_chunk) = body.split_at(len);
+
+    let metadata = match serde_json::from_slice::<Metadata>(metadata) {
+        Ok(f) => f,
+        Err(_) => {
+            return Err(MetadataError::Deserialize);
+        }
+    };
+
+    // file is encrypted by newer client which this one cannot decrypt
+    if metadata.format_version > FORMAT_VERSION {
+        return Err(MetadataError::UnsupportedFormat(metadata.format_version));
     }
+
+    Ok((metadata, first_chunk.to_vec()))
 }
 
 // function for streaming download. reqwest does not support stream in wasm environment
 // so directly use `fetch()` and use `ReadableStream` from its body.
 async fn get_download_stream(
     base_uri: &str,
-    id: i64,
-) -> Result<wasm_streams::ReadableStream, JsValue> {
+    id: &str,
+    from_seq: i64,
+    ticket: Option<&str>,
+) -> Result<wasm_streams::ReadableStream, DownloadError> {
     let mut opts = RequestInit::new();
     opts.method("GET");
 
-    let url = format!("/api/download?id={}", id);
+    let mut url = format!("/api/download?id={}&from_seq={}", id, from_seq);
+    // ticket is url-safe base64, so it goes in query as is
+    if let Some(ticket) = ticket {
+        url.push_str(&format!("&ticket={}", ticket));
+    }
     let url = join_uri(base_uri, &url);
-    let request = Request::new_with_str_and_init(&url, &opts)?;
+    let request = Request::new_with_str_and_init(&url, &opts).map_err(DownloadError::JsValue)?;
 
     let window = window().unwrap();
-    let resp = JsFuture::from(window.fetch_with_request(&request)).await?;
+    let resp = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(DownloadError::JsValue)?;
     let resp: Response = resp.dyn_into().unwrap();
+    // file can be deleted or expire after it was opened
+    if resp.status() == 404 {
+        return Err(DownloadError::MetadataError(MetadataError::FileNotFound));
+    }
+    if resp.status() == 410 {
+        return Err(DownloadError::MetadataError(MetadataError::Gone));
+    }
+    if !resp.ok() {
+        let request_ref = match resp.headers().get(REQUEST_ID_HEADER) {
+            Ok(Some(id)) => format!(", ref #{}", id),
+            _ => "".into(),
+        };
+        let body = match resp.text() {
+            Ok(text) => JsFuture::from(text).await.ok().and_then(|t| t.as_string()),
+            Err(_) => None,
+        };
+        return Err(DownloadError::Remote(
+            match body.and_then(|b| serde_json::from_str::<ErrorBody>(&b).ok()) {
+                Some(body) => format!("download: {}{}", body.describe(), request_ref),
+                None => format!(
+                    "download status != 200, but {}{}",
+                    resp.status(),
+                    request_ref
+                ),
+            },
+        ));
+    }
 
     let stream = resp.body().unwrap();
 
@@ -140,30 +337,167 @@ async fn get_download_stream(
     ))
 }
 
+// base uri answering ping first, among origin and its mirrors. origin is the fallback when no
+// mirror answers, or there is none.
+async fn fastest_base_uri(base_uri: &str, mirrors: &[String]) -> String {
+    if mirrors.is_empty() {
+        return base_uri.to_owned();
+    }
+
+    let client = reqwest::Client::new();
+    let pings = std::iter::once(base_uri)
+        .chain(mirrors.iter().map(String::as_str))
+        .map(|uri| {
+            let request = client.get(join_uri(uri, "/api/ping")).send();
+            Box::pin(async move {
+                match request.await {
+                    Ok(resp) if resp.status().is_success() => Ok(uri.to_owned()),
+                    _ => Err(()),
+                }
+            })
+        });
+    match future::select_ok(pings).await {
+        Ok((uri, _)) => uri,
+        Err(_) => base_uri.to_owned(),
+    }
+}
+
 fn text_input(comp: &DownloadComponent, classes: Classes) -> Html {
+    let metadata = match &comp.metadata {
+        Some(Ok(metadata)) => Some(metadata),
+        _ => None,
+    };
+    // text came with metadata, so the last download allowed is already spent by now
+    let burned = metadata.and_then(|m| m.downloads_left) == Some(1);
+    let expiry = match metadata.and_then(|m| m.expires_at) {
+        Some(expires_at) => format!("Expires at {}. ", local_date(expires_at)),
+        None => "".into(),
+    };
+    let copy_text = if comp.text_copied { "Copied" } else { "Copy" };
+
     html! {
-        <div class={classes}>
-            <textarea class=classes!("w-3/4") rows=6>
+        <div class=classes!(classes, "flex-col", "items-center")>
+            <textarea class=classes!("w-3/4") rows=6 ref={comp.textarea_ref.clone()}>
                 { comp.decrypted_text.as_ref().unwrap_or(&"".into()) }
             </textarea>
+            <div class=classes!("flex", "justify-center", "mt-2")>
+                <span class=classes!("hako-muted")>
+                    { format!("{}Copy it somewhere before it's gone", expiry) }
+                </span>
+                <span class=classes!("hako-link", "cursor-pointer", "ml-3") onclick={comp.link.callback(|_| DownloadMsg::CopyText)}>
+                    { copy_text }
+                </span>
+            </div>
+            <span class=classes!("hako-error", "mt-2") hidden={!burned}>
+                { "This text could be read only once. Reloading this page will not show it again" }
+            </span>
         </div>
     }
 }
 
+// bytes per second this device decrypts, measured by encrypting sample block for a moment, as
+// encryption costs the same as decryption with this cipher
+fn decryption_throughput() -> f64 {
+    const SAMPLE_SIZE: usize = 1024 * 1024;
+    const MIN_MS: f64 = 50.0;
+
+    let cipher = XChaCha20Poly1305::new(&Key::default());
+    let nonce = XNonce::default();
+    let sample = vec![0u8; SAMPLE_SIZE];
+    let start = js_sys::Date::now();
+    let mut bytes = 0;
+    let mut elapsed = 0.0;
+    while elapsed < MIN_MS {
+        if cipher.encrypt(&nonce, sample.as_ref()).is_err() {
+            break;
+        }
+        bytes += SAMPLE_SIZE;
+        elapsed = js_sys::Date::now() - start;
+    }
+    bytes as f64 * 1000.0 / elapsed.max(1.0)
+}
+
+// unix timestamp in locale of browser
+fn local_date(timestamp: i64) -> String {
+    let date = js_sys::Date::new(&JsValue::from_f64(timestamp as f64 * 1000_f64));
+    String::from(date.to_locale_string("default", &JsValue::UNDEFINED))
+}
+
+// fetch file metadata
+fn fetch_metadata(link: ComponentLink<DownloadComponent>, base_uri: String, id: String) {
+    spawn_local(async move {
+        match open_file(&base_uri, &id).await {
+            Ok(opened) => link.send_message(DownloadMsg::Metadata(Ok(opened))),
+            Err(e) => link.send_message(DownloadMsg::Metadata(Err(e))),
+        }
+    });
+}
+
+// passphrase in fragment of current url, e.g. `/abc#correct-horse`. fragment is never sent in
+// requests, so server cannot see it
+fn fragment_key() -> Option<String> {
+    let hash = yew::utils::window().location().hash().ok()?;
+    let key = hash.strip_prefix('#').filter(|key| !key.is_empty())?;
+    js_sys::decode_uri_component(key).ok().map(String::from)
+}
+
+fn decrypt_filename(metadata: &Metadata, passphrase: &str) -> Result<Vec<u8>, DownloadError> {
+    let h = Hkdf::<Sha256>::new(Some(metadata.salt.as_ref()), passphrase.as_bytes());
+    let mut key_slice = [0u8; 32];
+    if let Err(err) = h.expand(&[], &mut key_slice[..]) {
+        log::error!("cannot expand passphrase by hkdf: {:?}", err);
+        let msg = "cannot expand passphrase by hkdf";
+        return Err(DownloadError::KeyGeneration(Cow::from(msg)));
+    }
+    let key = Key::clone_from_slice(&key_slice);
+    let cipher = XChaCha20Poly1305::new(&key);
+    let filename_nonce = GenericArray::from_slice(metadata.filename_nonce.as_ref());
+    cipher
+        .decrypt(filename_nonce, metadata.filename.as_ref())
+        .map_err(DownloadError::Aead)
+}
+
+fn server_error_text(error: &Option<String>, request_ref: &str) -> String {
+    match error {
+        Some(error) => format!("Server error: {}{}", error, request_ref),
+        None => format!("Server error{}", request_ref),
+    }
+}
+
+// progress goes first in title, as tabs cut off the rest
+fn title(component: &DownloadComponent) -> Option<String> {
+    if component.download_error.is_some() {
+        return Some("Download failed".into());
+    }
+    let size = match &component.metadata {
+        Some(Ok(m)) => m.size as usize,
+        Some(Err(_)) => return Some("File unavailable".into()),
+        None => return None,
+    };
+    match component.downloaded_size {
+        Some(downloaded) if downloaded >= size => Some("Downloaded".into()),
+        Some(downloaded) => Some(format!(
+            "Downloading {}%…",
+            (downloaded as f64 / size as f64 * 100_f64) as usize
+        )),
+        None => Some("Download ready".into()),
+    }
+}
+
 impl Component for DownloadComponent {
     type Message = DownloadMsg;
     type Properties = DownloadProps;
 
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
-        let base_uri = yew::utils::window().origin();
-        // fetch file metadata
-        let id = props.id;
+        let base_uri = base_uri();
+        fetch_metadata(link.clone(), base_uri.clone(), props.id.clone());
+        let fragment_key = fragment_key();
+
         let clink = link.clone();
         let base_uri_cloned = base_uri.clone();
         spawn_local(async move {
-            match get_file_metadata(&base_uri_cloned, id).await {
-                Ok(metadata) => clink.send_message(DownloadMsg::Metadata(Ok(metadata))),
-                Err(e) => clink.send_message(DownloadMsg::Metadata(Err(e))),
+            if let Some(app_config) = get_app_config(&base_uri_cloned).await {
+                clink.send_message(DownloadMsg::AppConfig(app_config));
             }
         });
 
@@ -172,20 +506,101 @@ impl Component for DownloadComponent {
             base_uri,
             passphrase_ref: NodeRef::default(),
             a_ref: NodeRef::default(),
-            passphrase_available: false,
+            textarea_ref: NodeRef::default(),
+            passphrase_available: fragment_key.is_some(),
             file_id: props.id,
             metadata: None,
+            first_chunk: Vec::new(),
             decrypted_filename: None,
             decrypted_text: None,
             downloaded_size: None,
             download_error: None,
+            upload_watcher: None,
+            upload_progress: None,
+            fragment_key,
+            client_error_reports: false,
+            error_reported: false,
+            text_copied: false,
+            decryption_estimate: None,
         }
     }
 
     fn update(&mut self, msg: Self::Message) -> bool {
         match msg {
-            DownloadMsg::Metadata(metadata) => {
-                self.metadata = Some(metadata);
+            DownloadMsg::AppConfig(app_config) => {
+                self.client_error_reports = app_config.client_error_reports;
+                true
+            }
+            DownloadMsg::ReportError => {
+                if let (Some(err), false) = (&self.download_error, self.error_reported) {
+                    telemetry::report(&self.base_uri, err.kind());
+                    self.error_reported = true;
+                }
+                true
+            }
+            DownloadMsg::CopyText => {
+                if let Some(textarea) = self.textarea_ref.cast::<HtmlTextAreaElement>() {
+                    textarea.select();
+                    let copied = match yew::utils::document().dyn_into::<HtmlDocument>() {
+                        Ok(document) => document.exec_command("copy"),
+                        Err(document) => Err(document.into()),
+                    };
+                    match copied {
+                        Ok(copied) => self.text_copied = copied,
+                        Err(e) => log::error!("failed to copy text: {:?}", e),
+                    }
+                }
+                true
+            }
+            DownloadMsg::WaitForUpload => {
+                match watch_upload(&self.base_uri, &self.file_id, self.link.clone()) {
+                    Ok(watcher) => self.upload_watcher = Some(watcher),
+                    Err(e) => {
+                        log::error!("failed to watch upload: {:?}", e);
+                    }
+                }
+                true
+            }
+            DownloadMsg::UploadProgress(progress) => {
+                self.upload_progress = Some(progress);
+                true
+            }
+            DownloadMsg::UploadComplete => {
+                // open file again, now that it is there
+                self.upload_watcher = None;
+                self.upload_progress = None;
+                self.metadata = None;
+                fetch_metadata(
+                    self.link.clone(),
+                    self.base_uri.clone(),
+                    self.file_id.clone(),
+                );
+                true
+            }
+            DownloadMsg::Metadata(opened) => {
+                match opened {
+                    Ok((metadata, first_chunk)) => {
+                        // with key in url, show what is going to be saved before asking for a
+                        // click. wrong key in url shows up here instead of after the click.
+                        if let (Some(key), false) = (&self.fragment_key, metadata.is_text) {
+                            match decrypt_filename(&metadata, key) {
+                                Ok(filename) => {
+                                    self.link.send_message(DownloadMsg::Filename(filename))
+                                }
+                                Err(e) => self.download_error = Some(e),
+                            }
+                        }
+                        if !metadata.is_text && metadata.size >= ESTIMATED_SIZE {
+                            let estimate = metadata.size as f64 / decryption_throughput();
+                            self.decryption_estimate = Some(estimate);
+                        }
+                        self.metadata = Some(Ok(metadata));
+                        self.first_chunk = first_chunk;
+                    }
+                    Err(e) => {
+                        self.metadata = Some(Err(e));
+                    }
+                }
 
                 true
             }
@@ -209,9 +624,10 @@ impl Component for DownloadComponent {
                     }
                 };
 
-                // get passphrase from input
-                let passphrase = if let Some(input) = self.passphrase_ref.cast::<HtmlInputElement>()
-                {
+                // get passphrase from url, or from input
+                let passphrase = if let Some(key) = &self.fragment_key {
+                    key.clone()
+                } else if let Some(input) = self.passphrase_ref.cast::<HtmlInputElement>() {
                     input.value()
                 } else {
                     let msg = "cannot get passphrase string from input";
@@ -224,6 +640,7 @@ impl Component for DownloadComponent {
                 self.decrypted_filename = None;
                 self.downloaded_size = None;
                 self.download_error = None;
+                self.error_reported = false;
 
                 if metadata.is_text {
                     self.link
@@ -265,28 +682,47 @@ impl Component for DownloadComponent {
                 self.link
                     .send_message(DownloadMsg::Filename(decrypted_filename));
 
-                let file_id = self.file_id;
+                let file_id = self.file_id.clone();
                 let metadata = metadata.clone();
+                let first_chunk = self.first_chunk.clone();
                 let clink = self.link.clone();
                 let base_uri = self.base_uri.clone();
                 spawn_local(async move {
-                    let stream = match get_download_stream(&base_uri, file_id).await {
-                        Ok(stream) => stream,
-                        Err(e) => {
-                            log::error!("cannot get stream: {:?}", e);
-                            clink.send_message(DownloadMsg::DownloadError(DownloadError::JsValue(
-                                e,
-                            )));
-                            return;
-                        }
-                    };
+                    // first chunk is already here, so fetch only the rest if there is any
+                    let has_rest = (first_chunk.len() as i64) < metadata.size;
+                    let first = stream::once(async move { Ok::<_, DownloadError>(first_chunk) });
+                    let mut stream: Pin<Box<dyn Stream<Item = Result<Vec<u8>, DownloadError>>>> =
+                        if has_rest {
+                            let mirror = fastest_base_uri(&base_uri, &metadata.mirrors).await;
+                            let ticket = metadata.download_ticket.as_deref();
+                            let rest = match get_download_stream(&mirror, &file_id, 2, ticket).await
+                            {
+                                Ok(stream) => Ok(stream),
+                                // mirror may be failing while answering ping
+                                Err(e) if mirror != base_uri => {
+                                    log::error!("mirror failed, falling back to origin: {:?}", e);
+                                    get_download_stream(&base_uri, &file_id, 2, ticket).await
+                                }
+                                Err(e) => Err(e),
+                            };
+                            let rest = match rest {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    log::error!("cannot get stream: {:?}", e);
+                                    clink.send_message(DownloadMsg::DownloadError(e));
+                                    return;
+                                }
+                            };
 
-                    let stream = stream.into_stream();
-                    let stream = stream
-                        .and_then(|b| async move { b.dyn_into::<Uint8Array>() })
-                        .map_err(DownloadError::JsValue)
-                        .map_ok(|arr| arr.to_vec());
-                    let mut stream = Box::pin(stream);
+                            let rest = rest
+                                .into_stream()
+                                .and_then(|b| async move { b.dyn_into::<Uint8Array>() })
+                                .map_err(DownloadError::JsValue)
+                                .map_ok(|arr| arr.to_vec());
+                            Box::pin(first.chain(rest))
+                        } else {
+                            Box::pin(first)
+                        };
 
                     // make cipher
                     let cipher = XChaCha20Poly1305::new(&key);
@@ -373,58 +809,17 @@ impl Component for DownloadComponent {
                 let cipher = XChaCha20Poly1305::new(&key);
                 let nonce = *XNonce::from_slice(&metadata.nonce);
 
-                let file_id = self.file_id;
-                let base_uri = self.base_uri.clone();
-                let clink = self.link.clone();
-                let decrypt_fn = async move {
-                    let client = reqwest::Client::new();
-                    let resp = client
-                        .get(join_uri(&base_uri, "/api/download"))
-                        .query(&[("id", file_id)])
-                        .send()
-                        .await;
-                    let resp = match resp {
-                        Ok(resp) => {
-                            if resp.status() == 404 {
-                                return Err(DownloadError::MetadataError(
-                                    MetadataError::FileNotFound,
-                                ));
-                            } else if resp.status() != 200 {
-                                return Err(DownloadError::MetadataError(
-                                    MetadataError::NotAvailable,
-                                ));
-                            }
-                            resp
-                        }
-                        Err(_) => {
-                            return Err(DownloadError::MetadataError(MetadataError::NotAvailable));
-                        }
-                    };
-                    let body = match resp.bytes().await {
-                        Ok(body) => body,
-                        Err(_) => {
-                            return Err(DownloadError::MetadataError(MetadataError::NotAvailable));
-                        }
-                    };
-
-                    let decrypted = match cipher.decrypt(&nonce, body.as_ref()) {
-                        Ok(decrypted) => decrypted,
-                        Err(e) => {
-                            return Err(DownloadError::Aead(e));
-                        }
-                    };
-
-                    clink.send_message(DownloadMsg::TextDownloadComplete(decrypted));
-
-                    Ok(())
-                };
-
-                let clink = self.link.clone();
-                spawn_local(decrypt_fn.map(move |res| {
-                    if let Err(e) = res {
-                        clink.send_message(DownloadMsg::DownloadError(e));
+                // text is never split, so first chunk is the whole ciphertext
+                match cipher.decrypt(&nonce, self.first_chunk.as_ref()) {
+                    Ok(decrypted) => {
+                        self.link
+                            .send_message(DownloadMsg::TextDownloadComplete(decrypted));
+                    }
+                    Err(e) => {
+                        self.link
+                            .send_message(DownloadMsg::DownloadError(DownloadError::Aead(e)));
                     }
-                }));
+                }
 
                 true
             }
@@ -466,6 +861,7 @@ impl Component for DownloadComponent {
             }
             DownloadMsg::DownloadError(err) => {
                 self.download_error = Some(err);
+                self.error_reported = false;
 
                 true
             }
@@ -554,22 +950,25 @@ impl Component for DownloadComponent {
         false
     }
 
+    fn rendered(&mut self, _first_render: bool) {
+        set_title(title(self).as_deref());
+    }
+
     fn view(&self) -> Html {
         let passphrase_oninput = self.link.callback(|_| DownloadMsg::PassphraseInput);
         let download_onclick = self.link.callback(|_| DownloadMsg::StartDownload);
+        let wait_onclick = self.link.callback(|_| DownloadMsg::WaitForUpload);
 
         let mut button_class = vec![
             "border-solid",
-            "bg-gray-700",
-            "text-gray-300",
+            "hako-button",
             "px-5",
             "py-3",
             "my-5",
             "rounded-xl",
         ];
         if self.passphrase_available {
-            button_class.push("hover:bg-gray-400");
-            button_class.push("hover:text-gray-700");
+            button_class.push("hako-button-enabled");
             button_class.push("cursor-pointer");
         } else {
             button_class.push("cursor-not-allowed");
@@ -577,16 +976,66 @@ impl Component for DownloadComponent {
 
         let make_meta_span = |s: &str| {
             html! {
-                <span class=classes!("text-gray-900", "mt-3")>{ s }</span>
+                <span class=classes!("hako-status", "mt-3")>{ s }</span>
             }
         };
         let metadata_div = match self.metadata {
             Some(ref m) => match m {
-                Ok(_) => make_meta_span("Enter passphrase"),
+                Ok(_) => match self.decryption_estimate {
+                    Some(estimate) if estimate >= SLOW_DECRYPTION_SECS => html! {
+                        <div class=classes!("flex", "flex-col", "items-center")>
+                            { make_meta_span("Enter passphrase") }
+                            <span class=classes!("hako-muted", "mt-2")>
+                                { format!(
+                                    "Decrypting this file may take {} on this device. A faster computer may be a better fit for it",
+                                    format_duration(estimate)
+                                ) }
+                            </span>
+                        </div>
+                    },
+                    _ => make_meta_span("Enter passphrase"),
+                },
                 Err(e) => match e {
                     MetadataError::FileNotFound => make_meta_span("File not found"),
+                    MetadataError::Gone => make_meta_span("This file has expired"),
                     MetadataError::NotAvailable => make_meta_span("Server not available"),
+                    MetadataError::ServerError(error, request_ref) => {
+                        make_meta_span(&server_error_text(error, request_ref))
+                    }
                     MetadataError::Deserialize => make_meta_span("Malformed response from server"),
+                    MetadataError::NotYetAvailable(available_from) => make_meta_span(&format!(
+                        "This file will be available from {}",
+                        local_date(*available_from)
+                    )),
+                    MetadataError::UnsupportedFormat(version) => make_meta_span(&format!(
+                        "This file uses format v{} of newer Hako. Please update your client (reload this page)",
+                        version
+                    )),
+                    MetadataError::UploadInProgress if self.upload_watcher.is_some() => {
+                        let text = match &self.upload_progress {
+                            Some(UploadProgress {
+                                received_bytes,
+                                expected_size: Some(expected_size),
+                            }) if *expected_size > 0 => format!(
+                                "Sender is still uploading: {}%. Download is ready as soon as it finishes",
+                                received_bytes * 100 / expected_size
+                            ),
+                            Some(progress) => format!(
+                                "Sender is still uploading: {} received. Download is ready as soon as it finishes",
+                                format_size(progress.received_bytes as u64)
+                            ),
+                            None => "Waiting for sender to finish uploading...".into(),
+                        };
+                        make_meta_span(&text)
+                    }
+                    MetadataError::UploadInProgress => html! {
+                        <span class=classes!("hako-status", "mt-3")>
+                            { "Sender is still uploading this file. " }
+                            <span class=classes!("hako-link", "cursor-pointer") onclick={wait_onclick}>
+                                { "Notify me when ready" }
+                            </span>
+                        </span>
+                    },
                 },
             },
             None => make_meta_span("Loading..."),
@@ -633,15 +1082,23 @@ impl Component for DownloadComponent {
                 DownloadError::KeyGeneration(msg) => format!("Key error: {}", msg).into(),
                 DownloadError::JsValue(_) => "File read error".into(),
                 DownloadError::Aead(_) => "Decryption error".into(),
+                DownloadError::MetadataError(MetadataError::ServerError(error, request_ref)) => {
+                    server_error_text(error, request_ref).into()
+                }
                 DownloadError::MetadataError(_) => "File unavailable".into(),
                 DownloadError::Utf8Error(_) => "UTF-8 conversion error".into(),
+                DownloadError::Remote(msg) => format!("Server error: {}", msg).into(),
                 DownloadError::Other => "Unknown error".into(),
             },
             None => "".into(),
         };
         let download_error_component = html! {
             <div class=classes!(download_error_class)>
-                <span class=classes!("text-red-300")>{ download_error_text }</span>
+                <span class=classes!("hako-error")>{ download_error_text }</span>
+                <pre class=classes!("hako-link", "cursor-pointer", "ml-3") hidden={!self.client_error_reports || self.error_reported} onclick={self.link.callback(|_| DownloadMsg::ReportError)}>
+                    { "report" }
+                </pre>
+                <span class=classes!("hako-muted", "ml-3") hidden={!self.error_reported}>{ "reported, thanks" }</span>
             </div>
         };
         let decrypted_filename = self.decrypted_filename.clone().unwrap_or_else(|| "".into());
@@ -658,25 +1115,28 @@ impl Component for DownloadComponent {
                 <div class=classes!("flex", "justify-center", "my-5")>
                     { metadata_div }
                 </div>
-                <div class=classes!("flex", "justify-center")>
+                <div class=classes!("flex", "justify-center") hidden={self.fragment_key.is_some()}>
                     <input
                         id="passphrase"
                         type="password"
                         ref={self.passphrase_ref.clone()}
-                        class=classes!("px-4", "py-2", "rounded-lg", "border", "border-gray-300", "focus:outline-none", "focus:ring-2", "focus:ring-gray-200", "text-center")
+                        class=classes!("hako-input", "px-4", "py-2", "rounded-lg", "border", "focus:outline-none", "focus:ring-2", "text-center")
                         disabled=disabled
                         placeholder={ "Passphrase" }
                         oninput={passphrase_oninput}
                     />
                 </div>
                 <div class=classes!("flex", "justify-center", "mt-5")>
-                    <p class=classes!("text-gray-300", "mb-3")>{ &decrypted_filename }</p>
+                    <p class=classes!("hako-text", "mb-3")>{ &decrypted_filename }</p>
                 </div>
                 <div class=classes!(progress_class)>
-                    <div class=classes!("overflow-hidden", "h-2", "mb-4", "text-xs", "flex", "rounded", "bg-blue-200", "w-1/2", "mt-4")>
-                        <div style={format!("width:{}%", progress_percent_width)} class=classes!("shadow-none", "flex", "flex-col", "text-center", "whitespace-nowrap", "text-white", "justify-center", "bg-blue-400")></div>
+                    <div role="progressbar" aria-label="Download progress" aria-valuemin="0" aria-valuemax="100" aria-valuenow={progress_percent_width.to_string()} class=classes!("overflow-hidden", "h-2", "mb-4", "text-xs", "flex", "rounded", "hako-progress", "w-1/2", "mt-4")>
+                        <div style={format!("width:{}%", progress_percent_width)} class=classes!("shadow-none", "flex", "flex-col", "text-center", "whitespace-nowrap", "text-white", "justify-center", "hako-progress-bar")></div>
                     </div>
                 </div>
+                <div class=classes!("sr-only") aria-live="polite">
+                    { progress_milestone(progress_percent_width, "downloaded") }
+                </div>
                 { text_input(self, textarea_class) }
                 <div class=classes!("flex", "justify-center")>
                     <button
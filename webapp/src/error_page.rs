@@ -0,0 +1,37 @@
+use yew::{classes, html, Html};
+use yew_router::{
+    agent::{RouteAgentDispatcher, RouteRequest},
+    components::RouterAnchor,
+    route::Route,
+};
+
+use crate::{
+    switch::{AppRoute, Page},
+    utils::set_title,
+};
+
+pub fn error_page(title: &str, detail: &str) -> Html {
+    set_title(Some(title));
+    html! {
+        <>
+            <div class=classes!("flex", "justify-center", "mt-12")>
+                <span class=classes!("text-2xl", "hako-text")>{ title }</span>
+            </div>
+            <div class=classes!("flex", "justify-center", "mt-3")>
+                <span class=classes!("hako-muted")>{ detail }</span>
+            </div>
+            <div class=classes!("flex", "justify-center", "my-5")>
+                <RouterAnchor<Page> route=Page(AppRoute::Upload) classes="hako-link">
+                    { "Back to upload" }
+                </RouterAnchor<Page>>
+            </div>
+        </>
+    }
+}
+
+// leave current page for error page, when app cannot go on at all
+pub fn show_fatal_error() {
+    RouteAgentDispatcher::<()>::new().send(RouteRequest::ChangeRoute(Route::from(Page(
+        AppRoute::Error,
+    ))));
+}
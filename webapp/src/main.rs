@@ -1,10 +1,19 @@
 use yew::{classes, html, Component, ComponentLink, Html};
 use yew_router::router::Router;
 
-use crate::{download::DownloadComponent, switch::AppRoute, upload::UploadComponent};
+use crate::{
+    download::DownloadComponent,
+    error_page::error_page,
+    switch::{is_share_id, without_fragment, AppRoute, Page},
+    upload::{UploadComponent, UploadType},
+};
 
+mod app_config;
 mod download;
+mod error_page;
+mod passphrase;
 mod switch;
+mod telemetry;
 mod upload;
 mod utils;
 
@@ -28,19 +37,22 @@ impl Component for MainComponent {
 
     fn view(&self) -> Html {
         html! {
-            <div class=classes!("bg-gray-500", "h-screen", "flex")>
-                <div class=classes!("m-auto", "min-w-full", "lg:min-w-1/2", "min-h-full", "lg:min-h-0", "border-solid", "border-2", "border-opacity-20", "rounded-xl")>
-                    <h1 class=classes!("text-center", "text-6xl", "text-gray-300", "font-sans", "m-5")>
+            <div class=classes!("hako-app", "h-screen", "flex")>
+                <div class=classes!("hako-panel", "m-auto", "min-w-full", "lg:min-w-1/2", "min-h-full", "lg:min-h-0", "border-solid", "border-2", "border-opacity-20", "rounded-xl")>
+                    <h1 class=classes!("text-center", "text-6xl", "hako-text", "font-sans", "m-5")>
                         { "Hako" }
                     </h1>
-                    <Router<AppRoute>
-                        render = Router::render(|switch: AppRoute| {
+                    <Router<Page>
+                        render = Router::render(|Page(switch): Page| {
                             match switch {
                                 AppRoute::Upload => html! { <UploadComponent /> },
-                                AppRoute::Download(id) => html! { <DownloadComponent id=id /> },
+                                AppRoute::Paste => html! { <UploadComponent upload_type=UploadType::Text /> },
+                                AppRoute::Download(id) if is_share_id(without_fragment(&id)) => html! { <DownloadComponent id=without_fragment(&id).to_owned() /> },
+                                AppRoute::Download(path) => error_page("Page not found", &format!("There is nothing at /{}", path)),
+                                AppRoute::Error => error_page("Something went wrong", "Hako cannot continue in this browser. Please try again later."),
+                                AppRoute::NotFound(path) => error_page("Page not found", &format!("There is nothing at /{}", path)),
                             }
                         })
-                        redirect = Router::redirect(|_| { AppRoute::Upload })
                     />
                 </div>
             </div>
@@ -0,0 +1,80 @@
+// common english words for generated passphrases, one per line
+const WORDLIST: &str = include_str!("wordlist.txt");
+// number of words in generated passphrase. with about 1800 words, this gives over 60 bits of
+// entropy.
+pub const PASSPHRASE_WORDS: usize = 6;
+
+// diceware-style passphrase of words picked uniformly at random
+pub fn generate_passphrase() -> Result<String, getrandom::Error> {
+    let words: Vec<&str> = WORDLIST.lines().collect();
+    let count = words.len() as u32;
+    // values past largest multiple of word count would favor first words
+    let zone = u32::MAX - u32::MAX % count;
+
+    let mut picked = Vec::with_capacity(PASSPHRASE_WORDS);
+    while picked.len() < PASSPHRASE_WORDS {
+        let mut b = [0u8; 4];
+        getrandom::getrandom(&mut b)?;
+        let v = u32::from_be_bytes(b);
+        if v < zone {
+            picked.push(words[(v % count) as usize]);
+        }
+    }
+
+    Ok(picked.join("-"))
+}
+
+// entropy of passphrase made by `generate_passphrase`
+pub fn generated_bits() -> f64 {
+    PASSPHRASE_WORDS as f64 * (WORDLIST.lines().count() as f64).log2()
+}
+
+// rough entropy of passphrase chosen by human, by NIST SP 800-63 (2004) rule of thumb. people
+// pick predictable strings, so counting character classes would overestimate a lot.
+pub fn estimate_bits(passphrase: &str) -> f64 {
+    let mut bits = 0_f64;
+    for i in 0..passphrase.chars().count() {
+        bits += match i {
+            0 => 4.0,
+            1..=7 => 2.0,
+            8..=19 => 1.5,
+            _ => 1.0,
+        };
+    }
+    let has_upper = passphrase.chars().any(|c| c.is_uppercase());
+    let has_non_alpha = passphrase.chars().any(|c| !c.is_alphabetic());
+    if has_upper && has_non_alpha {
+        bits += 6.0;
+    }
+    bits
+}
+
+// key is derived by single hkdf-sha256 run, which is cheap. assume offline attacker with a few
+// GPUs, which compute billions of sha256 per second.
+pub const GUESSES_PER_SEC: f64 = 1e10;
+
+// time to find passphrase of given entropy on average, at `GUESSES_PER_SEC`
+pub fn crack_time(bits: f64) -> String {
+    let secs = 2_f64.powf(bits - 1.0) / GUESSES_PER_SEC;
+    let units = [
+        (60.0 * 60.0 * 24.0 * 365.0, "year"),
+        (60.0 * 60.0 * 24.0, "day"),
+        (60.0 * 60.0, "hour"),
+        (60.0, "minute"),
+        (1.0, "second"),
+    ];
+    if secs < 1.0 {
+        return "less than a second".into();
+    }
+    let years = secs / units[0].0;
+    if years >= 1e6 {
+        return "millions of years".into();
+    }
+    for (unit_secs, name) in units {
+        if secs >= unit_secs {
+            let n = (secs / unit_secs).round() as u64;
+            return format!("~{} {}{}", n, name, if n == 1 { "" } else { "s" });
+        }
+    }
+    unreachable!()
+}
@@ -1,9 +1,61 @@
 use yew_router::Switch;
 
+use crate::utils::base_path;
+
 #[derive(Switch, Debug, Clone)]
 pub enum AppRoute {
+    #[to = "/error"]
+    Error,
+    #[to = "/paste"]
+    Paste,
+    // share id, checked by `is_share_id`
     #[to = "/{id}"]
-    Download(i64),
-    #[to = "/"]
+    Download(String),
+    #[to = "/!"]
     Upload,
+    // every other path
+    #[to = "/{*:path}"]
+    NotFound(String),
+}
+
+// route of app under base path of server. paths outside it are not found
+#[derive(Debug, Clone)]
+pub struct Page(pub AppRoute);
+
+impl Switch for Page {
+    fn from_route_part<STATE>(part: String, state: Option<STATE>) -> (Option<Self>, Option<STATE>) {
+        let base_path = base_path();
+        let rest = match part.strip_prefix(base_path.as_str()) {
+            Some(rest) if rest.starts_with('/') => rest.to_owned(),
+            // base path itself, which is upload page
+            Some(rest) if rest.is_empty() || rest.starts_with('?') || rest.starts_with('#') => {
+                format!("/{}", rest)
+            }
+            _ => {
+                let path = part.trim_start_matches('/').to_owned();
+                return (Some(Page(AppRoute::NotFound(path))), state);
+            }
+        };
+        let (route, state) = AppRoute::from_route_part(rest, state);
+        (route.map(Page), state)
+    }
+
+    fn build_route_section<STATE>(self, route: &mut String) -> Option<STATE> {
+        route.push_str(&base_path());
+        self.0.build_route_section(route)
+    }
+}
+
+// share ids are random slugs of letters, digits, `-` and `_` as configured on server. numeric ids
+// of older links, optionally followed by `-` and signature in hex, are made of the same
+pub fn is_share_id(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+// fragment may be matched as part of share id, but it is only for webapp to read. see
+// `fragment_key` of download page
+pub fn without_fragment(s: &str) -> &str {
+    s.split('#').next().unwrap_or(s)
 }
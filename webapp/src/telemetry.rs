@@ -0,0 +1,62 @@
+use serde::Serialize;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::utils::join_uri;
+
+#[derive(Serialize)]
+struct ClientErrorReport {
+    kind: &'static str,
+    app_version: &'static str,
+    browser: &'static str,
+}
+
+// family of browser from its user agent. edge and opera claim to be chrome too, so they are
+// checked first, and chrome claims to be safari
+fn browser_family() -> &'static str {
+    let user_agent = yew::utils::window()
+        .navigator()
+        .user_agent()
+        .unwrap_or_default()
+        .to_lowercase();
+    [
+        ("edg/", "edge"),
+        ("opr/", "opera"),
+        ("firefox/", "firefox"),
+        ("chrome/", "chrome"),
+        ("safari/", "safari"),
+    ]
+    .iter()
+    .find(|(token, _)| user_agent.contains(token))
+    .map_or("other", |(_, family)| family)
+}
+
+// send kind of error which user chose to report. message of error is left out, as it may
+// contain file names
+pub fn report(base_uri: &str, kind: &'static str) {
+    let report = ClientErrorReport {
+        kind,
+        app_version: env!("CARGO_PKG_VERSION"),
+        browser: browser_family(),
+    };
+    let body = match serde_json::to_string(&report) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("failed to serialize error report: {:?}", e);
+            return;
+        }
+    };
+
+    let uri = join_uri(base_uri, "/api/client_error");
+    spawn_local(async move {
+        let client = reqwest::Client::new();
+        let result = client
+            .post(uri)
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await;
+        if let Err(e) = result {
+            log::error!("failed to report error: {:?}", e);
+        }
+    });
+}
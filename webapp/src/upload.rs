@@ -3,31 +3,57 @@ use std::borrow::Cow;
 use aead::generic_array::GenericArray;
 use chacha20poly1305::aead::{Aead, NewAead};
 use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
-use futures_util::{FutureExt, TryStreamExt};
+use futures_channel::mpsc;
+use futures_util::future::{self, Either};
+use futures_util::{pin_mut, stream::Stream, FutureExt, StreamExt, TryStreamExt};
+use hako_core::{field, PreparedFile, NONCE_LEN, SALT_LEN, STREAM_NONCE_LEN};
 use hkdf::Hkdf;
 use js_sys::Uint8Array;
 use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
 use serde_json::Value;
-use sha2::Sha256;
-use wasm_bindgen::{JsCast, JsValue};
-use wasm_bindgen_futures::spawn_local;
+use sha2::{Digest, Sha256};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+    Blob, BlobEvent, Event, MediaRecorder, MediaStream, MediaStreamConstraints, MediaStreamTrack,
+};
 use yew::{
     classes, html,
     web_sys::{HtmlInputElement, HtmlTextAreaElement},
-    ChangeData, Component, ComponentLink, Html, NodeRef,
+    ChangeData, Component, ComponentLink, Html, NodeRef, Properties,
 };
 
-use crate::utils::{join_uri, BLOCK_SIZE};
+use crate::app_config::{get_app_config, AppConfig};
+use crate::error_page::show_fatal_error;
+use crate::passphrase::{crack_time, estimate_bits, generate_passphrase, generated_bits};
+use crate::telemetry;
+use crate::utils::{
+    base_uri, chunk_size_hint, error_body, format_size, join_uri, progress_milestone, request_ref,
+    set_title, sleep, BLOCK_OVERHEAD, BLOCK_SIZE, FORMAT_VERSION,
+};
 
 pub enum UploadMsg {
+    AppConfig(AppConfig),
+    ReportError,
     FileChanged(web_sys::File),
     PassphraseInput,
-    ChangeUploadType,
+    GeneratePassphrase,
+    ChangeUploadType(UploadType),
+    RecordSourceChanged(RecordSource),
     FileUploadStart,
     TextUploadStart,
+    RecordStart,
+    RecordStream(MediaStream),
+    RecordStop,
     Progress(ProgressInfo),
+    // seconds until request refused by rate limit is retried, or none when it is retried
+    RateLimited(Option<u64>),
     UploadError(UploadError),
-    UploadComplete(i64),
+    // share id and deletion token of uploaded file
+    UploadComplete(String, Option<String>),
+    DeleteFile,
+    DeleteComplete(Result<(), UploadError>),
 }
 
 #[derive(Debug)]
@@ -35,30 +61,394 @@ pub enum UploadError {
     JsValue(JsValue),
     Aead(aead::Error),
     Remote(String),
+    TooLarge(u64),
+    ForbiddenType(String),
+    TextQuotaExceeded(u64),
+    Capture(JsValue),
 }
 
-#[derive(Clone)]
+impl UploadError {
+    // kind of error in error report, without details
+    fn kind(&self) -> &'static str {
+        match self {
+            UploadError::JsValue(_) => "upload_file_read",
+            UploadError::Aead(_) => "upload_encryption",
+            UploadError::Remote(_) => "upload_server",
+            UploadError::TooLarge(_) => "upload_too_large",
+            UploadError::ForbiddenType(_) => "upload_forbidden_type",
+            UploadError::TextQuotaExceeded(_) => "upload_text_quota",
+            UploadError::Capture(_) => "upload_capture",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum UploadType {
     File,
     Text,
+    Recording,
+}
+
+impl UploadType {
+    fn name(&self) -> &'static str {
+        match self {
+            UploadType::File => "File",
+            UploadType::Text => "Text",
+            UploadType::Recording => "Recording",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RecordSource {
+    Voice,
+    Screen,
+}
+
+// interval of `dataavailable` events while recording, so that chunks are encrypted and uploaded
+// while recording is still in progress
+const RECORD_TIMESLICE_MS: i32 = 1000;
+
+// media capture in progress. event handlers must live until recorder fires `stop` event, which
+// closes the channel feeding the encryptor.
+struct Recording {
+    media_stream: MediaStream,
+    recorder: MediaRecorder,
+    stopped: bool,
+    _ondataavailable: Closure<dyn FnMut(BlobEvent)>,
+    _onstop: Closure<dyn FnMut(Event)>,
+}
+
+impl Recording {
+    fn stop(&mut self) {
+        // recorder may be already inactive, for example when user ended screen sharing
+        let _ = self.recorder.stop();
+        for track in self.media_stream.get_tracks().iter() {
+            if let Ok(track) = track.dyn_into::<MediaStreamTrack>() {
+                track.stop();
+            }
+        }
+        self.stopped = true;
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        // handlers are freed with this struct, so do not let recorder call them anymore
+        self.recorder.set_ondataavailable(None);
+        self.recorder.set_onstop(None);
+    }
 }
 
 pub enum ProgressInfo {
     UploadBytes(usize),
 }
 
+#[derive(Properties, Clone, PartialEq)]
+pub struct UploadProps {
+    #[prop_or(UploadType::File)]
+    pub upload_type: UploadType,
+}
+
 pub struct UploadComponent {
     link: ComponentLink<Self>,
     base_uri: String,
+    app_config: Option<AppConfig>,
     selected_file: Option<web_sys::File>,
     upload_type: UploadType,
+    record_source: RecordSource,
+    recording: Option<Recording>,
     textarea_ref: NodeRef,
+    // focus textarea on next render, when page is opened for pasting text
+    focus_textarea: bool,
     passphrase_ref: NodeRef,
+    available_from_ref: NodeRef,
+    max_downloads_ref: NodeRef,
+    key_in_link_ref: NodeRef,
+    api_key_ref: NodeRef,
     passphrase_available: bool,
+    // passphrase was generated, so it is shown to be sent to recipient
+    passphrase_generated: bool,
+    // estimated entropy of current passphrase
+    passphrase_bits: Option<f64>,
     file_size: Option<usize>,
     uploaded_size: Option<usize>,
-    file_id: Option<i64>,
+    share_id: Option<String>,
+    // passphrase put in fragment of share url, if uploader chose so
+    link_key: Option<String>,
+    deletion_token: Option<String>,
+    deleted: bool,
+    // seconds until rate limited request is retried
+    rate_limited: Option<u64>,
     upload_error: Option<UploadError>,
+    // current error was reported to server operator
+    error_reported: bool,
+}
+
+// detail of error response which server returns when the file needs more chunks than it allows
+#[derive(Deserialize)]
+struct ChunkCountExceeded {
+    max_file_size: u64,
+}
+
+// detail of error response which server returns when client made too many text uploads today
+#[derive(Deserialize)]
+struct TextQuotaExceeded {
+    text_uploads_per_day: u64,
+}
+
+// detail of response to last chunk which server returns when chunks before it are missing
+#[derive(Deserialize)]
+struct ChunksMissing {
+    missing_chunks: i64,
+    missing_seqs: Vec<i64>,
+}
+
+// chunks which ciphertext of `size` bytes of plaintext is uploaded in, when every encrypted block
+// is cut into chunks of `chunk_len`
+fn chunk_count(size: u64, chunk_len: usize) -> u64 {
+    let chunk_len = chunk_len as u64;
+    let per_block = ((BLOCK_SIZE + BLOCK_OVERHEAD) as u64 + chunk_len - 1) / chunk_len;
+    let last_block = size % BLOCK_SIZE as u64 + BLOCK_OVERHEAD as u64;
+    size / BLOCK_SIZE as u64 * per_block + (last_block + chunk_len - 1) / chunk_len
+}
+
+// length of chunks which encrypted blocks are cut into for this connection. blocks are cut into
+// equal parts, and into fewer of them if file would need more chunks than server takes
+fn chunk_len(size: Option<u64>, chunk_count_limit: Option<u64>) -> usize {
+    let block = BLOCK_SIZE + BLOCK_OVERHEAD;
+    let hint = chunk_size_hint(block);
+    let mut parts = (block + hint - 1) / hint;
+    loop {
+        let len = (block + parts - 1) / parts;
+        let fits = match (size, chunk_count_limit) {
+            (Some(size), Some(limit)) => chunk_count(size, len) <= limit,
+            _ => true,
+        };
+        if fits || parts == 1 {
+            return len;
+        }
+        parts -= 1;
+    }
+}
+
+fn prepare_request(
+    client: &reqwest::Client,
+    base_uri: &str,
+    api_key: Option<&str>,
+    form: Form,
+) -> reqwest::RequestBuilder {
+    let req = client
+        .post(join_uri(base_uri, "/api/prepare_upload"))
+        .multipart(form);
+    match api_key {
+        Some(key) => req.header("authorization", format!("Bearer {}", key)),
+        None => req,
+    }
+}
+
+// id, share id, deletion token and upload token of file from response of prepare_upload, or of
+// upload_small which responds the same
+async fn prepared_file(
+    api: &str,
+    resp: reqwest::Response,
+) -> Result<(i64, String, Option<String>, Option<String>), UploadError> {
+    if resp.status() != 200 {
+        return Err(remote_error(api, resp).await);
+    }
+    let b = match resp.bytes().await {
+        Ok(b) => b.to_vec(),
+        Err(_) => {
+            return Err(UploadError::Remote("failed to read resp body".into()));
+        }
+    };
+    match serde_json::from_slice::<PreparedFile>(b.as_ref()) {
+        // servers before signed share urls do not return share id, which is plain file id then
+        Ok(f) => Ok((
+            f.id,
+            f.share_id.unwrap_or_else(|| f.id.to_string()),
+            f.deletion_token,
+            f.upload_token,
+        )),
+        Err(_) => Err(UploadError::Remote("failed to deserialize body".into())),
+    }
+}
+
+fn upload_small_request(
+    client: &reqwest::Client,
+    base_uri: &str,
+    api_key: Option<&str>,
+    form: Form,
+) -> reqwest::RequestBuilder {
+    let req = client
+        .post(join_uri(base_uri, "/api/upload_small"))
+        .multipart(form);
+    match api_key {
+        Some(key) => req.header("authorization", format!("Bearer {}", key)),
+        None => req,
+    }
+}
+
+fn upload_request(
+    client: &reqwest::Client,
+    base_uri: &str,
+    upload_token: Option<&str>,
+    form: Form,
+) -> reqwest::RequestBuilder {
+    let req = client
+        .post(join_uri(base_uri, "/api/upload"))
+        .multipart(form);
+    match upload_token {
+        Some(token) => req.header("authorization", format!("Bearer {}", token)),
+        None => req,
+    }
+}
+
+// send request built by `request`, and when rate limit refuses it, wait as long as server tells in
+// `retry-after` and send again. request is built for every attempt, as multipart body is consumed
+// by sending. refusals without `retry-after`, e.g. of quotas, are returned as they are.
+async fn send_retrying<F>(
+    clink: &ComponentLink<UploadComponent>,
+    request: F,
+) -> reqwest::Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    loop {
+        let resp = request().send().await?;
+        if resp.status() != 429 {
+            return Ok(resp);
+        }
+        let retry_after = resp
+            .headers()
+            .get("retry-after")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        let retry_after = match retry_after {
+            Some(retry_after) => retry_after,
+            None => return Ok(resp),
+        };
+        log::error!("rate limited, retrying in {} s", retry_after);
+        clink.send_message(UploadMsg::RateLimited(Some(retry_after)));
+        sleep(retry_after).await;
+        clink.send_message(UploadMsg::RateLimited(None));
+    }
+}
+
+// prepare is sent this many times before its failure is shown
+const PREPARE_ATTEMPTS: u64 = 3;
+// seconds to wait for answer of prepare before sending it again. chunk of upload_small is sized
+// for the connection, so it is sent well within this
+const PREPARE_TIMEOUT: u64 = 60;
+
+// send prepare request, which carries idempotency key, and send it again with the same key when it
+// fails on the way, gets no answer in time or server fails. attempt whose answer was lost may have
+// prepared file already, and server answers the retry with that file instead of another one.
+async fn send_idempotent<F>(
+    clink: &ComponentLink<UploadComponent>,
+    request: F,
+) -> reqwest::Result<reqwest::Response>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    for attempt in 1..PREPARE_ATTEMPTS {
+        let send = send_retrying(clink, &request);
+        let timeout = sleep(PREPARE_TIMEOUT);
+        pin_mut!(send, timeout);
+        match future::select(send, timeout).await {
+            Either::Left((Ok(resp), _)) if !resp.status().is_server_error() => return Ok(resp),
+            Either::Left((Ok(resp), _)) => {
+                log::error!("prepare failed with {}, retrying", resp.status());
+            }
+            Either::Left((Err(e), _)) => log::error!("prepare failed: {:?}, retrying", e),
+            Either::Right(_) => log::error!("prepare timed out, retrying"),
+        }
+        sleep(attempt).await;
+    }
+    // last attempt waits as long as browser does
+    send_retrying(clink, &request).await
+}
+
+// ask server for hash of stored ciphertext and compare it with hash of sent chunks, so that
+// corrupted storage is caught right after upload instead of at first download
+async fn verify_upload(
+    client: &reqwest::Client,
+    base_uri: &str,
+    share_id: &str,
+    digest: &[u8],
+) -> Result<(), UploadError> {
+    let resp = match client
+        .get(join_uri(base_uri, "/api/verify"))
+        .query(&[("id", share_id)])
+        .send()
+        .await
+    {
+        Ok(resp) => resp,
+        Err(_) => {
+            return Err(UploadError::Remote("failed to request verify".into()));
+        }
+    };
+    if resp.status() != 200 {
+        return Err(remote_error("verify", resp).await);
+    }
+    let v = match resp.bytes().await {
+        Ok(b) => match serde_json::from_slice::<Value>(b.as_ref()) {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(UploadError::Remote("failed to deserialize body".into()));
+            }
+        },
+        Err(_) => {
+            return Err(UploadError::Remote("failed to read resp body".into()));
+        }
+    };
+
+    let expected: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    match v.get("digest").and_then(Value::as_str) {
+        Some(stored) if stored == expected => Ok(()),
+        _ => Err(UploadError::Remote(
+            "stored file does not match uploaded one".into(),
+        )),
+    }
+}
+
+// turn failed response of upload APIs into error, using details given by server if any
+async fn remote_error(api: &str, resp: reqwest::Response) -> UploadError {
+    let status = resp.status();
+    let request_ref = request_ref(&resp);
+    let body = match error_body(resp).await {
+        Some(body) => body,
+        None => {
+            return UploadError::Remote(format!(
+                "{} status != 200, but {}{}",
+                api, status, request_ref
+            ))
+        }
+    };
+
+    match body.code.as_str() {
+        "chunk_count_exceeded" | "file_too_large" => {
+            if let Some(e) = body.detail::<ChunkCountExceeded>() {
+                return UploadError::TooLarge(e.max_file_size);
+            }
+        }
+        "text_quota_exceeded" => {
+            if let Some(e) = body.detail::<TextQuotaExceeded>() {
+                return UploadError::TextQuotaExceeded(e.text_uploads_per_day);
+            }
+        }
+        // chunks are sent in order, so missing ones were dropped on the way
+        "chunks_missing" => {
+            if let Some(e) = body.detail::<ChunksMissing>() {
+                return UploadError::Remote(format!(
+                    "server is missing {} chunks of upload, e.g. {:?}{}",
+                    e.missing_chunks, e.missing_seqs, request_ref
+                ));
+            }
+        }
+        _ => {}
+    }
+    UploadError::Remote(format!("{}: {}{}", api, body.describe(), request_ref))
 }
 
 fn file_input(comp: &UploadComponent) -> Html {
@@ -73,7 +463,7 @@ fn file_input(comp: &UploadComponent) -> Html {
 
     html! {
         <div class=classes!("flex", "items-center", "justify-center", "bg-gray-lighter", "mt-12")>
-            <label class=classes!("w-1/2", "flex", "flex-col", "items-center", "px-4", "py-6", "bg-gray-600", "text-gray-400", "rounded-lg", "shadow-lg", "tracking-wide", "uppercase", "border", "border-gray-400", "cursor-pointer", "hover:bg-gray-400", "hover:text-gray-600")>
+            <label class=classes!("w-1/2", "flex", "flex-col", "items-center", "px-4", "py-6", "hako-file-select", "rounded-lg", "shadow-lg", "tracking-wide", "uppercase", "border", "cursor-pointer")>
                 <svg class=classes!("w-8", "h-8") fill="currentColor" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
                     <path d="M16.88 9.1A4 4 0 0 1 16 17H5a5 5 0 0 1-1-9.9V7a3 3 0 0 1 4.52-2.59A4.98 4.98 0 0 1 17 8c0 .38-.04.74-.12 1.1zM11 11h3l-4-4-4 4h3v3h2v-3z" />
                 </svg>
@@ -93,63 +483,485 @@ fn text_input(comp: &UploadComponent) -> Html {
     }
 }
 
+fn recording_input(comp: &UploadComponent) -> Html {
+    let source_class = |source| {
+        if comp.record_source == source {
+            vec!["hako-text"]
+        } else {
+            vec!["hako-link", "cursor-pointer"]
+        }
+    };
+    let voice_onclick = comp
+        .link
+        .callback(|_| UploadMsg::RecordSourceChanged(RecordSource::Voice));
+    let screen_onclick = comp
+        .link
+        .callback(|_| UploadMsg::RecordSourceChanged(RecordSource::Screen));
+
+    html! {
+        <div class=classes!("flex", "justify-center", "mt-12")>
+            <pre class=classes!(source_class(RecordSource::Voice)) onclick={voice_onclick}>
+                { "Voice" }
+            </pre>
+            <pre class=classes!("hako-muted")>
+                { " / " }
+            </pre>
+            <pre class=classes!(source_class(RecordSource::Screen)) onclick={screen_onclick}>
+                { "Screen" }
+            </pre>
+        </div>
+    }
+}
+
+impl UploadComponent {
+    // progress goes first in title, as tabs cut off the rest
+    fn title(&self) -> Option<String> {
+        if self.upload_error.is_some() {
+            return Some("Upload failed".into());
+        }
+        if self.share_id.is_some() {
+            return Some("Upload complete".into());
+        }
+        let uploaded = self.uploaded_size?;
+        match self.file_size {
+            Some(file_size) if file_size > 0 => Some(format!(
+                "Uploading {}%…",
+                (uploaded as f64 / file_size as f64 * 100_f64) as usize
+            )),
+            _ => Some("Uploading…".into()),
+        }
+    }
+
+    // publishing time chosen by uploader, as unix timestamp
+    fn available_from(&self) -> Option<i64> {
+        let value = self.available_from_ref.cast::<HtmlInputElement>()?.value();
+        if value.is_empty() {
+            return None;
+        }
+        // `datetime-local` value is parsed in local time zone
+        let ms = js_sys::Date::new(&JsValue::from_str(&value)).get_time();
+        if ms.is_nan() {
+            return None;
+        }
+        Some((ms / 1000_f64) as i64)
+    }
+
+    // number of downloads after which server deletes the file
+    fn max_downloads(&self) -> Option<i64> {
+        let value = self.max_downloads_ref.cast::<HtmlInputElement>()?.value();
+        value.parse::<i64>().ok().filter(|n| *n > 0)
+    }
+
+    // passphrase to put in share url, so that recipient does not have to type it
+    fn key_in_link(&self) -> Option<String> {
+        let checked = self.key_in_link_ref.cast::<HtmlInputElement>()?.checked();
+        let passphrase = self.passphrase_ref.cast::<HtmlInputElement>()?.value();
+        Some(passphrase).filter(|_| checked)
+    }
+
+    // key to upload with, if server requires one
+    fn api_key(&self) -> Option<String> {
+        let value = self.api_key_ref.cast::<HtmlInputElement>()?.value();
+        Some(value.trim().to_owned()).filter(|key| !key.is_empty())
+    }
+
+    // encrypt plaintext from given stream and upload it as a file named `filename`. `size` is
+    // plaintext size if it is known up front, which lets server check the upload as a whole.
+    // returns whether the upload has started.
+    fn start_stream_upload<S>(
+        &self,
+        passphrase: &str,
+        filename: String,
+        size: Option<u64>,
+        stream: S,
+    ) -> bool
+    where
+        S: Stream<Item = Result<Vec<u8>, UploadError>> + 'static,
+    {
+        // generate salt for hkdf expand()
+        let mut salt = [0u8; SALT_LEN];
+        if let Err(err) = getrandom::getrandom(&mut salt) {
+            log::error!("cannot get random salt value: {:?}", err);
+            show_fatal_error();
+            return false;
+        }
+
+        // identifies this upload, so that retried prepare request does not create another
+        // file on server
+        let mut idempotency_key = [0u8; 16];
+        if let Err(err) = getrandom::getrandom(&mut idempotency_key) {
+            log::error!("cannot get random idempotency key: {:?}", err);
+            show_fatal_error();
+            return false;
+        }
+
+        // generate key by hkdf
+        let h = Hkdf::<Sha256>::new(Some(&salt), passphrase.as_bytes());
+        let mut key_slice = [0u8; 32];
+        if let Err(err) = h.expand(&[], &mut key_slice[..]) {
+            log::error!("cannot expand passphrase by hkdf: {:?}", err);
+            return false;
+        }
+
+        // generate nonce for XChaCha20Poly1305
+        let mut stream_nonce = [0u8; STREAM_NONCE_LEN];
+        if let Err(err) = getrandom::getrandom(&mut stream_nonce) {
+            log::error!("cannot get random nonce value: {:?}", err);
+            show_fatal_error();
+            return false;
+        }
+        let mut filename_nonce = [0u8; NONCE_LEN];
+        if let Err(err) = getrandom::getrandom(&mut filename_nonce) {
+            log::error!("cannot get random nonce value: {:?}", err);
+            show_fatal_error();
+            return false;
+        }
+
+        let key = Key::from_slice(&key_slice);
+        let cipher = XChaCha20Poly1305::new(key);
+
+        let stream_nonce = GenericArray::from_slice(stream_nonce.as_ref());
+        let filename_nonce = GenericArray::from_slice(filename_nonce.as_ref());
+
+        // encrypt filename
+        let encrypted_filename = {
+            match cipher.encrypt(
+                filename_nonce,
+                filename.bytes().collect::<Vec<u8>>().as_ref(),
+            ) {
+                Ok(encrypted) => encrypted,
+                Err(err) => {
+                    log::error!("failed to encrypt filename: {:?}", err);
+                    return true;
+                }
+            }
+        };
+
+        let mut fut = Box::pin(stream);
+
+        let stream_nonce = *stream_nonce;
+        let filename_nonce = *filename_nonce;
+        let clink = self.link.clone();
+        let base_uri = self.base_uri.clone();
+        let available_from = self.available_from();
+        let max_downloads = self.max_downloads();
+        let api_key = self.api_key();
+        let chunk_count_limit = self
+            .app_config
+            .as_ref()
+            .and_then(|app_config| app_config.chunk_count_limit);
+        let chunk_len = chunk_len(size, chunk_count_limit);
+        // every full block is encrypted on its own, and the remainder goes into last block even if
+        // empty
+        let expected = size.map(|size| {
+            let blocks = size / BLOCK_SIZE as u64 + 1;
+            (
+                chunk_count(size, chunk_len),
+                size + blocks * BLOCK_OVERHEAD as u64,
+            )
+        });
+
+        // core logic of streaming upload / encryption
+        let encrypt_routine = async move {
+            // use stream encryptor
+            let mut encryptor = aead::stream::EncryptorBE32::from_aead(cipher, &stream_nonce);
+            // send prepare request
+            let client = reqwest::Client::new();
+            let prepare_form = || {
+                let mut form = Form::new()
+                    .part(field::NONCE, Part::stream(stream_nonce.to_vec()))
+                    .part(field::FILENAME_NONCE, Part::stream(filename_nonce.to_vec()))
+                    .part(field::SALT, Part::stream(salt.to_vec()))
+                    .part(field::FILENAME, Part::stream(encrypted_filename.clone()))
+                    .part(field::FORMAT_VERSION, Part::bytes(vec![FORMAT_VERSION]))
+                    .part(
+                        field::IDEMPOTENCY_KEY,
+                        Part::bytes(idempotency_key.to_vec()),
+                    );
+                if let Some(t) = available_from {
+                    form = form.part(field::AVAILABLE_FROM, Part::bytes(t.to_be_bytes().to_vec()));
+                }
+                if let Some(n) = max_downloads {
+                    form = form.part(field::MAX_DOWNLOADS, Part::bytes(n.to_be_bytes().to_vec()));
+                }
+                if let Some((chunks, size)) = expected {
+                    form = form
+                        .part(
+                            field::EXPECTED_CHUNKS,
+                            Part::bytes(chunks.to_be_bytes().to_vec()),
+                        )
+                        .part(
+                            field::EXPECTED_SIZE,
+                            Part::bytes(size.to_be_bytes().to_vec()),
+                        );
+                }
+                form
+            };
+            // file which fits in one chunk is prepared and uploaded at once
+            if let Some((1, _)) = expected {
+                let mut buffer = Vec::new();
+                while let Some(v) = fut.try_next().await? {
+                    buffer.extend(v);
+                }
+                let chunk = encryptor
+                    .encrypt_last(buffer.as_ref())
+                    .map_err(UploadError::Aead)?;
+                let chunk_len = chunk.len();
+                let digest = Sha256::digest(&chunk).to_vec();
+                let request = || {
+                    let form = prepare_form()
+                        .part(field::CONTENT, Part::stream(chunk.clone()))
+                        .part(field::DIGEST, Part::bytes(digest.clone()));
+                    upload_small_request(&client, &base_uri, api_key.as_deref(), form)
+                };
+                let (_, share_id, deletion_token, _) = match send_idempotent(&clink, request).await
+                {
+                    Ok(resp) => prepared_file("upload_small", resp).await?,
+                    Err(e) => {
+                        log::error!("remote error: {:?}", e);
+                        return Err(UploadError::Remote("failed to request upload_small".into()));
+                    }
+                };
+                clink.send_message(UploadMsg::Progress(ProgressInfo::UploadBytes(chunk_len)));
+                verify_upload(&client, &base_uri, &share_id, &digest).await?;
+                clink.send_message(UploadMsg::UploadComplete(share_id, deletion_token));
+
+                return Ok(());
+            }
+
+            let (file_id, share_id, deletion_token, upload_token) =
+                match send_idempotent(&clink, || {
+                    prepare_request(&client, &base_uri, api_key.as_deref(), prepare_form())
+                })
+                .await
+                {
+                    Ok(resp) => prepared_file("prepare_upload", resp).await?,
+                    Err(e) => {
+                        log::error!("remote error: {:?}", e);
+                        return Err(UploadError::Remote(
+                            "failed to request prepare_upload".into(),
+                        ));
+                    }
+                };
+
+            let id = file_id.to_be_bytes().to_vec();
+            // cut encrypted block into chunks and upload them in order. returns number of chunks
+            // sent. ciphertext keeps its blocks however it is cut, so downloads do not care
+            let send_block = {
+                let (client, base_uri, upload_token, clink, id) =
+                    (&client, &base_uri, upload_token.as_deref(), &clink, &id);
+                move |first_seq: i64, block: Vec<u8>, is_last_block: bool| async move {
+                    let count = block.chunks(chunk_len).count();
+                    for (i, chunk) in block.chunks(chunk_len).enumerate() {
+                        let is_last = is_last_block && i == count - 1;
+                        let seq = (first_seq + i as i64).to_be_bytes().to_vec();
+                        let digest = Sha256::digest(chunk).to_vec();
+                        let request = || {
+                            let form = Form::new()
+                                .part(field::ID, Part::bytes(id.clone()))
+                                .part(field::SEQ, Part::bytes(seq.clone()))
+                                .part(field::IS_LAST, Part::bytes(vec![is_last as u8]))
+                                .part(field::CONTENT, Part::stream(chunk.to_vec()))
+                                .part(field::DIGEST, Part::bytes(digest.clone()));
+                            upload_request(client, base_uri, upload_token, form)
+                        };
+                        match send_retrying(clink, request).await {
+                            Ok(resp) => {
+                                if resp.status() != 200 {
+                                    return Err(remote_error("upload", resp).await);
+                                }
+                            }
+                            Err(_) => {
+                                return Err(UploadError::Remote("failed to upload chunk".into()));
+                            }
+                        }
+                        clink.send_message(UploadMsg::Progress(ProgressInfo::UploadBytes(
+                            chunk.len(),
+                        )));
+                    }
+                    Ok(count as i64)
+                }
+            };
+
+            let mut seq: i64 = 1;
+            // hash of every chunk sent, to verify stored file with
+            let mut file_hasher = Sha256::new();
+            let mut buffer = Vec::<u8>::with_capacity(BLOCK_SIZE);
+            // start encryption and upload
+            while let Some(v) = fut.try_next().await? {
+                let mut v: &[u8] = v.as_ref();
+                // divide inputs into fixed block size
+                while buffer.len() + v.len() >= BLOCK_SIZE {
+                    let split_idx = BLOCK_SIZE - buffer.len();
+                    buffer.extend(&v[..split_idx]);
+                    // upload block to server
+                    // this will block next encryption...
+                    // maybe there is more good way to handle this
+                    let block = encryptor
+                        .encrypt_next(buffer.as_ref())
+                        .map_err(UploadError::Aead)?;
+                    file_hasher.update(&block);
+                    seq += send_block(seq, block, false).await?;
+                    buffer.clear();
+                    v = &v[split_idx..];
+                }
+                buffer.extend(v);
+            }
+            // upload last block
+            let block = encryptor
+                .encrypt_last(buffer.as_ref())
+                .map_err(UploadError::Aead)?;
+            file_hasher.update(&block);
+            send_block(seq, block, true).await?;
+            verify_upload(&client, &base_uri, &share_id, &file_hasher.finalize()).await?;
+            clink.send_message(UploadMsg::UploadComplete(share_id, deletion_token));
+
+            Ok(())
+        };
+
+        let clink = self.link.clone();
+        // spawn entire routine in promise
+        // TODO: research Web Workers and try to gain more performance
+        spawn_local(encrypt_routine.map(move |r: Result<(), UploadError>| {
+            if let Err(e) = r {
+                log::error!("encryption error: {:?}", e);
+                clink.send_message(UploadMsg::UploadError(e));
+            }
+        }));
+
+        true
+    }
+}
+
 impl Component for UploadComponent {
     type Message = UploadMsg;
-    type Properties = ();
+    type Properties = UploadProps;
 
-    fn create(_props: Self::Properties, link: ComponentLink<Self>) -> Self {
-        let base_uri = yew::utils::window().origin();
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let base_uri = base_uri();
+
+        // fetch server limits
+        let clink = link.clone();
+        let base_uri_cloned = base_uri.clone();
+        spawn_local(async move {
+            if let Some(app_config) = get_app_config(&base_uri_cloned).await {
+                clink.send_message(UploadMsg::AppConfig(app_config));
+            }
+        });
 
         Self {
             link,
             base_uri,
+            app_config: None,
             selected_file: None,
-            upload_type: UploadType::File,
+            upload_type: props.upload_type,
+            record_source: RecordSource::Voice,
+            recording: None,
             textarea_ref: NodeRef::default(),
+            focus_textarea: props.upload_type == UploadType::Text,
             passphrase_ref: NodeRef::default(),
+            available_from_ref: NodeRef::default(),
+            max_downloads_ref: NodeRef::default(),
+            key_in_link_ref: NodeRef::default(),
+            api_key_ref: NodeRef::default(),
             passphrase_available: false,
+            passphrase_generated: false,
+            passphrase_bits: None,
             file_size: None,
             uploaded_size: None,
-            file_id: None,
+            rate_limited: None,
+            share_id: None,
+            link_key: None,
+            deletion_token: None,
+            deleted: false,
             upload_error: None,
+            error_reported: false,
         }
     }
 
     fn update(&mut self, msg: Self::Message) -> bool {
         match msg {
+            UploadMsg::AppConfig(app_config) => {
+                self.app_config = Some(app_config);
+
+                false
+            }
+            UploadMsg::ReportError => {
+                if let (Some(err), false) = (&self.upload_error, self.error_reported) {
+                    telemetry::report(&self.base_uri, err.kind());
+                    self.error_reported = true;
+                }
+
+                true
+            }
             UploadMsg::FileChanged(file) => {
                 let file_size = file.size() as usize;
-                self.file_id = None;
+                self.share_id = None;
+                self.link_key = None;
+                self.deletion_token = None;
+                self.deleted = false;
                 self.uploaded_size = None;
                 self.file_size = Some(file_size);
                 self.selected_file = Some(file);
                 self.passphrase_available = false;
+                self.passphrase_generated = false;
+                self.passphrase_bits = None;
                 if let Some(input) = self.passphrase_ref.cast::<HtmlInputElement>() {
                     input.set_value("");
                 }
                 true
             }
+            UploadMsg::GeneratePassphrase => {
+                let passphrase = match generate_passphrase() {
+                    Ok(passphrase) => passphrase,
+                    Err(err) => {
+                        log::error!("cannot generate passphrase: {:?}", err);
+                        show_fatal_error();
+                        return false;
+                    }
+                };
+                if let Some(input) = self.passphrase_ref.cast::<HtmlInputElement>() {
+                    input.set_value(&passphrase);
+                    self.passphrase_available = true;
+                    self.passphrase_generated = true;
+                    self.passphrase_bits = Some(generated_bits());
+                }
+                true
+            }
             UploadMsg::PassphraseInput => {
                 if let Some(input) = self.passphrase_ref.cast::<HtmlInputElement>() {
                     let v = input.value();
                     self.passphrase_available = !v.is_empty();
+                    self.passphrase_bits = if v.is_empty() {
+                        None
+                    } else {
+                        Some(estimate_bits(&v))
+                    };
                 }
                 true
             }
-            UploadMsg::ChangeUploadType => {
-                match self.upload_type {
-                    UploadType::File => {
-                        self.upload_type = UploadType::Text;
-                    }
-                    UploadType::Text => {
-                        self.upload_type = UploadType::File;
-                    }
+            UploadMsg::ChangeUploadType(upload_type) => {
+                // recording should be stopped explicitly
+                if self.recording.is_some() {
+                    return false;
+                }
+                self.upload_type = upload_type;
+                true
+            }
+            UploadMsg::RecordSourceChanged(record_source) => {
+                if self.recording.is_some() {
+                    return false;
                 }
+                self.record_source = record_source;
                 true
             }
             UploadMsg::FileUploadStart => {
                 self.upload_error = None;
-                self.file_id = None;
+                self.error_reported = false;
+                self.share_id = None;
+                self.link_key = None;
+                self.deletion_token = None;
+                self.deleted = false;
                 self.uploaded_size = None;
                 if !self.passphrase_available {
                     return false;
@@ -159,6 +971,24 @@ impl Component for UploadComponent {
                 } else {
                     return false;
                 };
+                self.file_size = Some(file.size() as usize);
+
+                // refuse files which server will not accept, before encrypting anything
+                if let Some(app_config) = &self.app_config {
+                    if file.size() as u64 > app_config.max_file_size {
+                        self.upload_error = Some(UploadError::TooLarge(app_config.max_file_size));
+                        return true;
+                    }
+
+                    let name = file.name();
+                    if let Some((_, ext)) = name.rsplit_once('.') {
+                        let ext = ext.to_lowercase();
+                        if app_config.forbidden_extensions.contains(&ext) {
+                            self.upload_error = Some(UploadError::ForbiddenType(ext));
+                            return true;
+                        }
+                    }
+                }
 
                 // get passphrase from input
                 let passphrase = if let Some(input) = self.passphrase_ref.cast::<HtmlInputElement>()
@@ -169,39 +999,6 @@ impl Component for UploadComponent {
                     return false;
                 };
 
-                // generate salt for hkdf expand()
-                let mut salt = [0u8; 32];
-                if let Err(err) = getrandom::getrandom(&mut salt) {
-                    log::error!("cannot get random salt value: {:?}", err);
-                    return false;
-                }
-
-                // generate key by hkdf
-                let h = Hkdf::<Sha256>::new(Some(&salt), passphrase.as_bytes());
-                let mut key_slice = [0u8; 32];
-                if let Err(err) = h.expand(&[], &mut key_slice[..]) {
-                    log::error!("cannot expand passphrase by hkdf: {:?}", err);
-                    return false;
-                }
-
-                // generate nonce for XChaCha20Poly1305
-                let mut stream_nonce = [0u8; 19];
-                if let Err(err) = getrandom::getrandom(&mut stream_nonce) {
-                    log::error!("cannot get random nonce value: {:?}", err);
-                    return false;
-                }
-                let mut filename_nonce = [0u8; 24];
-                if let Err(err) = getrandom::getrandom(&mut filename_nonce) {
-                    log::error!("cannot get random nonce value: {:?}", err);
-                    return false;
-                }
-
-                let key = Key::from_slice(&key_slice);
-                let cipher = XChaCha20Poly1305::new(key);
-
-                let stream_nonce = GenericArray::from_slice(stream_nonce.as_ref());
-                let filename_nonce = GenericArray::from_slice(filename_nonce.as_ref());
-
                 let sys_stream = {
                     if let Ok(s) = file.stream().dyn_into() {
                         s
@@ -211,203 +1008,24 @@ impl Component for UploadComponent {
                     }
                 };
 
-                // encrypt filename
-                let filename = file.name();
-                let encrypted_filename = {
-                    match cipher.encrypt(
-                        filename_nonce,
-                        filename.bytes().collect::<Vec<u8>>().as_ref(),
-                    ) {
-                        Ok(encrypted) => encrypted,
-                        Err(err) => {
-                            log::error!("failed to encrypt filename: {:?}", err);
-                            return true;
-                        }
-                    }
-                };
-
                 // read file
                 let stream = wasm_streams::ReadableStream::from_raw(sys_stream).into_stream();
 
                 // stream which read files and transforms that `Uint8Array`s to `Result<Vec<u8>>`.
-                let fut = stream
+                let stream = stream
                     .and_then(|b| async move { b.dyn_into::<Uint8Array>() })
                     .map_err(UploadError::JsValue)
                     .map_ok(|arr| arr.to_vec());
 
-                let mut fut = Box::pin(fut);
-
-                let stream_nonce = *stream_nonce;
-                let filename_nonce = *filename_nonce;
-                let clink = self.link.clone();
-                let base_uri = self.base_uri.clone();
-
-                // core logic of streaming upload / encryption
-                let encrypt_routine = async move {
-                    // use stream encryptor
-                    let mut encryptor =
-                        aead::stream::EncryptorBE32::from_aead(cipher, &stream_nonce);
-                    // send prepare request
-                    let client = reqwest::Client::new();
-                    let form = Form::new()
-                        .part("nonce", Part::stream(stream_nonce.to_vec()))
-                        .part("filename_nonce", Part::stream(filename_nonce.to_vec()))
-                        .part("salt", Part::stream(salt.to_vec()))
-                        .part("filename", Part::stream(encrypted_filename));
-                    let file_id = match client
-                        .post(join_uri(&base_uri, "/api/prepare_upload"))
-                        .multipart(form)
-                        .send()
-                        .await
-                    {
-                        Ok(resp) => {
-                            if resp.status() != 200 {
-                                return Err(UploadError::Remote(format!(
-                                    "prepare_upload status != 200, but {}",
-                                    resp.status()
-                                )));
-                            }
-                            let b = {
-                                match resp.bytes().await {
-                                    Ok(b) => b.to_vec(),
-                                    Err(_) => {
-                                        return Err(UploadError::Remote(
-                                            "failed to read resp body".into(),
-                                        ));
-                                    }
-                                }
-                            };
-                            match serde_json::from_slice::<Value>(b.as_ref()) {
-                                Ok(v) => {
-                                    if let Some(v) = v.get("id").and_then(Value::as_i64) {
-                                        v
-                                    } else {
-                                        return Err(UploadError::Remote(
-                                            "failed to deserialize body".into(),
-                                        ));
-                                    }
-                                }
-                                Err(_) => {
-                                    return Err(UploadError::Remote(
-                                        "failed to deserialize body".into(),
-                                    ));
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("remote error: {:?}", e);
-                            return Err(UploadError::Remote(
-                                "failed to request prepare_upload".into(),
-                            ));
-                        }
-                    };
-
-                    let id = file_id.to_be_bytes();
-                    let mut seq: i64 = 1;
-                    let mut buffer = Vec::<u8>::with_capacity(BLOCK_SIZE);
-                    // start encryption and upload
-                    while let Some(v) = fut.try_next().await? {
-                        let mut v: &[u8] = v.as_ref();
-                        // divide inputs into fixed block size
-                        while buffer.len() + v.len() >= BLOCK_SIZE {
-                            let split_idx = BLOCK_SIZE - buffer.len();
-                            buffer.extend(&v[..split_idx]);
-                            // upload chunk to server
-                            // this will block next encryption...
-                            // maybe there is more good way to handle this
-                            let chunk = encryptor
-                                .encrypt_next(buffer.as_ref())
-                                .map_err(UploadError::Aead)?;
-                            let chunk_len = chunk.len();
-                            let id = id.to_vec();
-                            let seq_b = seq.to_be_bytes().to_vec();
-                            // upload encrypted chunk to server
-                            let form = Form::new()
-                                .part("id", Part::bytes(id))
-                                .part("seq", Part::bytes(seq_b))
-                                .part("is_last", Part::bytes(vec![0]))
-                                .part("content", Part::stream(chunk));
-                            match client
-                                .post(join_uri(&base_uri, "/api/upload"))
-                                .multipart(form)
-                                .send()
-                                .await
-                            {
-                                Ok(resp) => {
-                                    if resp.status() != 200 {
-                                        return Err(UploadError::Remote(format!(
-                                            "upload status != 200, but {}",
-                                            resp.status()
-                                        )));
-                                    }
-                                }
-                                Err(_) => {
-                                    return Err(UploadError::Remote(
-                                        "failed to upload chunk".into(),
-                                    ));
-                                }
-                            }
-                            buffer.clear();
-                            v = &v[split_idx..];
-                            seq += 1;
-
-                            clink.send_message(UploadMsg::Progress(ProgressInfo::UploadBytes(
-                                chunk_len,
-                            )));
-                        }
-                        buffer.extend(v);
-                    }
-                    // upload last chunk
-                    let chunk = encryptor
-                        .encrypt_last(buffer.as_ref())
-                        .map_err(UploadError::Aead)?;
-                    let id_b = id.to_vec();
-                    let seq = seq.to_be_bytes().to_vec();
-                    let chunk_len = chunk.len();
-                    let form = Form::new()
-                        .part("id", Part::bytes(id_b))
-                        .part("seq", Part::bytes(seq))
-                        .part("is_last", Part::bytes(vec![1]))
-                        .part("content", Part::stream(chunk));
-                    match client
-                        .post(join_uri(&base_uri, "/api/upload"))
-                        .multipart(form)
-                        .send()
-                        .await
-                    {
-                        Ok(resp) => {
-                            if resp.status() != 200 {
-                                return Err(UploadError::Remote(format!(
-                                    "upload status != 200, but {}",
-                                    resp.status()
-                                )));
-                            }
-                        }
-                        Err(_) => {
-                            return Err(UploadError::Remote("failed to upload chunk".into()));
-                        }
-                    }
-                    clink.send_message(UploadMsg::Progress(ProgressInfo::UploadBytes(chunk_len)));
-                    clink.send_message(UploadMsg::UploadComplete(file_id));
-
-                    Ok(())
-                };
-
-                let clink = self.link.clone();
-                // spawn entire routine in promise
-                // TODO: research Web Workers and try to gain more performance
-                spawn_local(encrypt_routine.map(move |r: Result<(), UploadError>| {
-                    if let Err(e) = r {
-                        log::error!("encryption error: {:?}", e);
-                        clink.send_message(UploadMsg::UploadError(e));
-                    }
-                }));
-
-                true
+                self.start_stream_upload(&passphrase, file.name(), Some(file.size() as u64), stream)
             }
             UploadMsg::TextUploadStart => {
                 self.upload_error = None;
-                self.file_id = None;
+                self.error_reported = false;
+                self.share_id = None;
+                self.link_key = None;
+                self.deletion_token = None;
+                self.deleted = false;
                 self.uploaded_size = None;
                 if !self.passphrase_available {
                     return false;
@@ -433,9 +1051,19 @@ impl Component for UploadComponent {
                 };
 
                 // generate salt for hkdf expand()
-                let mut salt = [0u8; 32];
+                let mut salt = [0u8; SALT_LEN];
                 if let Err(err) = getrandom::getrandom(&mut salt) {
                     log::error!("cannot get random salt value: {:?}", err);
+                    show_fatal_error();
+                    return false;
+                }
+
+                // identifies this upload, so that retried prepare request does not create another
+                // file on server
+                let mut idempotency_key = [0u8; 16];
+                if let Err(err) = getrandom::getrandom(&mut idempotency_key) {
+                    log::error!("cannot get random idempotency key: {:?}", err);
+                    show_fatal_error();
                     return false;
                 }
 
@@ -451,9 +1079,10 @@ impl Component for UploadComponent {
                 let cipher = XChaCha20Poly1305::new(key);
 
                 // generate nonce for XChaCha20Poly1305
-                let mut nonce = [0u8; 24];
+                let mut nonce = [0u8; NONCE_LEN];
                 if let Err(err) = getrandom::getrandom(&mut nonce) {
                     log::error!("cannot get random nonce value: {:?}", err);
+                    show_fatal_error();
                     return false;
                 }
                 let nonce = XNonce::from_slice(&nonce);
@@ -470,87 +1099,49 @@ impl Component for UploadComponent {
                 let base_uri = self.base_uri.clone();
                 let nonce = *nonce;
                 let clink = self.link.clone();
+                let available_from = self.available_from();
+                let max_downloads = self.max_downloads();
+                let api_key = self.api_key();
                 let encrypt_fn = async move {
                     let client = reqwest::Client::new();
-                    let form = Form::new()
-                        .part("is_text", Part::bytes(vec![1]))
-                        .part("nonce", Part::stream(nonce.to_vec()))
-                        .part("salt", Part::stream(salt.to_vec()));
-                    let file_id = match client
-                        .post(join_uri(&base_uri, "/api/prepare_upload"))
-                        .multipart(form)
-                        .send()
-                        .await
-                    {
-                        Ok(resp) => {
-                            if resp.status() != 200 {
-                                return Err(UploadError::Remote(format!(
-                                    "prepare_upload status != 200, but {}",
-                                    resp.status()
-                                )));
-                            }
-                            let b = {
-                                match resp.bytes().await {
-                                    Ok(b) => b.to_vec(),
-                                    Err(_) => {
-                                        return Err(UploadError::Remote(
-                                            "failed to read resp body".into(),
-                                        ));
-                                    }
-                                }
-                            };
-                            match serde_json::from_slice::<Value>(b.as_ref()) {
-                                Ok(v) => {
-                                    if let Some(v) = v.get("id").and_then(Value::as_i64) {
-                                        v
-                                    } else {
-                                        return Err(UploadError::Remote(
-                                            "failed to deserialize body".into(),
-                                        ));
-                                    }
-                                }
-                                Err(_) => {
-                                    return Err(UploadError::Remote(
-                                        "failed to deserialize body".into(),
-                                    ));
-                                }
-                            }
+                    // text is always sent in one chunk, so file is prepared and uploaded at once
+                    let digest = Sha256::digest(&encrypted).to_vec();
+                    let request = || {
+                        let mut form = Form::new()
+                            .part(field::IS_TEXT, Part::bytes(vec![1]))
+                            .part(field::NONCE, Part::stream(nonce.to_vec()))
+                            .part(field::SALT, Part::stream(salt.to_vec()))
+                            .part(field::FORMAT_VERSION, Part::bytes(vec![FORMAT_VERSION]))
+                            .part(
+                                field::IDEMPOTENCY_KEY,
+                                Part::bytes(idempotency_key.to_vec()),
+                            );
+                        if let Some(t) = available_from {
+                            form = form
+                                .part(field::AVAILABLE_FROM, Part::bytes(t.to_be_bytes().to_vec()));
                         }
-                        Err(e) => {
-                            log::error!("remote error: {:?}", e);
-                            return Err(UploadError::Remote(
-                                "failed to request prepare_upload".into(),
-                            ));
+                        if let Some(n) = max_downloads {
+                            form = form
+                                .part(field::MAX_DOWNLOADS, Part::bytes(n.to_be_bytes().to_vec()));
                         }
+                        let form = form
+                            .part(field::CONTENT, Part::stream(encrypted.clone()))
+                            .part(field::DIGEST, Part::bytes(digest.clone()));
+                        upload_small_request(&client, &base_uri, api_key.as_deref(), form)
                     };
-
-                    let id = file_id.to_be_bytes();
-                    let seq = 1_i64.to_be_bytes().to_vec();
-                    let form = Form::new()
-                        .part("id", Part::bytes(id.to_vec()))
-                        .part("seq", Part::bytes(seq))
-                        .part("is_last", Part::bytes(vec![1]))
-                        .part("content", Part::stream(encrypted));
-                    match client
-                        .post(join_uri(&base_uri, "/api/upload"))
-                        .multipart(form)
-                        .send()
-                        .await
-                    {
-                        Ok(resp) => {
-                            if resp.status() != 200 {
-                                return Err(UploadError::Remote(format!(
-                                    "upload status != 200, but {}",
-                                    resp.status()
-                                )));
+                    let (_, share_id, deletion_token, _) =
+                        match send_idempotent(&clink, request).await {
+                            Ok(resp) => prepared_file("upload_small", resp).await?,
+                            Err(e) => {
+                                log::error!("remote error: {:?}", e);
+                                return Err(UploadError::Remote(
+                                    "failed to request upload_small".into(),
+                                ));
                             }
-                        }
-                        Err(_) => {
-                            return Err(UploadError::Remote("failed to upload chunk".into()));
-                        }
-                    };
+                        };
+                    verify_upload(&client, &base_uri, &share_id, &digest).await?;
 
-                    clink.send_message(UploadMsg::UploadComplete(file_id));
+                    clink.send_message(UploadMsg::UploadComplete(share_id, deletion_token));
 
                     Ok(())
                 };
@@ -564,15 +1155,165 @@ impl Component for UploadComponent {
 
                 true
             }
+            UploadMsg::RecordStart => {
+                self.upload_error = None;
+                self.error_reported = false;
+                self.share_id = None;
+                self.link_key = None;
+                self.deletion_token = None;
+                self.deleted = false;
+                self.uploaded_size = None;
+                if !self.passphrase_available || self.recording.is_some() {
+                    return false;
+                }
+
+                let media_devices = match yew::utils::window().navigator().media_devices() {
+                    Ok(media_devices) => media_devices,
+                    Err(e) => {
+                        self.upload_error = Some(UploadError::Capture(e));
+                        return true;
+                    }
+                };
+                // ask user for permission to capture
+                let promise = match self.record_source {
+                    RecordSource::Voice => {
+                        let mut constraints = MediaStreamConstraints::new();
+                        constraints.audio(&JsValue::TRUE);
+                        media_devices.get_user_media_with_constraints(&constraints)
+                    }
+                    RecordSource::Screen => media_devices.get_display_media(),
+                };
+                let promise = match promise {
+                    Ok(promise) => promise,
+                    Err(e) => {
+                        self.upload_error = Some(UploadError::Capture(e));
+                        return true;
+                    }
+                };
+
+                let clink = self.link.clone();
+                spawn_local(async move {
+                    match JsFuture::from(promise)
+                        .await
+                        .and_then(|s| s.dyn_into::<MediaStream>())
+                    {
+                        Ok(media_stream) => {
+                            clink.send_message(UploadMsg::RecordStream(media_stream))
+                        }
+                        Err(e) => {
+                            clink.send_message(UploadMsg::UploadError(UploadError::Capture(e)))
+                        }
+                    }
+                });
+
+                true
+            }
+            UploadMsg::RecordStream(media_stream) => {
+                let stop_tracks = |media_stream: &MediaStream| {
+                    for track in media_stream.get_tracks().iter() {
+                        if let Ok(track) = track.dyn_into::<MediaStreamTrack>() {
+                            track.stop();
+                        }
+                    }
+                };
+
+                // get passphrase from input
+                let passphrase = if let Some(input) = self.passphrase_ref.cast::<HtmlInputElement>()
+                {
+                    input.value()
+                } else {
+                    log::error!("cannot get passphrase string from input");
+                    stop_tracks(&media_stream);
+                    return false;
+                };
+
+                let recorder = match MediaRecorder::new_with_media_stream(&media_stream) {
+                    Ok(recorder) => recorder,
+                    Err(e) => {
+                        stop_tracks(&media_stream);
+                        self.upload_error = Some(UploadError::Capture(e));
+                        return true;
+                    }
+                };
+
+                // recorded blobs are passed to encryptor through channel, which is closed when
+                // recorder stops
+                let (sender, receiver) = mpsc::unbounded::<Blob>();
+                let data_sender = sender.clone();
+                let ondataavailable = Closure::wrap(Box::new(move |e: BlobEvent| {
+                    if let Some(blob) = e.data() {
+                        if blob.size() > 0_f64 {
+                            let _ = data_sender.unbounded_send(blob);
+                        }
+                    }
+                })
+                    as Box<dyn FnMut(BlobEvent)>);
+                let onstop = Closure::wrap(Box::new(move |_: Event| {
+                    sender.close_channel();
+                }) as Box<dyn FnMut(Event)>);
+                recorder.set_ondataavailable(Some(ondataavailable.as_ref().unchecked_ref()));
+                recorder.set_onstop(Some(onstop.as_ref().unchecked_ref()));
+
+                if let Err(e) = recorder.start_with_time_slice(RECORD_TIMESLICE_MS) {
+                    recorder.set_ondataavailable(None);
+                    recorder.set_onstop(None);
+                    stop_tracks(&media_stream);
+                    self.upload_error = Some(UploadError::Capture(e));
+                    return true;
+                }
+                let mut recording = Recording {
+                    media_stream,
+                    recorder,
+                    stopped: false,
+                    _ondataavailable: ondataavailable,
+                    _onstop: onstop,
+                };
+
+                // mime type is decided by browser when recording starts
+                let mime_type = recording.recorder.mime_type();
+                let extension = if mime_type.contains("mp4") {
+                    "mp4"
+                } else if mime_type.contains("ogg") {
+                    "ogg"
+                } else {
+                    "webm"
+                };
+                let filename = format!("recording-{}.{}", js_sys::Date::now() as u64, extension);
+
+                let stream = receiver.then(|blob| async move {
+                    let buf = JsFuture::from(blob.array_buffer())
+                        .await
+                        .map_err(UploadError::JsValue)?;
+                    Ok::<_, UploadError>(Uint8Array::new(&buf).to_vec())
+                });
+
+                // total size is unknown until recording ends
+                self.file_size = None;
+                self.uploaded_size = Some(0);
+                if !self.start_stream_upload(&passphrase, filename, None, stream) {
+                    recording.stop();
+                    return true;
+                }
+                self.recording = Some(recording);
+
+                true
+            }
+            UploadMsg::RecordStop => {
+                if let Some(recording) = &mut self.recording {
+                    if !recording.stopped {
+                        recording.stop();
+                    }
+                }
+
+                true
+            }
             UploadMsg::Progress(info) => {
                 match info {
                     ProgressInfo::UploadBytes(b) => {
                         let before = self.uploaded_size.unwrap_or(0);
-                        let file_size = self.file_size.unwrap_or(0);
-                        let after = if before + b > file_size {
-                            file_size
-                        } else {
-                            before + b
+                        let after = match self.file_size {
+                            Some(file_size) if before + b > file_size => file_size,
+                            _ => before + b,
                         };
                         self.uploaded_size = Some(after);
                     }
@@ -580,44 +1321,149 @@ impl Component for UploadComponent {
 
                 true
             }
+            UploadMsg::RateLimited(retry_after) => {
+                self.rate_limited = retry_after;
+
+                true
+            }
             UploadMsg::UploadError(err) => {
+                self.rate_limited = None;
                 self.upload_error = Some(err);
+                self.error_reported = false;
+                if let Some(mut recording) = self.recording.take() {
+                    recording.stop();
+                }
 
                 true
             }
-            UploadMsg::UploadComplete(file_id) => {
-                self.file_id = Some(file_id);
+            UploadMsg::UploadComplete(share_id, deletion_token) => {
+                self.share_id = Some(share_id);
+                self.link_key = self.key_in_link();
+                self.deletion_token = deletion_token;
+                self.recording = None;
+
+                true
+            }
+            UploadMsg::DeleteFile => {
+                let (share_id, token) = match (&self.share_id, &self.deletion_token) {
+                    (Some(share_id), Some(token)) => (share_id.clone(), token.clone()),
+                    _ => return false,
+                };
+
+                let base_uri = self.base_uri.clone();
+                let clink = self.link.clone();
+                spawn_local(async move {
+                    let client = reqwest::Client::new();
+                    let result = match client
+                        .delete(join_uri(&base_uri, "/api/file"))
+                        .query(&[("id", share_id)])
+                        .header("authorization", format!("Bearer {}", token))
+                        .send()
+                        .await
+                    {
+                        Ok(resp) => {
+                            if resp.status() != 200 {
+                                Err(remote_error("delete", resp).await)
+                            } else {
+                                Ok(())
+                            }
+                        }
+                        Err(_) => Err(UploadError::Remote("failed to request delete".into())),
+                    };
+                    clink.send_message(UploadMsg::DeleteComplete(result));
+                });
+
+                false
+            }
+            UploadMsg::DeleteComplete(result) => {
+                match result {
+                    Ok(()) => {
+                        self.deleted = true;
+                        self.deletion_token = None;
+                    }
+                    Err(e) => {
+                        self.upload_error = Some(e);
+                    }
+                }
 
                 true
             }
         }
     }
 
-    fn change(&mut self, _props: Self::Properties) -> bool {
-        false
+    fn change(&mut self, props: Self::Properties) -> bool {
+        // recording should be stopped explicitly
+        if props.upload_type == self.upload_type || self.recording.is_some() {
+            return false;
+        }
+        self.upload_type = props.upload_type;
+        self.focus_textarea = props.upload_type == UploadType::Text;
+        true
+    }
+
+    fn rendered(&mut self, _first_render: bool) {
+        set_title(self.title().as_deref());
+        if self.focus_textarea {
+            self.focus_textarea = false;
+            if let Some(textarea) = self.textarea_ref.cast::<HtmlTextAreaElement>() {
+                let _ = textarea.focus();
+            }
+        }
     }
 
     fn view(&self) -> Html {
-        let upload_type = self.upload_type.clone();
+        let upload_type = self.upload_type;
+        let is_recording = self.recording.as_ref().map_or(false, |r| !r.stopped);
         let upload_onclick = self.link.callback(move |_| match upload_type {
             UploadType::File => UploadMsg::FileUploadStart,
             UploadType::Text => UploadMsg::TextUploadStart,
+            UploadType::Recording if is_recording => UploadMsg::RecordStop,
+            UploadType::Recording => UploadMsg::RecordStart,
         });
-        let filetype_change_onclick = self.link.callback(|_| UploadMsg::ChangeUploadType);
+        let other_upload_types = match self.upload_type {
+            UploadType::File => [UploadType::Text, UploadType::Recording],
+            UploadType::Text => [UploadType::File, UploadType::Recording],
+            UploadType::Recording => [UploadType::File, UploadType::Text],
+        };
         let passphrase_oninput = self.link.callback(|_| UploadMsg::PassphraseInput);
+        let generate_onclick = self.link.callback(|_| UploadMsg::GeneratePassphrase);
         let passphrase_hidden = match self.upload_type {
             UploadType::File => self.selected_file.is_none(),
-            UploadType::Text => false,
+            UploadType::Text | UploadType::Recording => false,
         };
+        let api_key_required = self
+            .app_config
+            .as_ref()
+            .map_or(false, |c| c.api_key_required);
+        let mut generated_notice_class = vec!["flex", "justify-center", "mt-2"];
+        if !self.passphrase_generated || passphrase_hidden {
+            generated_notice_class.push("hidden");
+        }
+        let mut strength_class = vec!["flex", "justify-center", "mt-2"];
+        if self.passphrase_bits.is_none() || passphrase_hidden {
+            strength_class.push("hidden");
+        }
+        let strength_text = self.passphrase_bits.map_or(String::new(), |bits| {
+            format!(
+                "Offline guessing at 10 billion/s would take {}",
+                crack_time(bits)
+            )
+        });
         let upload_button_disabled = match self.upload_type {
             UploadType::File => !self.passphrase_available || self.selected_file.is_none(),
             UploadType::Text => !self.passphrase_available,
+            // recording started before can be stopped anytime
+            UploadType::Recording => !self.passphrase_available && !is_recording,
+        };
+        let upload_button_text = match self.upload_type {
+            UploadType::Recording if is_recording => "STOP",
+            UploadType::Recording => "RECORD",
+            _ => "UPLOAD",
         };
 
         let mut button_class = vec![
             "border-solid",
-            "bg-gray-700",
-            "text-gray-300",
+            "hako-button",
             "px-5",
             "py-3",
             "my-5",
@@ -626,8 +1472,7 @@ impl Component for UploadComponent {
         if upload_button_disabled {
             button_class.push("cursor-not-allowed");
         } else {
-            button_class.push("hover:bg-gray-400");
-            button_class.push("hover:text-gray-700");
+            button_class.push("hako-button-enabled");
             button_class.push("cursor-pointer");
         }
 
@@ -635,27 +1480,62 @@ impl Component for UploadComponent {
         let mut progress_class = vec!["flex", "relative", "pt-1", "justify-center"];
         if self.uploaded_size.is_none() {
             upload_byte_class.push("hidden");
+        }
+        // progress cannot be told without total size
+        if self.uploaded_size.is_none() || self.file_size.is_none() {
             progress_class.push("hidden");
         }
         let uploaded = self.uploaded_size.unwrap_or(0);
         let file_size = self.file_size.unwrap_or(0);
+        let upload_byte_text = match self.file_size {
+            Some(file_size) => format!("{} / {}", uploaded, file_size),
+            None => uploaded.to_string(),
+        };
         let progress_percent_width = if file_size == 0 {
             0
         } else {
             ((uploaded as f64 / file_size as f64) * (100_f64)) as usize
         };
 
+        let mut rate_limited_class = vec!["flex", "justify-center"];
+        if self.rate_limited.is_none() {
+            rate_limited_class.push("hidden");
+        }
+        let rate_limited_text = match self.rate_limited {
+            Some(secs) => format!("rate limited, retrying in {} s", secs),
+            None => "".into(),
+        };
+
         let mut file_uri_class = vec!["flex", "justify-center", "mb-4"];
-        if self.file_id.is_none() || self.upload_error.is_some() {
+        let mut file_uri = join_uri(&self.base_uri, self.share_id.as_deref().unwrap_or_default());
+        if let Some(key) = &self.link_key {
+            file_uri = format!(
+                "{}#{}",
+                file_uri,
+                String::from(js_sys::encode_uri_component(key))
+            );
+        }
+        if self.share_id.is_none() || self.upload_error.is_some() {
             file_uri_class.push("hidden");
         }
-        let file_uri_component = html! {
-            <div class=classes!(file_uri_class)>
-                <span class=classes!("mr-2")>{ "Your file: " }</span>
-                <a class=classes!("text-blue-400") target="_blank" href={join_uri(&self.base_uri, &self.file_id.unwrap_or(0).to_string())}>
-                    { join_uri(&self.base_uri, &self.file_id.unwrap_or(0).to_string()) }
-                </a>
-            </div>
+        let file_uri_component = if self.deleted {
+            html! {
+                <div class=classes!(file_uri_class)>
+                    <span>{ "Your file is deleted." }</span>
+                </div>
+            }
+        } else {
+            html! {
+                <div class=classes!(file_uri_class)>
+                    <span class=classes!("mr-2")>{ "Your file: " }</span>
+                    <a class=classes!("hako-share-link") target="_blank" href={file_uri.clone()}>
+                        { file_uri }
+                    </a>
+                    <pre class=classes!("hako-link", "cursor-pointer", "ml-3") hidden={self.deletion_token.is_none()} onclick={self.link.callback(|_| UploadMsg::DeleteFile)}>
+                        { "delete" }
+                    </pre>
+                </div>
+            }
         };
 
         let mut upload_error_class = vec!["flex", "justify-center", "mb-4"];
@@ -667,12 +1547,34 @@ impl Component for UploadComponent {
                 UploadError::JsValue(_) => "File read error".into(),
                 UploadError::Aead(_) => "Encryption error".into(),
                 UploadError::Remote(msg) => format!("Server error: {}", msg).into(),
+                UploadError::TooLarge(max_file_size) => format!(
+                    "File too large for this server: limit is {}",
+                    format_size(*max_file_size)
+                )
+                .into(),
+                UploadError::ForbiddenType(ext) => {
+                    format!("Files of type .{} are not allowed on this server", ext).into()
+                }
+                UploadError::TextQuotaExceeded(text_uploads_per_day) => format!(
+                    "Daily limit of {} text uploads reached",
+                    text_uploads_per_day
+                )
+                .into(),
+                UploadError::Capture(_) => "Cannot capture media".into(),
             },
             None => "".into(),
         };
+        let reportable = self
+            .app_config
+            .as_ref()
+            .map_or(false, |c| c.client_error_reports);
         let upload_error_component = html! {
             <div class=classes!(upload_error_class)>
-                <span class=classes!("text-red-300")>{ upload_error_text }</span>
+                <span class=classes!("hako-error")>{ upload_error_text }</span>
+                <pre class=classes!("hako-link", "cursor-pointer", "ml-3") hidden={!reportable || self.error_reported} onclick={self.link.callback(|_| UploadMsg::ReportError)}>
+                    { "report" }
+                </pre>
+                <span class=classes!("hako-muted", "ml-3") hidden={!self.error_reported}>{ "reported, thanks" }</span>
             </div>
         };
 
@@ -682,52 +1584,87 @@ impl Component for UploadComponent {
                     match self.upload_type {
                         UploadType::File => file_input(self),
                         UploadType::Text => text_input(self),
+                        UploadType::Recording => recording_input(self),
                     }
 
                 }
                 <div class=classes!("flex", "justify-center", "mt-2")>
-                    <pre class=classes!("text-gray-800")>
+                    <pre class=classes!("hako-muted")>
                         { "...or " }
                     </pre>
-                    <pre class=classes!("text-blue-700", "hover:text-blue-400", "cursor-pointer") onclick={filetype_change_onclick}>
-                        {
-                            match self.upload_type {
-                                UploadType::File => "Text",
-                                UploadType::Text => "File",
-                            }
-                        }
+                    <pre class=classes!("hako-link", "cursor-pointer") onclick={self.link.callback(move |_| UploadMsg::ChangeUploadType(other_upload_types[0]))}>
+                        { other_upload_types[0].name() }
+                    </pre>
+                    <pre class=classes!("hako-muted")>
+                        { " / " }
+                    </pre>
+                    <pre class=classes!("hako-link", "cursor-pointer") onclick={self.link.callback(move |_| UploadMsg::ChangeUploadType(other_upload_types[1]))}>
+                        { other_upload_types[1].name() }
                     </pre>
                 </div>
                 <div class=classes!("flex", "justify-center", "mt-5")>
-                    <p class=classes!("text-gray-300", "mb-3")>{ self.selected_file.as_ref().map_or("".into(), |f: &web_sys::File| f.name()) }</p>
+                    <p class=classes!("hako-text", "mb-3")>{ self.selected_file.as_ref().map_or("".into(), |f: &web_sys::File| f.name()) }</p>
                 </div>
                 <div class=classes!("flex", "justify-center")>
                     <input
                         id="passphrase"
-                        type="password"
+                        type={ if self.passphrase_generated { "text" } else { "password" } }
                         ref={self.passphrase_ref.clone()}
-                        class=classes!("px-4", "py-2", "rounded-lg", "border", "border-gray-300", "focus:outline-none", "focus:ring-2", "focus:ring-gray-200", "text-center")
+                        class=classes!("hako-input", "px-4", "py-2", "rounded-lg", "border", "focus:outline-none", "focus:ring-2", "text-center")
                         placeholder={ "Passphrase" }
                         hidden={passphrase_hidden}
                         oninput={passphrase_oninput}
                     />
+                    <pre class=classes!("hako-link", "cursor-pointer", "ml-3", "self-center") hidden={passphrase_hidden} onclick={generate_onclick}>
+                        { "Generate" }
+                    </pre>
+                </div>
+                <div class=classes!("flex", "justify-center", "mt-3") hidden={passphrase_hidden}>
+                    <label class=classes!("hako-muted", "mr-3", "self-center") for="available_from">{ "Publish at (optional)" }</label>
+                    <input id="available_from" type="datetime-local" ref={self.available_from_ref.clone()} class=classes!("hako-input", "px-2", "py-1", "rounded-lg") />
+                </div>
+                <div class=classes!("flex", "justify-center", "mt-3") hidden={passphrase_hidden}>
+                    <label class=classes!("hako-muted", "mr-3", "self-center") for="max_downloads">{ "Burn after downloads (optional)" }</label>
+                    <input id="max_downloads" type="number" min="1" ref={self.max_downloads_ref.clone()} class=classes!("hako-input", "px-2", "py-1", "rounded-lg", "w-24") />
+                </div>
+                <div class=classes!("flex", "justify-center", "mt-3") hidden={passphrase_hidden}>
+                    <input id="key_in_link" type="checkbox" ref={self.key_in_link_ref.clone()} class=classes!("mr-2", "self-center") />
+                    <label class=classes!("hako-muted", "self-center") for="key_in_link">{ "Put passphrase in link" }</label>
+                </div>
+                <div class=classes!("flex", "justify-center", "mt-3") hidden={passphrase_hidden || !api_key_required}>
+                    <label class=classes!("hako-muted", "mr-3", "self-center") for="api_key">{ "API key" }</label>
+                    <input id="api_key" type="password" ref={self.api_key_ref.clone()} class=classes!("hako-input", "px-2", "py-1", "rounded-lg") />
+                </div>
+                <div class=classes!(strength_class)>
+                    <span class=classes!("hako-muted")>{ strength_text }</span>
+                </div>
+                <div class=classes!(generated_notice_class)>
+                    <span class=classes!("hako-muted")>
+                        { "Send this passphrase to the recipient through another channel than the link" }
+                    </span>
                 </div>
                 <div class=classes!(progress_class)>
-                    <div class=classes!("overflow-hidden", "h-2", "mb-4", "text-xs", "flex", "rounded", "bg-blue-200", "w-1/2", "mt-4")>
-                        <div style={format!("width:{}%", progress_percent_width)} class=classes!("shadow-none", "flex", "flex-col", "text-center", "whitespace-nowrap", "text-white", "justify-center", "bg-blue-400")></div>
+                    <div role="progressbar" aria-label="Upload progress" aria-valuemin="0" aria-valuemax="100" aria-valuenow={progress_percent_width.to_string()} class=classes!("overflow-hidden", "h-2", "mb-4", "text-xs", "flex", "rounded", "hako-progress", "w-1/2", "mt-4")>
+                        <div style={format!("width:{}%", progress_percent_width)} class=classes!("shadow-none", "flex", "flex-col", "text-center", "whitespace-nowrap", "text-white", "justify-center", "hako-progress-bar")></div>
                     </div>
                 </div>
+                <div class=classes!("sr-only") aria-live="polite">
+                    { progress_milestone(progress_percent_width, "uploaded") }
+                </div>
                 <div class=classes!(upload_byte_class)>
-                    <span class=classes!("text-gray-800")>
-                        { uploaded } { " / " } { file_size }
+                    <span class=classes!("hako-muted")>
+                        { upload_byte_text }
                     </span>
                 </div>
+                <div class=classes!(rate_limited_class)>
+                    <span class=classes!("hako-muted")>{ rate_limited_text }</span>
+                </div>
                 <div class=classes!("flex", "justify-center")>
                     <button
                         disabled={upload_button_disabled}
                         onclick={upload_onclick}
                         class=classes!(button_class)>
-                        { "UPLOAD" }
+                        { upload_button_text }
                     </button>
                 </div>
                 { upload_error_component }
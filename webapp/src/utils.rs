@@ -1,6 +1,30 @@
 pub const BLOCK_SIZE: usize = 1024 * 1024 * 10;
 // pub const BLOCK_SIZE: usize = 1024 * 128;
 pub const BLOCK_OVERHEAD: usize = 16;
+// version of encryption format produced by this client. bump this on incompatible changes, so
+// that stale clients can tell users to update instead of failing to decrypt.
+pub const FORMAT_VERSION: u8 = 1;
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// smallest upload chunk, sent on slow or metered connections
+const MIN_CHUNK_SIZE: usize = 256 * 1024;
+// seconds of transfer which one upload chunk is aimed to take on connection of known speed
+const CHUNK_SECONDS: f64 = 4.0;
+
+// path prefix which server is deployed under, e.g. `/hako`, or empty if at root. server tells it
+// in meta tag of index page
+pub fn base_path() -> String {
+    yew::utils::document()
+        .query_selector("meta[name=hako-base-path]")
+        .ok()
+        .flatten()
+        .and_then(|meta| meta.get_attribute("content"))
+        .unwrap_or_default()
+}
+
+// uri which api paths and share ids are joined to
+pub fn base_uri() -> String {
+    format!("{}{}", yew::utils::window().origin(), base_path())
+}
 
 pub fn join_uri<P, Q>(base_uri: P, rest: Q) -> String
 where
@@ -22,17 +46,128 @@ where
     }
 }
 
-pub mod base64 {
-    use serde::Deserialize;
-    use serde::Deserializer;
+// human readable size, e.g. "1.5 GiB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// human readable rough duration, e.g. "~8 minutes"
+pub fn format_duration(secs: f64) -> String {
+    let minutes = (secs / 60.0).round() as u64;
+    if secs < 60.0 {
+        format!("~{} seconds", secs.round() as u64)
+    } else if minutes < 120 {
+        format!("~{} minutes", minutes)
+    } else {
+        format!("~{} hours", (minutes + 30) / 60)
+    }
+}
+
+// title of browser tab, which shows progress while tab is in background
+pub fn set_title(state: Option<&str>) {
+    let title = match state {
+        Some(state) => format!("{} — Hako", state),
+        None => "Hako".to_owned(),
+    };
+    let document = yew::utils::document();
+    if document.title() != title {
+        document.set_title(&title);
+    }
+}
 
-    // pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
-    //     let base64 = base64::encode(v);
-    //     String::serialize(&base64, s)
-    // }
+// progress as screen readers are told it, e.g. "50% uploaded". text only changes every quarter, as
+// live region announcing every percent would talk over everything else
+pub fn progress_milestone(percent: usize, done: &str) -> String {
+    match percent.min(100) / 25 * 25 {
+        0 => String::new(),
+        milestone => format!("{}% {}", milestone, done),
+    }
+}
+
+// size of upload chunks for this connection, up to `max`. read from Network Information API where
+// browser has it, so that slow or metered connections send small chunks which show progress often
+// and cost little to retry. browsers without it send chunks of `max`.
+pub fn chunk_size_hint(max: usize) -> usize {
+    let navigator = yew::utils::window().navigator();
+    let connection = match js_sys::Reflect::get(&navigator, &"connection".into()) {
+        Ok(connection) if connection.is_object() => connection,
+        _ => return max,
+    };
+    let get = |name: &str| js_sys::Reflect::get(&connection, &name.into()).ok();
+
+    let save_data = get("saveData").and_then(|v| v.as_bool()).unwrap_or(false);
+    let effective_type = get("effectiveType").and_then(|v| v.as_string());
+    // estimated bandwidth in megabits per second
+    let downlink = get("downlink")
+        .and_then(|v| v.as_f64())
+        .filter(|downlink| *downlink > 0.0);
+    let size = match (effective_type.as_deref(), downlink) {
+        _ if save_data => MIN_CHUNK_SIZE,
+        (Some("slow-2g"), _) | (Some("2g"), _) => MIN_CHUNK_SIZE,
+        (_, Some(downlink)) => (downlink * 1_000_000.0 / 8.0 * CHUNK_SECONDS) as usize,
+        _ => max,
+    };
+    size.clamp(MIN_CHUNK_SIZE.min(max), max)
+}
+
+// resolves after given seconds, for waiting out rate limit of server
+pub async fn sleep(secs: u64) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let ms = (secs * 1000).min(i32::MAX as u64) as i32;
+        if let Err(err) =
+            yew::utils::window().set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms)
+        {
+            log::error!("failed to set timeout: {:?}", err);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+// body of error response of server. fields particular to `code` are in `detail`
+#[derive(serde::Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    detail: serde_json::Value,
+}
+
+impl ErrorBody {
+    pub fn detail<T: serde::de::DeserializeOwned>(&self) -> Option<T> {
+        serde_json::from_value(self.detail.clone()).ok()
+    }
+
+    // as shown to users, e.g. "file does not exist (not_found)"
+    pub fn describe(&self) -> String {
+        format!("{} ({})", self.message, self.code)
+    }
+}
+
+// error which server told in body of response. proxies in front of it may send other bodies
+pub async fn error_body(resp: reqwest::Response) -> Option<ErrorBody> {
+    let body = resp.bytes().await.ok()?;
+    serde_json::from_slice(&body).ok()
+}
 
-    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
-        let base64 = String::deserialize(d)?;
-        base64::decode(base64.as_bytes()).map_err(serde::de::Error::custom)
+// server tags every response with request id. show it in error messages so that users can
+// attach it to their bug reports.
+pub fn request_ref(resp: &reqwest::Response) -> String {
+    match resp
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(id) => format!(", ref #{}", id),
+        None => "".into(),
     }
 }